@@ -5,12 +5,13 @@ use std::str::FromStr;
 
 // use crate::output_level::OutputLevel;
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum OutputFormat {
     Debug,
     PrettyDebug,
     Json,
+    #[default]
     PrettyJson,
     Yaml,
     Stdout,
@@ -34,12 +35,6 @@ impl OutputFormat {
     }
 }
 
-impl Default for OutputFormat {
-    fn default() -> Self {
-        Self::PrettyJson
-    }
-}
-
 bitflags::bitflags! {
     #[derive(Clone, Debug)]
     pub struct OutputLevel: u16 {
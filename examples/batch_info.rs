@@ -0,0 +1,28 @@
+use futures::StreamExt;
+use rusty_ytdl::*;
+
+#[tokio::main]
+async fn main() {
+    let urls = [
+        "https://youtube.com/watch?v=Rbgw_rduQpM",
+        "https://youtube.com/watch?v=-h6PCkfTBcc",
+        "https://youtube.com/watch?v=2SUwOgmvzK4",
+        "https://youtube.com/watch?v=9Ueulv6BugQ",
+        "https://youtube.com/watch?v=R4hDcd9fzRk",
+        "https://youtube.com/watch?v=W5Sq71VTJ9Q",
+    ];
+
+    let instant = std::time::Instant::now();
+
+    let batch = VideoBatch::new(urls).unwrap().concurrency(3);
+
+    let mut infos = batch.basic_info();
+    while let Some((url, info)) = infos.next().await {
+        match info {
+            Ok(info) => println!("{url}: {}", info.video_details.title),
+            Err(err) => println!("{url}: {err}"),
+        }
+    }
+
+    println!("Time taken: {:?}", instant.elapsed());
+}
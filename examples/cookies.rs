@@ -13,4 +13,17 @@ async fn main() {
     };
 
     let _video = Video::new_with_options(video_url, video_options).unwrap();
+
+    // Cookies exported from a browser in Netscape format, rotated cookies are written back to the file
+    if let Ok(cookie_jar) = CookieJar::load("cookies.txt") {
+        let video_options = VideoOptions {
+            request_options: RequestOptions {
+                cookie_jar: Some(cookie_jar.autosave(true)),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let _video = Video::new_with_options(video_url, video_options).unwrap();
+    }
 }
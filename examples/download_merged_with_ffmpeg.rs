@@ -0,0 +1,27 @@
+use rusty_ytdl::{FFmpegMergeOptions, Video, VideoOptions, VideoQuality};
+
+#[tokio::main]
+async fn main() {
+    let url = "FZ8BxMU3BYc";
+
+    let video_options = VideoOptions {
+        quality: VideoQuality::Highest,
+        ..Default::default()
+    };
+
+    let video = Video::new_with_options(url, video_options).unwrap();
+
+    // Best video and best audio in one Matroska file, without re-encoding
+    video
+        .download_merged_with_ffmpeg(
+            "video.mkv",
+            FFmpegMergeOptions {
+                metadata: true,
+                chapters: true,
+                thumbnail: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+}
@@ -17,11 +17,16 @@ async fn main() {
             format: Some("mp3".to_string()),
             audio_filter: Some("aresample=48000,asetrate=48000*0.8".to_string()),
             video_filter: Some("eq=brightness=150:saturation=2".to_string()),
+            ..Default::default()
         }))
         .await
         .unwrap();
 
     while let Some(chunk) = stream.chunk().await.unwrap() {
         println!("{:#?}", chunk);
+
+        if let Some(progress) = stream.ffmpeg_progress().await {
+            println!("{:?} at {:?}x", progress.time, progress.speed);
+        }
     }
 }
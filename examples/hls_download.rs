@@ -0,0 +1,19 @@
+use rusty_ytdl::{stream::VariantSelection, HlsDownloader, HlsOptions};
+
+#[tokio::main]
+async fn main() {
+    let url = "https://test-streams.mux.dev/x36xhzz/x36xhzz.m3u8";
+
+    let downloader = HlsDownloader::new_with_options(
+        url,
+        HlsOptions {
+            variant: VariantSelection::MaxHeight(720),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let path = std::path::Path::new(r"stream.ts");
+
+    downloader.download(path).await.unwrap();
+}
@@ -0,0 +1,33 @@
+use rusty_ytdl::*;
+
+#[tokio::main]
+async fn main() {
+    let video_url = "https://www.youtube.com/watch?v=FZ8BxMU3BYc";
+
+    // The token is stored in token.json, later runs reuse it without logging in again
+    let auth = OAuth::new(FileTokenStore::new("token.json"));
+
+    if !auth.is_authorized().await.unwrap() {
+        auth.login(|code| {
+            println!(
+                "Open {} and enter the code {}",
+                code.verification_url, code.user_code
+            )
+        })
+        .await
+        .unwrap();
+    }
+
+    let video_options = VideoOptions {
+        request_options: RequestOptions {
+            auth: Some(auth),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let video = Video::new_with_options(video_url, video_options).unwrap();
+
+    let video_info = video.get_info().await.unwrap();
+    println!("{:#?}", video_info.video_details.title);
+}
@@ -0,0 +1,561 @@
+use async_trait::async_trait;
+use http::Extensions;
+use rand::Rng;
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION},
+    Request, Response,
+};
+use reqwest_middleware::{Middleware, Next};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Debug, Formatter, Result as fmtResult},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+use crate::{
+    constants::{
+        OAUTH_CLIENT_ID, OAUTH_CLIENT_SECRET, OAUTH_DEVICE_CODE_URL, OAUTH_EXPIRY_MARGIN,
+        OAUTH_SCOPE, OAUTH_TOKEN_URL,
+    },
+    structs::VideoError,
+};
+
+/// OAuth application and endpoints of the device-code flow. Default is the YouTube TV app
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OAuthClient {
+    pub client_id: String,
+    pub client_secret: String,
+    pub scope: String,
+    pub device_code_url: String,
+    pub token_url: String,
+}
+
+impl Default for OAuthClient {
+    fn default() -> Self {
+        Self {
+            client_id: OAUTH_CLIENT_ID.to_string(),
+            client_secret: OAUTH_CLIENT_SECRET.to_string(),
+            scope: OAUTH_SCOPE.to_string(),
+            device_code_url: OAUTH_DEVICE_CODE_URL.to_string(),
+            token_url: OAUTH_TOKEN_URL.to_string(),
+        }
+    }
+}
+
+/// Code the user enters at [`DeviceCode::verification_url`] to authorize this device
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct DeviceCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_url: String,
+    /// Seconds until the code expires
+    pub expires_in: u64,
+    /// Seconds to wait between token polls
+    #[serde(default = "default_poll_interval")]
+    pub interval: u64,
+}
+
+fn default_poll_interval() -> u64 {
+    5
+}
+
+/// Access and refresh token of an authorized device
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub token_type: String,
+    /// Unix timestamp the access token expires at
+    pub expires_at: u64,
+}
+
+impl OAuthToken {
+    /// Whether the access token expires within `margin`
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_at <= unix_now().saturating_add(margin.as_secs())
+    }
+}
+
+impl Debug for OAuthToken {
+    // Tokens are credentials, keep them out of logs
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmtResult {
+        f.debug_struct("OAuthToken")
+            .field("token_type", &self.token_type)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Persistence of the [`OAuthToken`] between runs
+#[async_trait]
+pub trait TokenStore: Send + Sync + Debug {
+    /// Stored token, `None` if the device was never authorized
+    async fn load(&self) -> Result<Option<OAuthToken>, VideoError>;
+
+    /// Store a new or refreshed token
+    async fn save(&self, token: &OAuthToken) -> Result<(), VideoError>;
+}
+
+/// Keep the token in memory, the device has to be authorized again on every run
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    token: RwLock<Option<OAuthToken>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn load(&self) -> Result<Option<OAuthToken>, VideoError> {
+        Ok(self.token.read().unwrap().clone())
+    }
+
+    async fn save(&self, token: &OAuthToken) -> Result<(), VideoError> {
+        *self.token.write().unwrap() = Some(token.clone());
+        Ok(())
+    }
+}
+
+/// Keep the token in a JSON file
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl TokenStore for FileTokenStore {
+    async fn load(&self) -> Result<Option<OAuthToken>, VideoError> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(VideoError::OAuthError(err.to_string())),
+        };
+
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|err| VideoError::OAuthError(err.to_string()))
+    }
+
+    /// Write the token readable by the owner only, replacing the file in one step
+    async fn save(&self, token: &OAuthToken) -> Result<(), VideoError> {
+        use tokio::io::AsyncWriteExt;
+
+        let map_err = |err: std::io::Error| VideoError::OAuthError(err.to_string());
+        let content =
+            serde_json::to_string(token).map_err(|err| VideoError::OAuthError(err.to_string()))?;
+
+        let mut temp_path = self.path.as_os_str().to_owned();
+        temp_path.push(format!(".{:016x}.tmp", rand::thread_rng().gen::<u64>()));
+
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let result = async {
+            let mut file = options.open(&temp_path).await?;
+            file.write_all(content.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp_path, &self.path).await
+        }
+        .await;
+
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+        }
+        result.map_err(map_err)
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: Option<String>,
+    refresh_token: Option<String>,
+    token_type: Option<String>,
+    expires_in: Option<u64>,
+    error: Option<String>,
+}
+
+/// OAuth device-code login. Innertube requests of clients built with [`crate::RequestOptions::auth`] carry its access token,
+/// refreshed when it expires.
+///
+/// Clones share the token.
+///
+/// # Example
+/// ```ignore
+///     let auth = OAuth::new(FileTokenStore::new("token.json"));
+///
+///     if !auth.is_authorized().await? {
+///         auth.login(|code| {
+///             println!("Open {} and enter {}", code.verification_url, code.user_code)
+///         })
+///         .await?;
+///     }
+///
+///     let video_options = VideoOptions {
+///         request_options: RequestOptions {
+///             auth: Some(auth),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     };
+/// ```
+#[derive(Clone, Debug)]
+pub struct OAuth {
+    client: OAuthClient,
+    store: Arc<dyn TokenStore>,
+    http: reqwest::Client,
+    token: Arc<tokio::sync::Mutex<Option<OAuthToken>>>,
+}
+
+impl OAuth {
+    /// Create [`OAuth`] with the YouTube TV app, persisting the token in `store`
+    pub fn new(store: impl TokenStore + 'static) -> Self {
+        Self {
+            client: OAuthClient::default(),
+            store: Arc::new(store),
+            http: reqwest::Client::new(),
+            token: Arc::new(tokio::sync::Mutex::new(None)),
+        }
+    }
+
+    /// OAuth application and endpoints. Default is [`OAuthClient::default`]
+    pub fn client(mut self, client: OAuthClient) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// [`reqwest::Client`] the token endpoints are called with, for example to go through a proxy
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Whether a token is available, in memory or in the [`TokenStore`]
+    pub async fn is_authorized(&self) -> Result<bool, VideoError> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = self.store.load().await?;
+        }
+
+        Ok(token.is_some())
+    }
+
+    /// Request a code for the user to authorize this device with
+    pub async fn request_device_code(&self) -> Result<DeviceCode, VideoError> {
+        let device_id: String = (0..32)
+            .map(|_| format!("{:x}", rand::thread_rng().gen_range(0..16)))
+            .collect();
+
+        let response = self
+            .http
+            .post(&self.client.device_code_url)
+            .json(&serde_json::json!({
+                "client_id": self.client.client_id,
+                "scope": self.client.scope,
+                "device_id": device_id,
+                "device_model": "ytlr::",
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        response
+            .json::<DeviceCode>()
+            .await
+            .map_err(|err| VideoError::OAuthError(err.to_string()))
+    }
+
+    /// Poll the token endpoint until the user authorized `device_code`, then store the token
+    pub async fn poll_token(&self, device_code: &DeviceCode) -> Result<OAuthToken, VideoError> {
+        let deadline = unix_now().saturating_add(device_code.expires_in);
+        let mut interval = Duration::from_secs(device_code.interval);
+
+        loop {
+            if unix_now() > deadline {
+                return Err(VideoError::OAuthError("device code expired".to_string()));
+            }
+
+            tokio::time::sleep(interval).await;
+
+            let response = self
+                .token_request(serde_json::json!({
+                    "client_id": self.client.client_id,
+                    "client_secret": self.client.client_secret,
+                    "code": device_code.device_code,
+                    "grant_type": "http://oauth.net/grant_type/device/1.0",
+                }))
+                .await?;
+
+            match response.error.as_deref() {
+                Some("authorization_pending") => continue,
+                Some("slow_down") => interval += Duration::from_secs(5),
+                Some(err) => return Err(VideoError::OAuthError(err.to_string())),
+                None => {
+                    let token = Self::new_token(response, None)?;
+                    self.store.save(&token).await?;
+                    *self.token.lock().await = Some(token.clone());
+
+                    return Ok(token);
+                }
+            }
+        }
+    }
+
+    /// Run the whole device-code flow. `prompt` shows the code to the user
+    pub async fn login(&self, prompt: impl FnOnce(&DeviceCode)) -> Result<OAuthToken, VideoError> {
+        let device_code = self.request_device_code().await?;
+        prompt(&device_code);
+
+        self.poll_token(&device_code).await
+    }
+
+    /// Exchange the refresh token for a new access token
+    pub async fn refresh(&self) -> Result<OAuthToken, VideoError> {
+        let mut token = self.token.lock().await;
+        self.refresh_locked(&mut token, true).await
+    }
+
+    /// Valid access token, refreshed if it is about to expire
+    pub async fn access_token(&self) -> Result<String, VideoError> {
+        let mut token = self.token.lock().await;
+
+        let expiring = match token.as_ref() {
+            Some(token) => token.expires_within(OAUTH_EXPIRY_MARGIN),
+            None => true,
+        };
+        if !expiring {
+            return Ok(token.as_ref().unwrap().access_token.clone());
+        }
+
+        Ok(self.refresh_locked(&mut token, false).await?.access_token)
+    }
+
+    async fn refresh_locked(
+        &self,
+        token: &mut Option<OAuthToken>,
+        force: bool,
+    ) -> Result<OAuthToken, VideoError> {
+        if token.is_none() {
+            *token = self.store.load().await?;
+        }
+
+        let Some(current) = token.as_ref() else {
+            return Err(VideoError::NotAuthenticated);
+        };
+        // The stored token may be fresher than ours, for example refreshed by another process
+        if !force && !current.expires_within(OAUTH_EXPIRY_MARGIN) {
+            return Ok(current.clone());
+        }
+
+        let response = self
+            .token_request(serde_json::json!({
+                "client_id": self.client.client_id,
+                "client_secret": self.client.client_secret,
+                "refresh_token": current.refresh_token,
+                "grant_type": "refresh_token",
+            }))
+            .await?;
+
+        if let Some(err) = response.error {
+            return Err(VideoError::OAuthError(err));
+        }
+
+        let refreshed = Self::new_token(response, Some(current.refresh_token.clone()))?;
+        self.store.save(&refreshed).await?;
+        *token = Some(refreshed.clone());
+
+        Ok(refreshed)
+    }
+
+    async fn token_request(&self, body: serde_json::Value) -> Result<TokenResponse, VideoError> {
+        // Pending authorizations are reported with an error status, the body tells what happened
+        self.http
+            .post(&self.client.token_url)
+            .json(&body)
+            .send()
+            .await?
+            .json::<TokenResponse>()
+            .await
+            .map_err(|err| VideoError::OAuthError(err.to_string()))
+    }
+
+    fn new_token(
+        response: TokenResponse,
+        refresh_token: Option<String>,
+    ) -> Result<OAuthToken, VideoError> {
+        let missing =
+            |field: &str| VideoError::OAuthError(format!("token response without {field}"));
+
+        Ok(OAuthToken {
+            access_token: response
+                .access_token
+                .ok_or_else(|| missing("access_token"))?,
+            // Refresh responses don't repeat the refresh token
+            refresh_token: response
+                .refresh_token
+                .or(refresh_token)
+                .ok_or_else(|| missing("refresh_token"))?,
+            token_type: response.token_type.unwrap_or_else(|| "Bearer".to_string()),
+            expires_at: unix_now().saturating_add(response.expires_in.unwrap_or(3600)),
+        })
+    }
+}
+
+/// Adds the access token of an [`OAuth`] to innertube requests
+pub struct AuthMiddleware {
+    auth: OAuth,
+}
+
+impl AuthMiddleware {
+    pub fn new(auth: OAuth) -> Self {
+        Self { auth }
+    }
+}
+
+/// Innertube API of YouTube, the only requests the token is sent with
+fn is_innertube(url: &Url) -> bool {
+    let youtube_host = url
+        .host_str()
+        .is_some_and(|host| host == "youtube.com" || host.ends_with(".youtube.com"));
+
+    youtube_host && url.path().starts_with("/youtubei/")
+}
+
+#[async_trait]
+impl Middleware for AuthMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if is_innertube(req.url()) {
+            let access_token = self
+                .auth
+                .access_token()
+                .await
+                .map_err(reqwest_middleware::Error::middleware)?;
+
+            let header = HeaderValue::from_str(&format!("Bearer {access_token}"))
+                .map_err(reqwest_middleware::Error::middleware)?;
+            req.headers_mut().insert(AUTHORIZATION, header);
+        }
+
+        next.run(req, extensions).await
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{self, Response};
+
+    /// Serve `bodies` as JSON one per connection, then `404 Not Found`
+    async fn serve(bodies: Vec<&'static str>) -> String {
+        let mut bodies = bodies.into_iter();
+        test_server::serve(move |_| match bodies.next() {
+            Some(body) => Response::new("200 OK\r\nContent-Type: application/json", body),
+            None => Response::new("404 Not Found", ""),
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_device_code_flow() {
+        let url = serve(vec![
+            r#"{"device_code":"device","user_code":"ABC-DEF","verification_url":"https://www.google.com/device","expires_in":60,"interval":0}"#,
+            r#"{"error":"authorization_pending"}"#,
+            r#"{"access_token":"expired","refresh_token":"refresh","token_type":"Bearer","expires_in":0}"#,
+            r#"{"access_token":"fresh","token_type":"Bearer","expires_in":3600}"#,
+        ])
+        .await;
+
+        let auth = OAuth::new(MemoryTokenStore::new()).client(OAuthClient {
+            device_code_url: format!("{url}/device/code"),
+            token_url: format!("{url}/token"),
+            ..Default::default()
+        });
+        assert!(!auth.is_authorized().await.unwrap());
+
+        let mut user_code = String::new();
+        let token = auth
+            .login(|code| user_code = code.user_code.clone())
+            .await
+            .unwrap();
+        assert_eq!(user_code, "ABC-DEF");
+        assert_eq!(token.access_token, "expired");
+
+        // Expired access token is refreshed, the refresh token is kept
+        assert_eq!(auth.access_token().await.unwrap(), "fresh");
+        let stored = auth.store.load().await.unwrap().unwrap();
+        assert_eq!(stored.refresh_token, "refresh");
+        assert_eq!(auth.access_token().await.unwrap(), "fresh");
+    }
+
+    #[tokio::test]
+    async fn test_file_token_store() {
+        let path =
+            std::env::temp_dir().join(format!("rusty_ytdl_token_{}.json", std::process::id()));
+        let store = FileTokenStore::new(&path);
+        assert!(store.load().await.unwrap().is_none());
+
+        let token = OAuthToken {
+            access_token: "access".to_string(),
+            refresh_token: "refresh".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_at: 0,
+        };
+        store.save(&token).await.unwrap();
+        store.save(&token).await.unwrap();
+
+        assert_eq!(
+            store.load().await.unwrap().unwrap().refresh_token,
+            "refresh"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_token_is_sent_to_innertube_only() {
+        assert!(is_innertube(
+            &Url::parse("https://www.youtube.com/youtubei/v1/player").unwrap()
+        ));
+        assert!(is_innertube(
+            &Url::parse("https://youtube.com/youtubei/v1/search").unwrap()
+        ));
+        assert!(!is_innertube(
+            &Url::parse("https://www.youtube.com/watch?v=FZ8BxMU3BYc").unwrap()
+        ));
+        assert!(!is_innertube(
+            &Url::parse("https://evil.com/youtubei/v1/player").unwrap()
+        ));
+    }
+}
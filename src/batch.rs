@@ -0,0 +1,117 @@
+use futures::{stream, Stream, StreamExt};
+use reqwest_middleware::ClientWithMiddleware;
+use std::borrow::Cow;
+
+use crate::{
+    constants::DEFAULT_BATCH_CONCURRENCY,
+    structs::{VideoError, VideoInfo, VideoOptions},
+    utils::build_client,
+    Video,
+};
+
+/// Fetch information of many videos through one shared client with bounded concurrency.
+///
+/// The player script is fetched once per player version and shared by every video of the batch.
+///
+/// # Example
+///
+/// ```ignore
+///     use futures::StreamExt;
+///
+///     let batch = VideoBatch::new(["FZ8BxMU3BYc", "Rbgw_rduQpM"])?.concurrency(4);
+///
+///     let mut infos = batch.basic_info();
+///     while let Some((id, info)) = infos.next().await {
+///         match info {
+///             Ok(info) => println!("{id}: {}", info.video_details.title),
+///             Err(err) => println!("{id}: {err}"),
+///         }
+///     }
+/// ```
+#[derive(Clone, Debug, derive_more::Display)]
+#[display("VideoBatch({} videos, concurrency: {concurrency})", urls_or_ids.len())]
+pub struct VideoBatch<'opts> {
+    urls_or_ids: Vec<String>,
+    options: Cow<'opts, VideoOptions>,
+    concurrency: usize,
+    client: ClientWithMiddleware,
+}
+
+impl VideoBatch<'static> {
+    /// Create [`VideoBatch`] with default [`VideoOptions`]
+    pub fn new<I, S>(urls_or_ids: I) -> Result<Self, VideoError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self::new_with_options(urls_or_ids, VideoOptions::default())
+    }
+}
+
+impl<'opts> VideoBatch<'opts> {
+    /// Create [`VideoBatch`] with custom [`VideoOptions`], shared by every video of the batch
+    pub fn new_with_options<I, S>(
+        urls_or_ids: I,
+        options: impl Into<Cow<'opts, VideoOptions>>,
+    ) -> Result<Self, VideoError>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let options = options.into();
+        let client = build_client(&options.request_options)?;
+
+        Ok(Self {
+            urls_or_ids: urls_or_ids.into_iter().map(Into::into).collect(),
+            options,
+            concurrency: DEFAULT_BATCH_CONCURRENCY,
+            client,
+        })
+    }
+
+    /// Maximum number of videos fetched at the same time. Default is [`crate::constants::DEFAULT_BATCH_CONCURRENCY`]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Basic information of every video, see [`Video::get_basic_info`].
+    ///
+    /// Items are yielded in completion order, paired with the URL or id they were requested with
+    pub fn basic_info(&self) -> impl Stream<Item = (String, Result<VideoInfo, VideoError>)> + '_ {
+        self.fetch(false)
+    }
+
+    /// Full information of every video, see [`Video::get_info`].
+    ///
+    /// Items are yielded in completion order, paired with the URL or id they were requested with
+    pub fn info(&self) -> impl Stream<Item = (String, Result<VideoInfo, VideoError>)> + '_ {
+        self.fetch(true)
+    }
+
+    fn fetch(
+        &self,
+        full_info: bool,
+    ) -> impl Stream<Item = (String, Result<VideoInfo, VideoError>)> + '_ {
+        stream::iter(self.urls_or_ids.iter())
+            .map(move |url_or_id| async move {
+                let video = match Video::new_with_client(
+                    url_or_id.as_str(),
+                    self.options.as_ref(),
+                    self.client.clone(),
+                ) {
+                    Ok(video) => video,
+                    Err(err) => return (url_or_id.clone(), Err(err)),
+                };
+
+                let info = if full_info {
+                    video.get_info().await
+                } else {
+                    video.get_basic_info().await
+                };
+
+                (url_or_id.clone(), info)
+            })
+            .buffer_unordered(self.concurrency)
+    }
+}
@@ -0,0 +1,61 @@
+use std::path::Path;
+
+use crate::blocking::stream::LiveStream;
+use crate::hls::{HlsDownloader as AsyncHlsDownloader, HlsOptions};
+use crate::{block_async, VideoError};
+
+pub struct HlsDownloader(AsyncHlsDownloader);
+
+impl HlsDownloader {
+    /// Create [`HlsDownloader`] for the playlist at `url` with default [`HlsOptions`]
+    pub fn new(url: impl Into<String>) -> Result<Self, VideoError> {
+        Ok(Self(AsyncHlsDownloader::new(url)?))
+    }
+
+    /// Create [`HlsDownloader`] for the playlist at `url` with custom [`HlsOptions`]
+    pub fn new_with_options(
+        url: impl Into<String>,
+        options: HlsOptions,
+    ) -> Result<Self, VideoError> {
+        Ok(Self(AsyncHlsDownloader::new_with_options(url, options)?))
+    }
+
+    /// Stream of the playlist, the selected variant for master playlists
+    pub fn stream(&self) -> Result<LiveStream, VideoError> {
+        Ok(self.0.stream()?.into())
+    }
+
+    /// Stream of the separate audio rendition of the selected variant
+    pub fn audio_stream(&self) -> Result<Option<LiveStream>, VideoError> {
+        Ok(block_async!(self.0.audio_stream())?.map(LiveStream::from))
+    }
+
+    /// Download the playlist directly to the file
+    pub fn download<P: AsRef<Path>>(&self, path: P) -> Result<(), VideoError> {
+        Ok(block_async!(self.0.download(path))?)
+    }
+
+    /// Download the playlist as one file per segment with a local playlist
+    pub fn download_segments<P: AsRef<Path>>(&self, dir: P) -> Result<(), VideoError> {
+        Ok(block_async!(self.0.download_segments(dir))?)
+    }
+
+    /// Download the separate audio rendition directly to the file, `false` if the variant has none
+    pub fn download_audio<P: AsRef<Path>>(&self, path: P) -> Result<bool, VideoError> {
+        Ok(block_async!(self.0.download_audio(path))?)
+    }
+}
+
+impl std::ops::Deref for HlsDownloader {
+    type Target = AsyncHlsDownloader;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::ops::DerefMut for HlsDownloader {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
@@ -1,5 +1,6 @@
-use std::path::Path;
 use std::borrow::Cow;
+use std::path::Path;
+use std::time::Duration;
 
 use crate::block_async;
 #[cfg(feature = "live")]
@@ -15,7 +16,7 @@ use super::stream::LiveStreamOptions;
 use super::stream::{NonLiveStreamOptions, Stream};
 
 #[cfg(feature = "ffmpeg")]
-use crate::structs::FFmpegArgs;
+use crate::structs::{FFmpegArgs, FFmpegMergeOptions};
 
 #[derive(Clone, Debug, derive_more::Display, PartialEq, Eq)]
 /// If a video was created with a reference to options, it is tied to their lifetime `'opts`.
@@ -66,13 +67,46 @@ impl<'opts> Video<'opts> {
     ///     }
     /// ```
     pub fn stream(&self) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
+        let info = block_async!(self.0.get_info())?;
+
+        self.stream_from_info(info)
+    }
+
+    /// Poll the video every `poll_interval` until an upcoming live stream or premiere starts, then turn it into a [`Stream`] like [`Video::stream`].
+    /// Returns right away if the video is not upcoming. Transient errors while polling are ignored, see [`VideoError::is_retryable`]
+    pub fn wait_for_live(
+        &self,
+        poll_interval: Duration,
+    ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
+        loop {
+            match block_async!(self.0.get_info()) {
+                Ok(info) if !info.video_details.is_upcoming => return self.stream_from_info(info),
+                Ok(_) => {}
+                Err(err) if err.is_retryable() => {}
+                Err(err) => return Err(err),
+            }
+
+            std::thread::sleep(poll_interval);
+        }
+    }
+
+    fn stream_from_info(
+        &self,
+        info: VideoInfo,
+    ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
         let client = self.0.get_client();
 
         let options = self.0.get_options();
 
-        let info = block_async!(self.0.get_info())?;
-        let format = choose_format(&info.formats, &options)
-            .map_err(|_op| VideoError::VideoSourceNotFound)?;
+        if info.video_details.is_upcoming {
+            return Err(VideoError::PremiereScheduled {
+                scheduled_start_time: info.video_details.scheduled_start_time,
+                reason: None,
+            });
+        }
+
+        let format =
+            choose_format(&info.formats, options).map_err(|_op| VideoError::VideoSourceNotFound)?;
 
         let link = format.url;
 
@@ -87,6 +121,7 @@ impl<'opts> Video<'opts> {
                 let stream = LiveStream::new(LiveStreamOptions {
                     client: Some(client.clone()),
                     stream_url: link,
+                    ..Default::default()
                 })?;
 
                 return Ok(Box::new(stream));
@@ -128,6 +163,7 @@ impl<'opts> Video<'opts> {
             dl_chunk_size,
             start,
             end,
+            refresher: Some(self.0.link_refresher(format.itag)),
             #[cfg(feature = "ffmpeg")]
             ffmpeg_args: None,
         })?;
@@ -148,6 +184,7 @@ impl<'opts> Video<'opts> {
     ///            format: Some("mp3".to_string()),
     ///            audio_filter: Some("aresample=48000,asetrate=48000*0.8".to_string()),
     ///            video_filter: Some("eq=brightness=150:saturation=2".to_string()),
+    ///            ..Default::default()
     ///        })).unwrap();
     ///
     ///     while let Some(chunk) = stream.chunk().unwrap() {
@@ -163,8 +200,8 @@ impl<'opts> Video<'opts> {
         let options = self.0.get_options();
 
         let info = block_async!(self.0.get_info())?;
-        let format = choose_format(&info.formats, &options)
-            .map_err(|_op| VideoError::VideoSourceNotFound)?;
+        let format =
+            choose_format(&info.formats, options).map_err(|_op| VideoError::VideoSourceNotFound)?;
 
         let link = format.url;
 
@@ -179,6 +216,7 @@ impl<'opts> Video<'opts> {
                 let stream = LiveStream::new(LiveStreamOptions {
                     client: Some(client.clone()),
                     stream_url: link,
+                    ..Default::default()
                 })?;
 
                 return Ok(Box::new(stream));
@@ -223,6 +261,7 @@ impl<'opts> Video<'opts> {
             dl_chunk_size,
             start,
             end,
+            refresher: Some(self.0.link_refresher(format.itag)),
             ffmpeg_args,
         })?;
 
@@ -246,6 +285,18 @@ impl<'opts> Video<'opts> {
             .download_with_ffmpeg(path, ffmpeg_args))?)
     }
 
+    #[cfg(feature = "ffmpeg")]
+    /// Download the best video only and audio only formats merged into one file with ffmpeg
+    pub fn download_merged_with_ffmpeg<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: FFmpegMergeOptions,
+    ) -> Result<(), VideoError> {
+        Ok(block_async!(self
+            .0
+            .download_merged_with_ffmpeg(path, options))?)
+    }
+
     /// Get video URL
     pub fn get_video_url(&self) -> String {
         self.0.get_video_url()
@@ -1,3 +1,5 @@
+#[cfg(feature = "live")]
+mod hls;
 mod info;
 
 #[cfg(feature = "search")]
@@ -23,4 +25,6 @@ macro_rules! block_async {
     };
 }
 
+#[cfg(feature = "live")]
+pub use hls::HlsDownloader;
 pub use info::Video;
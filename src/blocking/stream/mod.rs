@@ -1,5 +1,5 @@
 mod streams;
 
 #[cfg(feature = "live")]
-pub use streams::{LiveStream, LiveStreamOptions};
+pub use streams::{LiveGap, LiveStream, LiveStreamOptions, VariantSelection};
 pub use streams::{NonLiveStream, NonLiveStreamOptions, Stream};
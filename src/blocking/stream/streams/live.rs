@@ -1,7 +1,8 @@
 use bytes::Bytes;
+use std::path::Path;
 
 use crate::blocking::stream::Stream;
-use crate::stream::{LiveStream as AsyncLiveStream, LiveStreamOptions};
+use crate::stream::{LiveGap, LiveStream as AsyncLiveStream, LiveStreamOptions, MediaFormat};
 use crate::{block_async, VideoError};
 
 pub struct LiveStream(AsyncLiveStream);
@@ -10,6 +11,37 @@ impl LiveStream {
     pub fn new(options: LiveStreamOptions) -> Result<Self, VideoError> {
         Ok(Self(AsyncLiveStream::new(options)?))
     }
+
+    /// Media playlist currently played, a variant when the stream is a master playlist
+    pub fn media_url(&self) -> Result<String, VideoError> {
+        Ok(block_async!(self.0.media_url())?)
+    }
+
+    /// Format of the downloaded segments, `None` before the first chunk
+    pub fn format(&self) -> Option<MediaFormat> {
+        block_async!(self.0.format())
+    }
+
+    /// Sequence gaps detected so far, see [`AsyncLiveStream::gaps`]
+    pub fn gaps(&self) -> Vec<LiveGap> {
+        block_async!(self.0.gaps())
+    }
+
+    /// Record the stream as one file per segment with a local playlist, see [`AsyncLiveStream::download_segments`]
+    pub fn download_segments<P: AsRef<Path>>(&self, dir: P) -> Result<(), VideoError> {
+        Ok(block_async!(self.0.download_segments(dir))?)
+    }
+
+    /// Separate audio rendition (`EXT-X-MEDIA`) of the played variant, see [`AsyncLiveStream::audio_rendition`]
+    pub fn audio_rendition(&self) -> Result<Option<LiveStream>, VideoError> {
+        Ok(block_async!(self.0.audio_rendition())?.map(Self::from))
+    }
+}
+
+impl From<AsyncLiveStream> for LiveStream {
+    fn from(stream: AsyncLiveStream) -> Self {
+        Self(stream)
+    }
 }
 
 impl Stream for LiveStream {
@@ -1,8 +1,8 @@
 use bytes::Bytes;
 
-#[cfg(feature = "live")]
-pub use crate::stream::LiveStreamOptions;
 pub use crate::stream::NonLiveStreamOptions;
+#[cfg(feature = "live")]
+pub use crate::stream::{LiveGap, LiveStreamOptions, VariantSelection};
 
 use crate::VideoError;
 
@@ -26,4 +26,10 @@ pub trait Stream {
     fn content_length(&self) -> usize {
         0
     }
+
+    /// Last progress ffmpeg reported, `None` without [`FFmpegArgs`](crate::FFmpegArgs) or before the first report
+    #[cfg(feature = "ffmpeg")]
+    fn ffmpeg_progress(&self) -> Option<crate::FFmpegProgress> {
+        None
+    }
 }
@@ -21,6 +21,12 @@ impl Stream for NonLiveStream {
     fn content_length(&self) -> usize {
         self.0.content_length() as usize
     }
+
+    #[cfg(feature = "ffmpeg")]
+    fn ffmpeg_progress(&self) -> Option<crate::FFmpegProgress> {
+        use crate::stream::Stream;
+        block_async!(self.0.ffmpeg_progress())
+    }
 }
 
 impl std::ops::Deref for NonLiveStream {
@@ -2,7 +2,7 @@ use mime::Mime;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::header::{HeaderMap, USER_AGENT};
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, str::FromStr, time::Duration};
 
 use crate::structs::{MimeType, StaticFormat};
 
@@ -52,6 +52,72 @@ pub(crate) const DEFAULT_DL_CHUNK_SIZE: u64 = 10485760;
 /// Default max number of retries for a web reqwest.
 pub(crate) const DEFAULT_MAX_RETRIES: u32 = 3;
 
+/// Default backoff before the first retry of a web request.
+pub(crate) const DEFAULT_MIN_RETRY_INTERVAL: Duration = Duration::from_millis(1000);
+
+/// Default upper bound of the exponential backoff between retries.
+pub(crate) const DEFAULT_MAX_RETRY_INTERVAL: Duration = Duration::from_millis(30000);
+
+/// Default longest `Retry-After` a web request waits for before giving up.
+pub(crate) const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(300);
+
+/// Default time a rate limited proxy of a pool is skipped.
+pub(crate) const DEFAULT_PROXY_COOLDOWN: Duration = Duration::from_secs(60);
+
+/// Default number of addresses an address pool rotates requests over.
+pub(crate) const DEFAULT_POOL_ADDRESSES: usize = 16;
+
+/// Client id of the YouTube TV app, used for the OAuth device-code flow.
+pub(crate) const OAUTH_CLIENT_ID: &str =
+    "861556708454-d6dlm3lh05idd8npek18k6be8ba3oc68.apps.googleusercontent.com";
+
+/// Client secret of the YouTube TV app, public like every installed-app secret.
+pub(crate) const OAUTH_CLIENT_SECRET: &str = "SboVhoG9s0rNafixCSGGKXAT";
+
+pub(crate) const OAUTH_SCOPE: &str =
+    "http://gdata.youtube.com https://www.googleapis.com/auth/youtube";
+
+pub(crate) const OAUTH_DEVICE_CODE_URL: &str = "https://www.youtube.com/o/oauth2/device/code";
+
+pub(crate) const OAUTH_TOKEN_URL: &str = "https://www.youtube.com/o/oauth2/token";
+
+/// Access tokens expiring sooner than this are refreshed before the next request.
+pub(crate) const OAUTH_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Default segment duration of live adaptive formats without a target duration.
+pub(crate) const DEFAULT_LIVE_SEGMENT_DURATION: Duration = Duration::from_secs(5);
+
+/// Default time a live stream may go without new segments before it is considered stalled or ended.
+pub(crate) const DEFAULT_LIVE_STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default number of HLS segments downloaded concurrently.
+pub(crate) const DEFAULT_LIVE_PREFETCH: usize = 3;
+
+/// Default number of attempts to download a failed HLS segment again.
+pub(crate) const DEFAULT_LIVE_SEGMENT_RETRIES: u32 = 2;
+
+/// Lines of ffmpeg's log kept for the error when it fails.
+pub(crate) const FFMPEG_STDERR_LINES: usize = 20;
+
+/// Media links expiring sooner than this are refreshed before the next request.
+pub(crate) const LINK_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Default number of videos a batch fetches at the same time.
+pub(crate) const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+
+/// Default iteration budget of a single loop in the player JavaScript, real player functions stay far below it.
+pub(crate) const DEFAULT_JS_LOOP_ITERATION_LIMIT: u64 = 1_000_000;
+
+/// Default call depth budget of the player JavaScript.
+pub(crate) const DEFAULT_JS_RECURSION_LIMIT: usize = 512;
+
+/// Default interpreter stack size budget of the player JavaScript.
+pub(crate) const DEFAULT_JS_STACK_SIZE_LIMIT: usize = 10_240;
+
+/// Default wall-clock limit of an external JavaScript runtime batch.
+#[cfg(feature = "external-js")]
+pub(crate) const DEFAULT_EXTERNAL_JS_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub static INNERTUBE_CLIENT: Lazy<HashMap<&str, (&str, &str, &str)>> =
     // (clientVersion, clientName, json value)
     Lazy::new(|| {
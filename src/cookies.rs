@@ -0,0 +1,536 @@
+use reqwest::{cookie::CookieStore, header::HeaderValue};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+use crate::structs::VideoError;
+
+const NETSCAPE_HEADER: &str = "# Netscape HTTP Cookie File";
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct StoredCookie {
+    /// Lowercase host, without leading dot
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// Unix timestamp, `None` for session cookies
+    expires: Option<u64>,
+    name: String,
+    value: String,
+}
+
+impl StoredCookie {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    fn same_key(&self, other: &StoredCookie) -> bool {
+        self.domain == other.domain && self.path == other.path && self.name == other.name
+    }
+
+    fn matches(&self, url: &Url, now: u64) -> bool {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return false;
+        };
+
+        let domain_match = host == self.domain
+            || (self.include_subdomains && host.ends_with(&format!(".{}", self.domain)));
+
+        domain_match
+            && path_match(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+            && !self.is_expired(now)
+    }
+
+    /// One line of a Netscape `cookies.txt` file
+    fn parse_line(line: &str) -> Result<Option<Self>, String> {
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(line) => (line, true),
+            None if line.starts_with('#') || line.trim().is_empty() => return Ok(None),
+            None => (line, false),
+        };
+
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(format!(
+                "expected 7 tab separated fields, found {}",
+                fields.len()
+            ));
+        };
+
+        let expires = expires
+            .parse::<u64>()
+            .map_err(|_| format!("invalid expiry `{expires}`"))?;
+
+        Ok(Some(Self {
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE")
+                || domain.starts_with('.'),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            http_only,
+            expires: (expires != 0).then_some(expires),
+            name: name.to_string(),
+            value: value.to_string(),
+        }))
+    }
+
+    fn to_line(&self) -> String {
+        let bool_str = |value: bool| if value { "TRUE" } else { "FALSE" };
+
+        format!(
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if self.http_only { HTTP_ONLY_PREFIX } else { "" },
+            if self.include_subdomains { "." } else { "" },
+            self.domain,
+            bool_str(self.include_subdomains),
+            self.path,
+            bool_str(self.secure),
+            self.expires.unwrap_or(0),
+            self.name,
+            self.value
+        )
+    }
+}
+
+/// Cookie store scoped by domain and path, loadable from and savable to Netscape `cookies.txt` files.
+///
+/// Cookies set by responses replace the stored ones, so rotated `__Secure-*` cookies stay fresh.
+/// With [`CookieJar::autosave`] the file the jar was loaded from is rewritten whenever a response changes it.
+///
+/// Clones share the same cookies.
+///
+/// # Example
+/// ```ignore
+///     let video_options = VideoOptions {
+///         request_options: RequestOptions {
+///             cookie_jar: Some(CookieJar::load("cookies.txt")?.autosave(true)),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     };
+/// ```
+#[derive(Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<RwLock<Vec<StoredCookie>>>,
+    path: Option<PathBuf>,
+    autosave: bool,
+    saves: Arc<AutosaveState>,
+}
+
+/// Autosaves of a jar and its clones, changes made while a save runs are written by that save
+#[derive(Default)]
+struct AutosaveState {
+    running: AtomicBool,
+    pending: AtomicBool,
+}
+
+impl CookieJar {
+    /// Create an empty [`CookieJar`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a Netscape `cookies.txt` file, it becomes the file [`CookieJar::save`] writes to
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, VideoError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| VideoError::CookieJarError(format!("{}: {err}", path.display())))?;
+
+        let mut jar = Self::from_netscape(&content)?;
+        jar.path = Some(path.to_path_buf());
+
+        Ok(jar)
+    }
+
+    /// Parse the content of a Netscape `cookies.txt` file. Expired cookies and malformed lines are
+    /// skipped, like curl and yt-dlp do
+    pub fn from_netscape(content: &str) -> Result<Self, VideoError> {
+        let now = unix_now();
+        let mut cookies: Vec<StoredCookie> = vec![];
+
+        for line in content.lines() {
+            let Ok(cookie) = StoredCookie::parse_line(line) else {
+                continue;
+            };
+
+            if let Some(cookie) = cookie.filter(|cookie| !cookie.is_expired(now)) {
+                cookies.retain(|stored| !stored.same_key(&cookie));
+                cookies.push(cookie);
+            }
+        }
+
+        Ok(Self {
+            cookies: Arc::new(RwLock::new(cookies)),
+            ..Default::default()
+        })
+    }
+
+    /// File written by [`CookieJar::save`] and autosave. Default is the loaded file
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Save the jar to its file in the background after every response setting cookies. Default is `false`
+    pub fn autosave(mut self, autosave: bool) -> Self {
+        self.autosave = autosave;
+        self
+    }
+
+    /// Content of the jar in Netscape `cookies.txt` format, session cookies included
+    pub fn to_netscape(&self) -> String {
+        let now = unix_now();
+        let mut content = format!("{NETSCAPE_HEADER}\n\n");
+
+        for cookie in self.cookies.read().unwrap().iter() {
+            if !cookie.is_expired(now) {
+                content.push_str(&cookie.to_line());
+                content.push('\n');
+            }
+        }
+
+        content
+    }
+
+    /// Write the jar to the file it was loaded from or set with [`CookieJar::path`]
+    pub fn save(&self) -> Result<(), VideoError> {
+        match &self.path {
+            Some(path) => self.save_to(path),
+            None => Err(VideoError::CookieJarError(
+                "cookie jar has no file to save to".to_string(),
+            )),
+        }
+    }
+
+    /// Write the jar to `path` in Netscape `cookies.txt` format, readable by the owner only
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<(), VideoError> {
+        use std::io::Write;
+
+        let path = path.as_ref();
+        let map_err =
+            |err: std::io::Error| VideoError::CookieJarError(format!("{}: {err}", path.display()));
+
+        // Replace the file in one step so a crash never leaves a truncated jar behind, the
+        // temporary file has a unique name and is created with mode 0600
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut temp = tempfile::Builder::new()
+            .prefix(".cookies")
+            .tempfile_in(dir)
+            .map_err(map_err)?;
+        temp.write_all(self.to_netscape().as_bytes())
+            .map_err(map_err)?;
+        temp.persist(path).map_err(|err| map_err(err.error))?;
+
+        Ok(())
+    }
+
+    /// Save the jar off the async executor, saves requested while one runs are merged into it
+    fn schedule_save(&self) {
+        self.saves.pending.store(true, Ordering::SeqCst);
+        if self.saves.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let jar = self.clone();
+        let save = move || loop {
+            jar.saves.pending.store(false, Ordering::SeqCst);
+            // Failing to persist must not fail the request, the jar stays usable in memory
+            let _ = jar.save();
+            jar.saves.running.store(false, Ordering::SeqCst);
+
+            if !jar.saves.pending.load(Ordering::SeqCst)
+                || jar.saves.running.swap(true, Ordering::SeqCst)
+            {
+                break;
+            }
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => drop(handle.spawn_blocking(save)),
+            Err(_) => save(),
+        }
+    }
+
+    /// Add cookies of a `Cookie` header like `key1=value1; key2=value2` for the host of `url` and its subdomains
+    pub fn add_cookie_str(&self, cookies: &str, url: &Url) {
+        let Some(host) = url.host_str() else {
+            return;
+        };
+
+        let mut stored = self.cookies.write().unwrap();
+        for pair in cookies.split(';') {
+            let Some((name, value)) = pair.split_once('=') else {
+                continue;
+            };
+
+            let cookie = StoredCookie {
+                domain: host.trim_start_matches("www.").to_ascii_lowercase(),
+                include_subdomains: true,
+                path: "/".to_string(),
+                secure: false,
+                http_only: false,
+                expires: None,
+                name: name.trim().to_string(),
+                value: value.trim().to_string(),
+            };
+            stored.retain(|stored| !stored.same_key(&cookie));
+            stored.push(cookie);
+        }
+    }
+
+    /// Value of the cookie `name` sent to `url`
+    pub fn get(&self, url: &Url, name: &str) -> Option<String> {
+        let now = unix_now();
+
+        self.cookies
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|cookie| cookie.name == name && cookie.matches(url, now))
+            .max_by_key(|cookie| cookie.path.len())
+            .map(|cookie| cookie.value.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.read().unwrap().is_empty()
+    }
+
+    /// Parse a `Set-Cookie` header received from `url`, `None` if it is invalid or not allowed for the host
+    fn parse_set_cookie(header: &HeaderValue, url: &Url, now: u64) -> Option<StoredCookie> {
+        let parsed = cookie::Cookie::parse(header.to_str().ok()?).ok()?;
+        let host = url.host_str()?.to_ascii_lowercase();
+
+        let (domain, include_subdomains) = match parsed.domain() {
+            Some(domain) => {
+                let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+                // A host may only set cookies for itself or its parent domains
+                if host != domain && !host.ends_with(&format!(".{domain}")) {
+                    return None;
+                }
+                (domain, true)
+            }
+            None => (host, false),
+        };
+
+        let path = match parsed.path() {
+            Some(path) if path.starts_with('/') => path.to_string(),
+            _ => default_path(url.path()),
+        };
+
+        // Max-Age takes precedence over Expires
+        let expires = match (parsed.max_age(), parsed.expires_datetime()) {
+            (Some(max_age), _) => Some(now.saturating_add_signed(max_age.whole_seconds())),
+            (None, Some(expires)) => Some(expires.unix_timestamp().max(0) as u64),
+            (None, None) => None,
+        };
+
+        Some(StoredCookie {
+            domain,
+            include_subdomains,
+            path,
+            secure: parsed.secure().unwrap_or(false),
+            http_only: parsed.http_only().unwrap_or(false),
+            expires,
+            name: parsed.name().to_string(),
+            value: parsed.value().to_string(),
+        })
+    }
+}
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let now = unix_now();
+        let mut changed = false;
+
+        {
+            let mut stored = self.cookies.write().unwrap();
+            for cookie in
+                cookie_headers.filter_map(|header| Self::parse_set_cookie(header, url, now))
+            {
+                let previous = stored.iter().position(|stored| stored.same_key(&cookie));
+                if previous.is_some_and(|index| stored[index] == cookie) {
+                    continue;
+                }
+
+                if let Some(index) = previous {
+                    stored.remove(index);
+                }
+                // Expiry in the past deletes the cookie
+                if !cookie.is_expired(now) {
+                    stored.push(cookie);
+                }
+                changed = true;
+            }
+        }
+
+        if changed && self.autosave && self.path.is_some() {
+            self.schedule_save();
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let now = unix_now();
+        let stored = self.cookies.read().unwrap();
+
+        let mut cookies: Vec<&StoredCookie> = stored
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .collect();
+        if cookies.is_empty() {
+            return None;
+        }
+
+        // More specific paths first
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        let header = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect::<Vec<String>>()
+            .join("; ");
+
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+impl fmt::Debug for CookieJar {
+    // Cookie values are credentials, keep them out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieJar")
+            .field("cookies", &self.len())
+            .field("path", &self.path)
+            .field("autosave", &self.autosave)
+            .finish()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// RFC 6265 path matching
+fn path_match(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+/// RFC 6265 default path of a cookie set without `Path`
+fn default_path(request_path: &str) -> String {
+    match request_path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(index) => request_path[..index].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COOKIES_TXT: &str = "# Netscape HTTP Cookie File
+# comment
+
+.youtube.com\tTRUE\t/\tTRUE\t0\tSID\tsid
+#HttpOnly_.youtube.com\tTRUE\t/\tTRUE\t4102444800\t__Secure-3PSID\told
+accounts.google.com\tFALSE\t/\tFALSE\t4102444800\tLSID\tlsid
+.youtube.com\tTRUE\t/\tFALSE\t1\tEXPIRED\tvalue
+";
+
+    #[test]
+    fn test_netscape_round_trip() {
+        let jar = CookieJar::from_netscape(COOKIES_TXT).unwrap();
+        assert_eq!(jar.len(), 3);
+
+        let reloaded = CookieJar::from_netscape(&jar.to_netscape()).unwrap();
+        assert_eq!(
+            *reloaded.cookies.read().unwrap(),
+            *jar.cookies.read().unwrap()
+        );
+
+        // Malformed lines are skipped
+        let jar =
+            CookieJar::from_netscape(&format!("youtube.com\tTRUE\t/\n{COOKIES_TXT}")).unwrap();
+        assert_eq!(jar.len(), 3);
+    }
+
+    #[test]
+    fn test_cookies_are_scoped() {
+        let jar = CookieJar::from_netscape(COOKIES_TXT).unwrap();
+        let youtube = Url::parse("https://www.youtube.com/watch?v=FZ8BxMU3BYc").unwrap();
+
+        assert_eq!(
+            jar.cookies(&youtube).unwrap(),
+            "SID=sid; __Secure-3PSID=old"
+        );
+        assert!(jar
+            .cookies(&Url::parse("http://www.youtube.com/").unwrap())
+            .is_none());
+        assert!(jar
+            .cookies(&Url::parse("https://notyoutube.com/").unwrap())
+            .is_none());
+        assert_eq!(
+            jar.cookies(&Url::parse("https://accounts.google.com/").unwrap())
+                .unwrap(),
+            "LSID=lsid"
+        );
+        assert!(jar
+            .cookies(&Url::parse("https://sub.accounts.google.com/").unwrap())
+            .is_none());
+    }
+
+    #[test]
+    fn test_rotated_cookies_are_saved() {
+        let path =
+            std::env::temp_dir().join(format!("rusty_ytdl_cookies_{}.txt", std::process::id()));
+        std::fs::write(&path, COOKIES_TXT).unwrap();
+
+        let jar = CookieJar::load(&path).unwrap().autosave(true);
+        let youtube = Url::parse("https://www.youtube.com/").unwrap();
+
+        let headers = [
+            HeaderValue::from_static(
+                "__Secure-3PSID=new; Domain=.youtube.com; Path=/; Secure; HttpOnly; Max-Age=3600",
+            ),
+            HeaderValue::from_static("SID=; Domain=.youtube.com; Path=/; Max-Age=0"),
+            HeaderValue::from_static("EVIL=1; Domain=.google.com"),
+        ];
+        jar.set_cookies(&mut headers.iter(), &youtube);
+
+        let saved = CookieJar::load(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            saved.get(&youtube, "__Secure-3PSID").as_deref(),
+            Some("new")
+        );
+        assert_eq!(saved.get(&youtube, "SID"), None);
+        assert_eq!(saved.len(), 2);
+    }
+}
@@ -0,0 +1,125 @@
+use std::path::Path;
+use std::time::Duration;
+
+use crate::stream::{LiveStream, LiveStreamOptions, Stream, VariantSelection};
+use crate::structs::{RequestOptions, VideoError};
+use crate::utils::build_client;
+
+/// Options of an [`HlsDownloader`]
+#[derive(Clone, Debug, Default)]
+pub struct HlsOptions {
+    pub request_options: RequestOptions,
+    /// Variant downloaded from a master playlist
+    pub variant: VariantSelection,
+    /// Switch between variants based on the measured throughput, see [`LiveStreamOptions::adaptive`]
+    pub adaptive: bool,
+    /// Language of the separate audio rendition, the default rendition otherwise
+    pub audio_language: Option<String>,
+    /// Live playlists which do not change for this long fail the download. Default is 60 seconds
+    pub stall_timeout: Option<Duration>,
+    /// Segments downloaded concurrently. Default is 3
+    pub prefetch: Option<usize>,
+    /// Attempts to download a failed segment again. Default is 2
+    pub segment_retries: Option<u32>,
+    /// Fetch segments missed between live playlist reloads, see [`LiveStreamOptions::recover_gaps`]
+    pub recover_gaps: bool,
+}
+
+/// Download any HLS playlist, not only YouTube ones.
+///
+/// Media and master playlists of VOD and live streams are supported, with AES-128 and SAMPLE-AES
+/// encryption, byte ranges, discontinuities and initialization segments.
+///
+/// # Example
+///
+/// ```ignore
+/// let downloader = HlsDownloader::new("https://example.com/event/master.m3u8")?;
+///
+/// downloader.download("event.ts").await?;
+/// ```
+pub struct HlsDownloader {
+    url: String,
+    options: HlsOptions,
+    client: reqwest_middleware::ClientWithMiddleware,
+}
+
+impl HlsDownloader {
+    /// Create [`HlsDownloader`] for the playlist at `url` with default [`HlsOptions`]
+    pub fn new(url: impl Into<String>) -> Result<Self, VideoError> {
+        Self::new_with_options(url, HlsOptions::default())
+    }
+
+    /// Create [`HlsDownloader`] for the playlist at `url` with custom [`HlsOptions`]
+    pub fn new_with_options(
+        url: impl Into<String>,
+        options: HlsOptions,
+    ) -> Result<Self, VideoError> {
+        let url = url::Url::parse(&url.into())?.to_string();
+        let client = build_client(&options.request_options)?;
+
+        Ok(Self {
+            url,
+            options,
+            client,
+        })
+    }
+
+    /// Get playlist URL
+    pub fn get_url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Stream of the playlist, the selected variant for master playlists
+    pub fn stream(&self) -> Result<LiveStream, VideoError> {
+        LiveStream::new(LiveStreamOptions {
+            client: Some(self.client.clone()),
+            stream_url: self.url.clone(),
+            stall_timeout: self.options.stall_timeout,
+            variant: self.options.variant.clone(),
+            adaptive: self.options.adaptive,
+            audio_language: self.options.audio_language.clone(),
+            prefetch: self.options.prefetch,
+            segment_retries: self.options.segment_retries,
+            recover_gaps: self.options.recover_gaps,
+        })
+    }
+
+    /// Stream of the separate audio rendition of the selected variant, see [`LiveStream::audio_rendition`]
+    pub async fn audio_stream(&self) -> Result<Option<LiveStream>, VideoError> {
+        self.stream()?.audio_rendition().await
+    }
+
+    /// Download the playlist directly to the file
+    pub async fn download<P: AsRef<Path>>(&self, path: P) -> Result<(), VideoError> {
+        write_stream(&self.stream()?, path).await
+    }
+
+    /// Download the playlist as one file per segment with a local playlist, see [`LiveStream::download_segments`]
+    pub async fn download_segments<P: AsRef<Path>>(&self, dir: P) -> Result<(), VideoError> {
+        self.stream()?.download_segments(dir).await
+    }
+
+    /// Download the separate audio rendition directly to the file, `false` if the variant has none
+    pub async fn download_audio<P: AsRef<Path>>(&self, path: P) -> Result<bool, VideoError> {
+        let Some(stream) = self.audio_stream().await? else {
+            return Ok(false);
+        };
+
+        write_stream(&stream, path).await?;
+
+        Ok(true)
+    }
+}
+
+async fn write_stream<P: AsRef<Path>>(stream: &LiveStream, path: P) -> Result<(), VideoError> {
+    use std::{fs::File, io::Write};
+
+    let mut file = File::create(path).map_err(|e| VideoError::DownloadError(e.to_string()))?;
+
+    while let Some(chunk) = stream.chunk().await? {
+        file.write_all(&chunk)
+            .map_err(|e| VideoError::DownloadError(e.to_string()))?;
+    }
+
+    Ok(())
+}
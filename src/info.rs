@@ -1,36 +1,43 @@
+use async_trait::async_trait;
 use once_cell::sync::Lazy;
-use reqwest::{
-    header::{HeaderMap, HeaderName, HeaderValue, COOKIE},
-    Client,
-};
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest_middleware::ClientWithMiddleware;
 use scraper::{Html, Selector};
 use serde_json::json;
 use std::{
     borrow::{Borrow, Cow},
+    collections::HashMap,
     path::Path,
+    sync::Arc,
     time::Duration,
 };
 use url::Url;
 
 #[cfg(feature = "live")]
-use crate::stream::{LiveStream, LiveStreamOptions};
+use crate::stream::{
+    DashLiveStream, DashLiveStreamOptions, LiveFromStart, LiveStream, LiveStreamOptions,
+};
 #[cfg(feature = "ffmpeg")]
-use crate::structs::FFmpegArgs;
+use crate::{
+    merge::{ffmetadata, merge_args, supports_cover, video_tags, InputServer, MergeInput},
+    stream::{read_ffmpeg_stderr, wait_ffmpeg},
+    structs::{FFmpegArgs, FFmpegMergeOptions},
+};
 
 use crate::{
-    constants::{BASE_URL, DEFAULT_DL_CHUNK_SIZE, DEFAULT_MAX_RETRIES, INNERTUBE_CLIENT},
+    constants::{BASE_URL, DEFAULT_DL_CHUNK_SIZE, INNERTUBE_CLIENT},
     info_extras::{get_media, get_related_videos},
-    stream::{NonLiveStream, NonLiveStreamOptions, Stream},
+    pool::video_client,
+    stream::{LinkRefresher, NonLiveStream, NonLiveStreamOptions, Stream},
     structs::{
-        CustomRetryableStrategy, PlayerResponse, VideoError, VideoInfo, VideoOptions, YTConfig,
+        PlayerResponse, RequestOptions, VideoDetails, VideoError, VideoFormat, VideoInfo,
+        VideoOptions, VideoQuality, VideoSearchOptions, YTConfig,
     },
+    tagging::{write_tags, Tags},
     utils::{
-        between, choose_format, clean_video_details, get_functions, get_html, get_html5player,
-        get_random_v6_ip, get_video_id, get_visitor_data, get_ytconfig,
-        is_age_restricted_from_html, is_live, is_not_yet_broadcasted, is_play_error,
-        is_player_response_error, is_private_video, is_rental, parse_live_video_formats,
+        between, build_client, choose_format, clean_video_details, get_functions, get_html,
+        get_html5player, get_playability_error, get_random_v6_ip, get_video_id, get_visitor_data,
+        get_ytconfig, is_age_restricted_from_html, is_live, is_upcoming, parse_live_video_formats,
         parse_video_formats, sort_formats,
     },
 };
@@ -52,17 +59,7 @@ impl Video<'static> {
     pub fn new(url_or_id: impl Into<String>) -> Result<Self, VideoError> {
         let video_id = get_video_id(&url_or_id.into()).ok_or(VideoError::VideoNotFound)?;
 
-        let client = Client::builder().build().map_err(VideoError::Reqwest)?;
-
-        let retry_policy = ExponentialBackoff::builder()
-            .retry_bounds(Duration::from_millis(1000), Duration::from_millis(30000))
-            .build_with_max_retries(DEFAULT_MAX_RETRIES);
-        let client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy_and_strategy(
-                retry_policy,
-                CustomRetryableStrategy,
-            ))
-            .build();
+        let client = build_client(&RequestOptions::default())?;
 
         Ok(Self {
             video_id,
@@ -83,48 +80,7 @@ impl<'opts> Video<'opts> {
         let options = options.into();
         let video_id = get_video_id(&url_or_id.into()).ok_or(VideoError::VideoNotFound)?;
 
-        let client = match options.request_options.client.clone() {
-            Some(client) => client,
-            None => {
-                let mut client_builder = Client::builder();
-
-                if let Some(proxy) = &options.request_options.proxy {
-                    client_builder = client_builder.proxy(proxy.clone());
-                }
-
-                if let Some(ipv6_block) = &options.request_options.ipv6_block {
-                    let ipv6 = get_random_v6_ip(ipv6_block)?;
-                    client_builder = client_builder.local_address(ipv6);
-                }
-
-                if let Some(cookie) = &options.request_options.cookies {
-                    let mut headers = HeaderMap::new();
-                    headers.insert(
-                        COOKIE,
-                        HeaderValue::from_str(cookie).map_err(|_x| VideoError::CookieError)?,
-                    );
-
-                    client_builder = client_builder.default_headers(headers)
-                }
-
-                client_builder.build().map_err(VideoError::Reqwest)?
-            }
-        };
-
-        let max_retries = options
-            .request_options
-            .max_retries
-            .unwrap_or(DEFAULT_MAX_RETRIES);
-
-        let retry_policy = ExponentialBackoff::builder()
-            .retry_bounds(Duration::from_millis(1000), Duration::from_millis(30000))
-            .build_with_max_retries(max_retries);
-        let client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy_and_strategy(
-                retry_policy,
-                CustomRetryableStrategy,
-            ))
-            .build();
+        let client = build_client(&options.request_options)?;
 
         Ok(Self {
             video_id,
@@ -133,6 +89,22 @@ impl<'opts> Video<'opts> {
         })
     }
 
+    /// Crate [`Video`] struct sharing an already built client, used by [`crate::VideoBatch`]
+    pub(crate) fn new_with_client(
+        url_or_id: impl Into<String>,
+        options: impl Into<Cow<'opts, VideoOptions>>,
+        client: ClientWithMiddleware,
+    ) -> Result<Self, VideoError> {
+        let video_id = get_video_id(&url_or_id.into()).ok_or(VideoError::VideoNotFound)?;
+
+        Ok(Self {
+            video_id,
+            options: options.into(),
+            // Pools rotating per video pick an egress for this video, not for the shared client
+            client: video_client(client),
+        })
+    }
+
     /// Try to get basic information about video
     /// - `HLS` and `DashMPD` formats excluded!
     #[cfg_attr(feature = "performance_analysis", flamer::flame)]
@@ -179,22 +151,20 @@ impl<'opts> Video<'opts> {
             (player_response, initial_response)
         };
 
-        if is_play_error(&player_response, ["ERROR"].to_vec()) {
-            return Err(VideoError::VideoNotFound);
-        }
+        let mut is_age_restricted = is_age_restricted_from_html(&player_response, &response);
 
-        if let Some(reason) = is_player_response_error(&player_response, &["not a bot"]) {
-            return Err(VideoError::VideoPlayerResponseError(reason));
-        }
-
-        let is_age_restricted = is_age_restricted_from_html(&player_response, &response);
-
-        if is_private_video(&player_response) && !is_age_restricted {
-            return Err(VideoError::VideoIsPrivate);
+        match get_playability_error(&player_response) {
+            // Age restricted videos are requested again with the embedded player below
+            Some(VideoError::AgeRestricted { .. }) => is_age_restricted = true,
+            Some(VideoError::VideoIsPrivate) if is_age_restricted => {}
+            // Upcoming live streams and premieres have details and a scheduled start but no formats yet
+            Some(VideoError::PremiereScheduled { .. }) => {}
+            Some(err) => return Err(err),
+            None => {}
         }
 
         // POToken experiment detected fallback to android_sdkless client (Webpage contains broken formats)
-        if !is_live(&player_response) {
+        if !is_live(&player_response) && !is_upcoming(&player_response) {
             let ios_ytconfig = self
                 .get_player_ytconfig(
                     &response,
@@ -202,7 +172,7 @@ impl<'opts> Video<'opts> {
                         .get("android_sdkless")
                         .cloned()
                         .unwrap_or_default(),
-                    self.options.request_options.po_token.as_ref()
+                    self.options.request_options.po_token.as_ref(),
                 )
                 .await?;
 
@@ -220,19 +190,24 @@ impl<'opts> Video<'opts> {
                         .get("tv_embedded")
                         .cloned()
                         .unwrap_or_default(),
-                    self.options.request_options.po_token.as_ref()
+                    self.options.request_options.po_token.as_ref(),
                 )
                 .await?;
 
             let player_response_new =
                 serde_json::from_str::<PlayerResponse>(&embed_ytconfig).unwrap_or_default();
 
-            player_response.streaming_data = player_response_new.streaming_data;
-            player_response.storyboards = player_response_new.storyboards;
-        }
-
-        if is_rental(&player_response) || is_not_yet_broadcasted(&player_response) {
-            return Err(VideoError::VideoSourceNotFound);
+            if get_playability_error(&player_response_new).is_none() {
+                player_response.streaming_data = player_response_new.streaming_data;
+                player_response.storyboards = player_response_new.storyboards;
+            } else if player_response.streaming_data.is_none() {
+                return Err(VideoError::AgeRestricted {
+                    reason: player_response
+                        .playability_status
+                        .as_ref()
+                        .and_then(|x| x.reason.clone()),
+                });
+            }
         }
 
         let video_details = clean_video_details(
@@ -252,22 +227,33 @@ impl<'opts> Video<'opts> {
             .as_ref()
             .and_then(|x| x.hls_manifest_url.clone());
 
+        let player_functions = get_functions(
+            get_html5player(response.as_str()).unwrap_or_default(),
+            client,
+        )
+        .await?;
+        let mut cipher_report = player_functions.report.clone();
+
+        let formats = parse_video_formats(
+            &player_response,
+            &player_functions,
+            &self.options.cipher_options,
+            &mut cipher_report,
+        )
+        .await
+        .unwrap_or_default();
+
+        if self.options.cipher_options.strict && !cipher_report.is_ok() {
+            return Err(VideoError::CipherExtractionFailed(Box::new(cipher_report)));
+        }
+
         Ok(VideoInfo {
             dash_manifest_url,
             hls_manifest_url,
-            formats: {
-                parse_video_formats(
-                    &player_response,
-                    get_functions(
-                        get_html5player(response.as_str()).unwrap_or_default(),
-                        client,
-                    )
-                    .await?,
-                )
-                .unwrap_or_default()
-            },
+            formats,
             related_videos: { get_related_videos(&initial_response).unwrap_or_default() },
             video_details,
+            cipher_report,
         })
     }
 
@@ -304,9 +290,146 @@ impl<'opts> Video<'opts> {
     ///     }
     /// ```
     pub async fn stream(&self) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
-        let client = &self.client;
+        let info = self.get_info().await?;
+
+        self.stream_from_info(info).await
+    }
+
+    /// Poll the video every `poll_interval` until an upcoming live stream or premiere starts, then turn it into a [`Stream`] like [`Video::stream`].
+    /// Returns right away if the video is not upcoming. Transient errors while polling are ignored, see [`VideoError::is_retryable`]
+    /// # Example
+    /// ```ignore
+    ///     let video = Video::new("https://www.youtube.com/watch?v=FZ8BxMU3BYc").unwrap();
+    ///
+    ///     let info = video.get_basic_info().await.unwrap();
+    ///     println!("Starts at {:?}", info.video_details.scheduled_start_time);
+    ///
+    ///     let stream = video.wait_for_live(Duration::from_secs(30)).await.unwrap();
+    ///
+    ///     while let Some(chunk) = stream.chunk().await.unwrap() {
+    ///           println!("{:#?}", chunk);
+    ///     }
+    /// ```
+    pub async fn wait_for_live(
+        &self,
+        poll_interval: Duration,
+    ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
+        loop {
+            match self.get_info().await {
+                Ok(info) if !info.video_details.is_upcoming => {
+                    return self.stream_from_info(info).await
+                }
+                Ok(_) => {}
+                Err(err) if err.is_retryable() => {}
+                Err(err) => return Err(err),
+            }
+
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
 
+    /// Record a live stream from its earliest available segment instead of the live edge.
+    ///
+    /// Video and audio are separate streams of their own segments, see [`DashLiveStream`]. Lowest qualities of
+    /// [`VideoOptions::quality`] pick the lowest formats, anything else the highest
+    /// # Example
+    /// ```ignore
+    ///     let video = Video::new("https://www.youtube.com/watch?v=jfKfPfyJRdk").unwrap();
+    ///
+    ///     let LiveFromStart { video, audio } = video.stream_live_from_start().await.unwrap();
+    ///
+    ///     while let Some(chunk) = video.chunk().await.unwrap() {
+    ///           println!("{:#?}", chunk);
+    ///     }
+    /// ```
+    #[cfg(feature = "live")]
+    pub async fn stream_live_from_start(&self) -> Result<LiveFromStart, VideoError> {
         let info = self.get_info().await?;
+
+        if info.video_details.is_upcoming {
+            return Err(VideoError::PremiereScheduled {
+                scheduled_start_time: info.video_details.scheduled_start_time,
+                reason: None,
+            });
+        }
+
+        // Only live adaptive formats have a segment duration
+        let live_formats: Vec<VideoFormat> = info
+            .formats
+            .into_iter()
+            .filter(|x| {
+                x.target_duration_sec.is_some() && !x.is_hls && !x.is_dash_mpd && !x.url.is_empty()
+            })
+            .collect();
+
+        let lowest = matches!(
+            self.options.quality,
+            VideoQuality::Lowest | VideoQuality::LowestVideo | VideoQuality::LowestAudio
+        );
+        let choose = |quality, filter| {
+            choose_format(
+                &live_formats,
+                &VideoOptions {
+                    quality,
+                    filter,
+                    ..Default::default()
+                },
+            )
+        };
+        let video_format = choose(
+            if lowest {
+                VideoQuality::LowestVideo
+            } else {
+                VideoQuality::HighestVideo
+            },
+            VideoSearchOptions::Video,
+        )?;
+        let audio_format = choose(
+            if lowest {
+                VideoQuality::LowestAudio
+            } else {
+                VideoQuality::HighestAudio
+            },
+            VideoSearchOptions::Audio,
+        )?;
+
+        let start_sequence = match &info.dash_manifest_url {
+            Some(url) => get_earliest_sequence(url, &self.client).await,
+            None => 0,
+        };
+
+        let stream = |format: VideoFormat| {
+            DashLiveStream::new(DashLiveStreamOptions {
+                client: Some(self.client.clone()),
+                url: format.url,
+                start_sequence,
+                target_duration: format
+                    .target_duration_sec
+                    .and_then(|x| Duration::try_from_secs_f64(x).ok()),
+                end_timeout: None,
+                refresher: Some(self.link_refresher(format.itag)),
+            })
+        };
+
+        Ok(LiveFromStart {
+            video: stream(video_format)?,
+            audio: stream(audio_format)?,
+        })
+    }
+
+    async fn stream_from_info(
+        &self,
+        info: VideoInfo,
+    ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
+        let client = &self.client;
+
+        if info.video_details.is_upcoming {
+            return Err(VideoError::PremiereScheduled {
+                scheduled_start_time: info.video_details.scheduled_start_time,
+                reason: None,
+            });
+        }
+
         let format = choose_format(&info.formats, &self.options)
             .map_err(|_op| VideoError::VideoSourceNotFound)?;
 
@@ -323,6 +446,7 @@ impl<'opts> Video<'opts> {
                 let stream = LiveStream::new(LiveStreamOptions {
                     client: Some(client.clone()),
                     stream_url: link,
+                    ..Default::default()
                 })?;
 
                 return Ok(Box::new(stream));
@@ -368,6 +492,7 @@ impl<'opts> Video<'opts> {
             dl_chunk_size,
             start,
             end,
+            refresher: Some(self.link_refresher(format.itag)),
             #[cfg(feature = "ffmpeg")]
             ffmpeg_args: None,
         })?;
@@ -388,6 +513,7 @@ impl<'opts> Video<'opts> {
     ///            format: Some("mp3".to_string()),
     ///            audio_filter: Some("aresample=48000,asetrate=48000*0.8".to_string()),
     ///            video_filter: Some("eq=brightness=150:saturation=2".to_string()),
+    ///            ..Default::default()
     ///        })).await.unwrap();
     ///
     ///     while let Some(chunk) = stream.chunk().await.unwrap() {
@@ -397,10 +523,20 @@ impl<'opts> Video<'opts> {
     pub async fn stream_with_ffmpeg(
         &self,
         ffmpeg_args: Option<FFmpegArgs>,
+    ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
+        let info = self.get_info().await?;
+
+        self.stream_with_ffmpeg_from_info(info, ffmpeg_args).await
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    async fn stream_with_ffmpeg_from_info(
+        &self,
+        info: VideoInfo,
+        ffmpeg_args: Option<FFmpegArgs>,
     ) -> Result<Box<dyn Stream + Send + Sync>, VideoError> {
         let client = &self.client;
 
-        let info = self.get_info().await?;
         let format = choose_format(&info.formats, &self.options)
             .map_err(|_op| VideoError::VideoSourceNotFound)?;
 
@@ -417,6 +553,7 @@ impl<'opts> Video<'opts> {
                 let stream = LiveStream::new(LiveStreamOptions {
                     client: Some(client.clone()),
                     stream_url: link,
+                    ..Default::default()
                 })?;
 
                 return Ok(Box::new(stream));
@@ -462,26 +599,46 @@ impl<'opts> Video<'opts> {
             dl_chunk_size,
             start,
             end,
+            refresher: Some(self.link_refresher(format.itag)),
             ffmpeg_args,
         })?;
 
         Ok(Box::new(stream))
     }
 
-    /// Download video directly to the file
+    /// Download video directly to the file, tagged with the details of the video when
+    /// [`DownloadOptions::tags`](crate::DownloadOptions::tags) is set
     pub async fn download<P: AsRef<Path>>(&self, path: P) -> Result<(), VideoError> {
         use std::{fs::File, io::Write};
 
-        let stream = self.stream().await?;
+        let info = self.get_info().await?;
+        let details = info.video_details.clone();
+        let stream = self.stream_from_info(info).await?;
 
-        let mut file = File::create(path).map_err(|e| VideoError::DownloadError(e.to_string()))?;
+        let mut file = File::create(&path).map_err(|e| VideoError::DownloadError(e.to_string()))?;
 
         while let Some(chunk) = stream.chunk().await? {
             file.write_all(&chunk)
                 .map_err(|e| VideoError::DownloadError(e.to_string()))?;
         }
+        drop(file);
 
-        Ok(())
+        self.tag_file(path.as_ref(), &details).await
+    }
+
+    /// Write the tags of [`DownloadOptions::tags`](crate::DownloadOptions::tags) into a downloaded file
+    async fn tag_file(&self, path: &Path, details: &VideoDetails) -> Result<(), VideoError> {
+        let Some(options) = &self.options.download_options.tags else {
+            return Ok(());
+        };
+
+        let tags = Tags::from_details(&self.client, details, options).await?;
+
+        // Tagging may rewrite the whole file, keep it off the executor threads
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || write_tags(&path, &tags))
+            .await
+            .map_err(|err| VideoError::TaggingError(err.to_string()))?
     }
 
     #[cfg(feature = "ffmpeg")]
@@ -493,16 +650,186 @@ impl<'opts> Video<'opts> {
     ) -> Result<(), VideoError> {
         use std::{fs::File, io::Write};
 
-        let stream = self.stream_with_ffmpeg(ffmpeg_args).await?;
+        let info = self.get_info().await?;
+        let details = info.video_details.clone();
+        let stream = self.stream_with_ffmpeg_from_info(info, ffmpeg_args).await?;
 
-        let mut file = File::create(path).map_err(|e| VideoError::DownloadError(e.to_string()))?;
+        let mut file = File::create(&path).map_err(|e| VideoError::DownloadError(e.to_string()))?;
 
         while let Some(chunk) = stream.chunk().await? {
             file.write_all(&chunk)
                 .map_err(|e| VideoError::DownloadError(e.to_string()))?;
         }
+        drop(file);
+
+        self.tag_file(path.as_ref(), &details).await
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    /// Download the best video only and audio only formats merged into one file with ffmpeg.
+    ///
+    /// The lowest formats are merged when [`VideoOptions::quality`] is `Lowest`, `LowestVideo` or
+    /// `LowestAudio`, the highest ones otherwise. Both are copied into the output container unless
+    /// [`FFmpegMergeOptions`] sets codecs, the container follows the file extension by default.
+    /// # Example
+    /// ```ignore
+    ///     let video = Video::new("https://www.youtube.com/watch?v=FZ8BxMU3BYc").unwrap();
+    ///
+    ///     video.download_merged_with_ffmpeg("video.mkv", FFmpegMergeOptions {
+    ///            metadata: true,
+    ///            chapters: true,
+    ///            thumbnail: true,
+    ///            ..Default::default()
+    ///        }).await.unwrap();
+    /// ```
+    pub async fn download_merged_with_ffmpeg<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: FFmpegMergeOptions,
+    ) -> Result<(), VideoError> {
+        let info = self.get_info().await?;
+
+        // Live formats are not split into video and audio
+        let formats: Vec<VideoFormat> = info
+            .formats
+            .iter()
+            .filter(|x| !x.is_live && !x.is_hls)
+            .cloned()
+            .collect();
+
+        let lowest = matches!(
+            self.options.quality,
+            VideoQuality::Lowest | VideoQuality::LowestVideo | VideoQuality::LowestAudio
+        );
+        let choose = |quality, filter| {
+            choose_format(
+                &formats,
+                &VideoOptions {
+                    quality,
+                    filter,
+                    ..self.options.clone().into_owned()
+                },
+            )
+        };
+        let (video_quality, audio_quality) = if lowest {
+            (VideoQuality::LowestVideo, VideoQuality::LowestAudio)
+        } else {
+            (VideoQuality::HighestVideo, VideoQuality::HighestAudio)
+        };
+        let video = choose(video_quality, VideoSearchOptions::Video)?;
+        let audio = choose(audio_quality, VideoSearchOptions::Audio)?;
+
+        let dl_chunk_size = self
+            .options
+            .download_options
+            .dl_chunk_size
+            .unwrap_or(DEFAULT_DL_CHUNK_SIZE);
+
+        let mut inputs = HashMap::new();
+        for (name, format) in [("video", video), ("audio", audio)] {
+            if format.url.is_empty() {
+                return Err(VideoError::VideoSourceNotFound);
+            }
+
+            let content_length = match format
+                .content_length
+                .as_deref()
+                .and_then(|x| x.parse::<u64>().ok())
+            {
+                Some(content_length) if content_length > 0 => content_length,
+                // Get content length from source url
+                _ => self
+                    .client
+                    .get(&format.url)
+                    .send()
+                    .await
+                    .map_err(VideoError::ReqwestMiddleware)?
+                    .content_length()
+                    .ok_or(VideoError::VideoNotFound)?,
+            };
+
+            inputs.insert(
+                name,
+                MergeInput::Stream {
+                    link: format.url,
+                    content_length,
+                    dl_chunk_size,
+                    refresher: Some(self.link_refresher(format.itag)),
+                },
+            );
+        }
+
+        let details = &info.video_details;
+        let metadata = options.metadata || options.chapters;
+        if metadata {
+            let tags = if options.metadata {
+                video_tags(details)
+            } else {
+                vec![]
+            };
+            let chapters = if options.chapters {
+                details.chapters.as_slice()
+            } else {
+                &[]
+            };
+            let length = details.length_seconds.parse::<i64>().unwrap_or(0) * 1000;
+
+            inputs.insert(
+                "metadata",
+                MergeInput::Data(ffmetadata(&tags, chapters, length).into_bytes()),
+            );
+        }
+
+        let thumbnail = details
+            .thumbnails
+            .iter()
+            .max_by_key(|x| x.width * x.height)
+            .filter(|_| options.thumbnail && supports_cover(&options, path.as_ref()));
+        if let Some(thumbnail) = thumbnail {
+            let data = self
+                .client
+                .get(&thumbnail.url)
+                .send()
+                .await
+                .map_err(VideoError::ReqwestMiddleware)?
+                .error_for_status()
+                .map_err(VideoError::Reqwest)?
+                .bytes()
+                .await
+                .map_err(VideoError::Reqwest)?;
 
-        Ok(())
+            inputs.insert("thumbnail", MergeInput::Data(data.to_vec()));
+        }
+
+        let server = InputServer::start(self.client.clone(), inputs).await?;
+        let args = merge_args(
+            &server,
+            &options,
+            metadata,
+            thumbnail.is_some(),
+            &path.as_ref().to_string_lossy(),
+        );
+
+        let mut child = tokio::process::Command::new("ffmpeg")
+            .args(&args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|x| VideoError::FFmpeg(x.to_string()))?;
+
+        let stderr = read_ffmpeg_stderr(
+            child.stderr.take().expect("stderr is piped"),
+            Default::default(),
+        );
+
+        // A failed download only shows as a broken input to ffmpeg, which may still exit successfully
+        let result = wait_ffmpeg(child, stderr).await;
+        match server.error().await {
+            Some(err) => Err(err),
+            None => result,
+        }
     }
 
     /// Get video URL
@@ -515,6 +842,18 @@ impl<'opts> Video<'opts> {
         self.video_id.clone()
     }
 
+    /// Refresher resolving the current link of the `itag` format through [`Video::get_info`]
+    pub(crate) fn link_refresher(&self, itag: u64) -> Arc<dyn LinkRefresher> {
+        Arc::new(FormatLinkRefresher {
+            video: Video {
+                video_id: self.video_id.clone(),
+                options: Cow::Owned(self.options.clone().into_owned()),
+                client: self.client.clone(),
+            },
+            itag,
+        })
+    }
+
     // Necessary to blocking api
     #[allow(dead_code)]
     pub(crate) fn get_client(&self) -> &reqwest_middleware::ClientWithMiddleware {
@@ -542,7 +881,7 @@ impl<'opts> Video<'opts> {
         let sts = ytcfg.sts.unwrap_or(0);
         let video_id = self.get_video_id();
 
-        let visitor_data = get_visitor_data(&html)?;
+        let visitor_data = get_visitor_data(html)?;
 
         let mut query = serde_json::from_str::<serde_json::Value>(&format!(
             r#"{{
@@ -563,7 +902,7 @@ impl<'opts> Video<'opts> {
                 .expect("Declared as object above")
                 .insert(
                     "serviceIntegrityDimensions".to_string(),
-                    json!({"poToken": po_token})
+                    json!({"poToken": po_token}),
                 );
         }
 
@@ -590,7 +929,7 @@ impl<'opts> Video<'opts> {
         );
         headers.insert(
             HeaderName::from_str("X-Goog-Visitor-Id").unwrap(),
-            HeaderValue::from_str(&visitor_data).unwrap()
+            HeaderValue::from_str(&visitor_data).unwrap(),
         );
 
         let response = self
@@ -613,6 +952,24 @@ impl<'opts> Video<'opts> {
     }
 }
 
+/// Earliest segment number the DASH manifest of a live stream still serves, `0` if unknown
+#[cfg(feature = "live")]
+async fn get_earliest_sequence(url: &str, client: &ClientWithMiddleware) -> u64 {
+    static EARLIEST_SEQUENCE_REGEX: Lazy<regex::Regex> =
+        Lazy::new(|| regex::Regex::new(r#"yt:earliestMediaSequence="(\d+)""#).unwrap());
+
+    get_html(client, url, None)
+        .await
+        .ok()
+        .and_then(|body| {
+            EARLIEST_SEQUENCE_REGEX
+                .captures(&body)
+                .and_then(|x| x.get(1))
+                .and_then(|x| x.as_str().parse::<u64>().ok())
+        })
+        .unwrap_or(0)
+}
+
 async fn get_m3u8(
     url: &str,
     client: &reqwest_middleware::ClientWithMiddleware,
@@ -647,3 +1004,27 @@ async fn get_m3u8(
         })
         .collect::<Vec<(String, String)>>())
 }
+
+/// Resolves a fresh link of a format of a video by its itag
+struct FormatLinkRefresher {
+    video: Video<'static>,
+    itag: u64,
+}
+
+#[async_trait]
+impl LinkRefresher for FormatLinkRefresher {
+    async fn refresh(&self) -> Result<String, VideoError> {
+        let info = self.video.get_info().await?;
+
+        info.formats
+            .into_iter()
+            .find(|format| {
+                format.itag == self.itag
+                    && !format.is_hls
+                    && !format.is_dash_mpd
+                    && !format.url.is_empty()
+            })
+            .map(|format| format.url)
+            .ok_or(VideoError::VideoSourceNotFound)
+    }
+}
@@ -0,0 +1,325 @@
+use async_trait::async_trait;
+use boa_engine::{Context, JsError, Source};
+use std::fmt::Debug;
+
+use crate::structs::{JsLimits, VideoError};
+
+/// JavaScript engine used to run the signature and n-parameter functions of the player
+///
+/// # Example
+///
+/// ```ignore
+///     let video_options = VideoOptions {
+///         cipher_options: CipherOptions {
+///             js_runtime: Arc::new(ExternalRuntime::node()),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     };
+/// ```
+#[async_trait]
+pub trait JsRuntime: Send + Sync + Debug {
+    /// Short name of the runtime, recorded in [`crate::CipherReport::js_runtime`]
+    fn name(&self) -> &str;
+
+    /// Evaluate `script` and call `function_name` once per input, results are returned in input order.
+    /// An input which throws only fails its own result, a script which fails to evaluate fails the whole batch
+    async fn call(
+        &self,
+        script: &str,
+        function_name: &str,
+        inputs: &[String],
+        limits: &JsLimits,
+    ) -> Result<Vec<Result<String, VideoError>>, VideoError>;
+}
+
+/// Embedded [`boa_engine`] runtime, budgeted by [`JsLimits`]. Used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BoaRuntime;
+
+#[async_trait]
+impl JsRuntime for BoaRuntime {
+    fn name(&self) -> &str {
+        "boa"
+    }
+
+    async fn call(
+        &self,
+        script: &str,
+        function_name: &str,
+        inputs: &[String],
+        limits: &JsLimits,
+    ) -> Result<Vec<Result<String, VideoError>>, VideoError> {
+        let (script, function_name, inputs, limits) = (
+            script.to_string(),
+            function_name.to_string(),
+            inputs.to_vec(),
+            limits.clone(),
+        );
+
+        // boa is CPU bound up to the budget, keep it off the executor threads
+        tokio::task::spawn_blocking(move || {
+            // Fresh realm for every player script
+            let mut interpreter = Interpreter::new(&script, &limits)?;
+
+            Ok(inputs
+                .iter()
+                .map(|input| interpreter.call(&function_name, input))
+                .collect())
+        })
+        .await
+        .map_err(|err| VideoError::JsExecutionError(err.to_string()))?
+    }
+}
+
+/// Isolated JavaScript realm running a single player script under a runtime budget
+pub struct Interpreter {
+    context: Context,
+    budget_exceeded: bool,
+}
+
+impl Interpreter {
+    /// Create a fresh realm and evaluate `script` in it
+    #[cfg_attr(feature = "performance_analysis", flamer::flame)]
+    pub fn new(script: &str, limits: &JsLimits) -> Result<Self, VideoError> {
+        let mut context = Context::default();
+        let runtime_limits = context.runtime_limits_mut();
+        runtime_limits.set_loop_iteration_limit(limits.loop_iteration_limit);
+        runtime_limits.set_recursion_limit(limits.recursion_limit);
+        runtime_limits.set_stack_size_limit(limits.stack_size_limit);
+
+        let mut interpreter = Self {
+            context,
+            budget_exceeded: false,
+        };
+        interpreter.eval(script)?;
+
+        Ok(interpreter)
+    }
+
+    /// Call `function_name` with a single string argument and return its string result
+    #[cfg_attr(feature = "performance_analysis", flamer::flame)]
+    pub fn call(&mut self, function_name: &str, argument: &str) -> Result<String, VideoError> {
+        // A realm which already ran out of budget is not trusted again
+        if self.budget_exceeded {
+            return Err(VideoError::JsBudgetExceeded);
+        }
+
+        let argument = serde_json::to_string(argument)
+            .map_err(|err| VideoError::JsExecutionError(err.to_string()))?;
+
+        let result = self.eval(&format!("{function_name}({argument})"))?;
+
+        result
+            .as_string()
+            .and_then(|js_str| js_str.to_std_string().ok())
+            .ok_or_else(|| {
+                VideoError::JsExecutionError(format!("{function_name} did not return a string"))
+            })
+    }
+
+    fn eval(&mut self, code: &str) -> Result<boa_engine::JsValue, VideoError> {
+        self.context
+            .eval(Source::from_bytes(code))
+            .map_err(|err| self.map_error(err))
+    }
+
+    fn map_error(&mut self, err: JsError) -> VideoError {
+        if err
+            .as_native()
+            .is_some_and(|native| native.is_runtime_limit())
+        {
+            self.budget_exceeded = true;
+            return VideoError::JsBudgetExceeded;
+        }
+
+        VideoError::JsExecutionError(err.to_string())
+    }
+}
+
+#[cfg(feature = "external-js")]
+pub use external::ExternalRuntime;
+
+#[cfg(feature = "external-js")]
+mod external {
+    use async_trait::async_trait;
+    use std::{io::Write, path::PathBuf, process::Stdio, time::Duration};
+    use tokio::{io::AsyncWriteExt, process::Command};
+
+    use super::JsRuntime;
+    use crate::{
+        constants::DEFAULT_EXTERNAL_JS_TIMEOUT,
+        structs::{JsLimits, VideoError},
+    };
+
+    /// Runtime shelling out to a local JavaScript binary such as `node`, `deno` or `qjs`.
+    ///
+    /// Each batch runs in a new process. [`JsLimits`] don't apply, the process is killed after [`ExternalRuntime::timeout`] instead.
+    /// The script is downloaded from YouTube, prefer runtimes which can run it without file system and network access
+    #[derive(Debug, Clone)]
+    pub struct ExternalRuntime {
+        /// Name recorded in the cipher report
+        pub name: String,
+        /// Binary to execute, looked up in `PATH` if not absolute
+        pub program: PathBuf,
+        /// Arguments placed before the script path
+        pub args: Vec<String>,
+        /// Pipe the script to stdin instead of passing the path of a temporary file,
+        /// `args` have to make the runtime read stdin (e.g. `-`)
+        pub stdin: bool,
+        /// Wall-clock limit of a batch
+        pub timeout: Duration,
+    }
+
+    impl ExternalRuntime {
+        /// Run `program` as `program [args..] <script path>`
+        pub fn new(
+            name: impl Into<String>,
+            program: impl Into<PathBuf>,
+            args: Vec<String>,
+        ) -> Self {
+            Self {
+                name: name.into(),
+                program: program.into(),
+                args,
+                stdin: false,
+                timeout: DEFAULT_EXTERNAL_JS_TIMEOUT,
+            }
+        }
+
+        /// [Node.js](https://nodejs.org) runtime, executed with the permission model and no file system grants.
+        ///
+        /// Requires Node.js 22.13 or newer, older versions take `--experimental-permission` instead.
+        /// The permission model does not restrict network access
+        pub fn node() -> Self {
+            Self {
+                stdin: true,
+                ..Self::new(
+                    "node",
+                    "node",
+                    vec!["--permission".to_string(), "-".to_string()],
+                )
+            }
+        }
+
+        /// [Deno](https://deno.com) runtime, executed without any permissions
+        pub fn deno() -> Self {
+            Self {
+                stdin: true,
+                ..Self::new(
+                    "deno",
+                    "deno",
+                    vec!["run".to_string(), "--quiet".to_string(), "-".to_string()],
+                )
+            }
+        }
+
+        /// [QuickJS](https://bellard.org/quickjs) runtime, executed without the `std` and `os` globals.
+        ///
+        /// QuickJS has no sandbox, the script could still import these modules
+        pub fn qjs() -> Self {
+            Self::new("qjs", "qjs", vec![])
+        }
+    }
+
+    #[async_trait]
+    impl JsRuntime for ExternalRuntime {
+        fn name(&self) -> &str {
+            &self.name
+        }
+
+        async fn call(
+            &self,
+            script: &str,
+            function_name: &str,
+            inputs: &[String],
+            _limits: &JsLimits,
+        ) -> Result<Vec<Result<String, VideoError>>, VideoError> {
+            let inputs_json = serde_json::to_string(inputs)
+                .map_err(|err| VideoError::JsExecutionError(err.to_string()))?;
+            // Exceptions are returned per input as `{ error }`
+            let program = format!(
+                "{script}\n;console.log(JSON.stringify({inputs_json}.map(function(input){{try{{return String({function_name}(input))}}catch(e){{return {{error:String(e)}}}}}})));"
+            );
+
+            let mut command = Command::new(&self.program);
+            command
+                .args(&self.args)
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .kill_on_drop(true);
+
+            // Created with a random name and owner-only permissions, removed on drop
+            let mut script_file = None;
+            if self.stdin {
+                command.stdin(Stdio::piped());
+            } else {
+                let program = program.clone();
+                let file = tokio::task::spawn_blocking(move || {
+                    let mut file = tempfile::Builder::new()
+                        .prefix("rusty_ytdl_")
+                        .suffix(".js")
+                        .tempfile()?;
+                    file.write_all(program.as_bytes())?;
+                    Ok::<_, std::io::Error>(file)
+                })
+                .await
+                .map_err(|err| VideoError::ChildProcessError(err.to_string()))?
+                .map_err(|err| VideoError::ChildProcessError(err.to_string()))?;
+
+                command.arg(file.path()).stdin(Stdio::null());
+                script_file = Some(file);
+            }
+
+            let mut child = command
+                .spawn()
+                .map_err(|err| VideoError::ChildProcessError(err.to_string()))?;
+            let stdin = child.stdin.take();
+
+            let output = tokio::time::timeout(self.timeout, async move {
+                if let Some(mut stdin) = stdin {
+                    // A runtime exiting early reports its error on stderr
+                    let _ = stdin.write_all(program.as_bytes()).await;
+                }
+                child.wait_with_output().await
+            })
+            .await;
+            drop(script_file);
+
+            let output = output
+                .map_err(|_| VideoError::JsBudgetExceeded)?
+                .map_err(|err| VideoError::ChildProcessError(err.to_string()))?;
+
+            if !output.status.success() {
+                return Err(VideoError::JsExecutionError(
+                    String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                ));
+            }
+
+            let results: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout)
+                .map_err(|err| VideoError::JsExecutionError(err.to_string()))?;
+
+            if results.len() != inputs.len() {
+                return Err(VideoError::JsExecutionError(format!(
+                    "{} returned {} results for {} inputs",
+                    self.name,
+                    results.len(),
+                    inputs.len()
+                )));
+            }
+
+            Ok(results
+                .into_iter()
+                .map(|result| match result {
+                    serde_json::Value::String(result) => Ok(result),
+                    result => Err(VideoError::JsExecutionError(
+                        result["error"]
+                            .as_str()
+                            .unwrap_or("unexpected result")
+                            .to_string(),
+                    )),
+                })
+                .collect())
+        }
+    }
+}
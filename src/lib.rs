@@ -9,9 +9,23 @@ pub extern crate flame;
 #[macro_use]
 extern crate flamer;
 
+mod auth;
+mod batch;
+mod cookies;
+#[cfg(feature = "live")]
+mod hls;
 mod info;
 mod info_extras;
+mod interpreter;
+#[cfg(feature = "ffmpeg")]
+mod merge;
+mod pool;
+mod retry;
 mod structs;
+mod tagging;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_server;
 mod utils;
 
 pub mod constants;
@@ -23,15 +37,27 @@ pub mod blocking;
 #[cfg(feature = "search")]
 pub mod search;
 
+pub use auth::{
+    DeviceCode, FileTokenStore, MemoryTokenStore, OAuth, OAuthClient, OAuthToken, TokenStore,
+};
+pub use batch::VideoBatch;
+pub use cookies::CookieJar;
+#[cfg(feature = "live")]
+pub use hls::{HlsDownloader, HlsOptions};
 pub use info::Video;
+#[cfg(feature = "external-js")]
+pub use interpreter::ExternalRuntime;
+pub use interpreter::{BoaRuntime, JsRuntime};
+pub use pool::{AddressPool, ProxyPool, Rotation};
 pub use structs::{
-    Author, Chapter, ColorInfo, DownloadOptions, Embed, MimeType, RangeObject, RelatedVideo,
-    RequestOptions, StoryBoard, Thumbnail, VideoDetails, VideoError, VideoFormat, VideoInfo,
+    Author, Chapter, CipherOptions, CipherReport, CipherStrategy, ColorInfo, DownloadOptions,
+    Embed, JsLimits, MimeType, RangeObject, RelatedVideo, RemovalKind, RequestOptions, RetryPolicy,
+    StoryBoard, TagOptions, Thumbnail, VideoDetails, VideoError, VideoFormat, VideoInfo,
     VideoOptions, VideoQuality, VideoSearchOptions,
 };
 
 #[cfg(feature = "ffmpeg")]
-pub use structs::FFmpegArgs;
+pub use structs::{FFmpegArgs, FFmpegMergeOptions, FFmpegPreset, FFmpegProgress};
 
 pub use utils::{choose_format, get_random_v6_ip, get_video_id};
 // export to access proxy feature
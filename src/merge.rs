@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    task::JoinHandle,
+};
+
+use crate::stream::{LinkRefresher, NonLiveStream, NonLiveStreamOptions, Stream};
+use crate::structs::{Chapter, FFmpegMergeOptions, VideoDetails, VideoError};
+
+/// Input ffmpeg reads from the [`InputServer`]
+pub(crate) enum MergeInput {
+    /// Media link, downloaded in chunks with a fresh link once it expires
+    Stream {
+        link: String,
+        content_length: u64,
+        dl_chunk_size: u64,
+        refresher: Option<Arc<dyn LinkRefresher>>,
+    },
+    Data(Vec<u8>),
+}
+
+/// Local HTTP server feeding the inputs of ffmpeg, with byte range support for seeking
+pub(crate) struct InputServer {
+    url: String,
+    /// First download error, ffmpeg only sees a broken input
+    error: Arc<Mutex<Option<VideoError>>>,
+    task: JoinHandle<()>,
+}
+
+impl InputServer {
+    /// Serve each input at `/<name>`
+    pub async fn start(
+        client: reqwest_middleware::ClientWithMiddleware,
+        inputs: HashMap<&'static str, MergeInput>,
+    ) -> Result<Self, VideoError> {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .map_err(|e| VideoError::FFmpeg(e.to_string()))?;
+        let url = format!(
+            "http://{}",
+            listener
+                .local_addr()
+                .map_err(|e| VideoError::FFmpeg(e.to_string()))?
+        );
+
+        let inputs = Arc::new(inputs);
+        let error = Arc::new(Mutex::new(None));
+        let task_error = error.clone();
+
+        let task = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let client = client.clone();
+                let inputs = inputs.clone();
+                let error = task_error.clone();
+
+                tokio::spawn(async move {
+                    let mut socket = socket;
+                    // Recorded before the connection closes, ffmpeg may exit right after
+                    if let Err(err) = serve(&mut socket, client, &inputs).await {
+                        error.lock().await.get_or_insert(err);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { url, error, task })
+    }
+
+    pub fn url(&self, name: &str) -> String {
+        format!("{}/{name}", self.url)
+    }
+
+    /// Download error of an input, if there was one
+    pub async fn error(&self) -> Option<VideoError> {
+        self.error.lock().await.take()
+    }
+}
+
+impl Drop for InputServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Answer one request of ffmpeg, only download errors are returned
+async fn serve(
+    socket: &mut TcpStream,
+    client: reqwest_middleware::ClientWithMiddleware,
+    inputs: &HashMap<&'static str, MergeInput>,
+) -> Result<(), VideoError> {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|x| x == b"\r\n\r\n") && request.len() < 16384 {
+        match socket.read(&mut buf).await {
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request).to_lowercase();
+
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let Some(input) = inputs.get(path.trim_start_matches('/')) else {
+        let _ = socket
+            .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+            .await;
+        return Ok(());
+    };
+
+    // ffmpeg seeks with open ended ranges only
+    let start = request
+        .lines()
+        .find_map(|line| line.strip_prefix("range: bytes="))
+        .and_then(|range| range.split('-').next()?.trim().parse::<u64>().ok());
+
+    let content_length = match input {
+        MergeInput::Stream { content_length, .. } => *content_length,
+        MergeInput::Data(data) => data.len() as u64,
+    };
+    let from = start.unwrap_or(0).min(content_length);
+
+    let head = if start.is_some() {
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {from}-{}/{content_length}\r\n",
+            content_length.saturating_sub(1)
+        )
+    } else {
+        "HTTP/1.1 200 OK\r\n".to_string()
+    };
+    let head = format!(
+        "{head}Accept-Ranges: bytes\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        content_length - from
+    );
+    if socket.write_all(head.as_bytes()).await.is_err() {
+        return Ok(());
+    }
+
+    match input {
+        MergeInput::Data(data) => {
+            let _ = socket.write_all(&data[from as usize..]).await;
+        }
+        MergeInput::Stream {
+            link,
+            content_length,
+            dl_chunk_size,
+            refresher,
+        } => {
+            if from == *content_length {
+                return Ok(());
+            }
+
+            let stream = NonLiveStream::new(NonLiveStreamOptions {
+                client: Some(client),
+                link: link.clone(),
+                content_length: *content_length,
+                dl_chunk_size: *dl_chunk_size,
+                start: from,
+                end: from + dl_chunk_size,
+                refresher: refresher.clone(),
+                ffmpeg_args: None,
+            })?;
+
+            while let Some(chunk) = stream.chunk().await? {
+                // ffmpeg closes inputs it seeks away from
+                if socket.write_all(&chunk).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Containers without picture streams, a cover would fail the whole merge
+const NO_COVER_CONTAINERS: [&str; 4] = ["webm", "ogg", "opus", "wav"];
+
+/// Whether the container of `output`, or the one forced by `options`, takes a cover
+pub(crate) fn supports_cover(options: &FFmpegMergeOptions, output: &Path) -> bool {
+    let container = match &options.format {
+        Some(format) => Some(format.to_lowercase()),
+        None => output
+            .extension()
+            .map(|x| x.to_string_lossy().to_lowercase()),
+    };
+
+    container.map_or(true, |container| {
+        !NO_COVER_CONTAINERS.contains(&container.as_str())
+    })
+}
+
+/// ffmpeg arguments merging the `video` and `audio` inputs of `server` into `output`, together with
+/// the `metadata` and `thumbnail` inputs when they are served
+pub(crate) fn merge_args(
+    server: &InputServer,
+    options: &FFmpegMergeOptions,
+    metadata: bool,
+    thumbnail: bool,
+    output: &str,
+) -> Vec<String> {
+    let mut inputs = vec![vec![server.url("video")], vec![server.url("audio")]];
+    let mut args: Vec<String> = [
+        "-y",
+        "-loglevel",
+        "error",
+        "-nostats",
+        "-progress",
+        "pipe:2",
+    ]
+    .map(String::from)
+    .to_vec();
+
+    let mut maps = vec!["0:v:0".to_string(), "1:a:0".to_string()];
+    let mut codecs = vec![
+        "-c:v:0".to_string(),
+        options.video_codec.clone().unwrap_or("copy".to_string()),
+        "-c:a".to_string(),
+        options.audio_codec.clone().unwrap_or("copy".to_string()),
+    ];
+
+    if metadata {
+        let index = inputs.len();
+        inputs.push(vec![
+            "-f".to_string(),
+            "ffmetadata".to_string(),
+            server.url("metadata"),
+        ]);
+        codecs.extend([
+            "-map_metadata".to_string(),
+            index.to_string(),
+            "-map_chapters".to_string(),
+            index.to_string(),
+        ]);
+    }
+
+    // Cover art, thumbnails may be WebP which containers do not take
+    if thumbnail {
+        maps.push(format!("{}:v:0", inputs.len()));
+        inputs.push(vec![server.url("thumbnail")]);
+        codecs.extend(["-c:v:1", "mjpeg", "-disposition:v:1", "attached_pic"].map(String::from));
+    }
+
+    for mut input in inputs {
+        input.insert(input.len() - 1, "-i".to_string());
+        args.extend(input);
+    }
+    for map in maps {
+        args.extend(["-map".to_string(), map]);
+    }
+    args.extend(codecs);
+
+    if let Some(format) = &options.format {
+        args.extend(["-f".to_string(), format.clone()]);
+    }
+    args.push(output.to_string());
+
+    args
+}
+
+/// Tags of the video embedded as metadata
+pub(crate) fn video_tags(details: &VideoDetails) -> Vec<(&'static str, String)> {
+    let mut tags = vec![
+        ("title", details.title.clone()),
+        ("comment", details.description.clone()),
+        ("date", details.publish_date.clone()),
+        ("purl", details.video_url.clone()),
+    ];
+    if let Some(author) = &details.author {
+        tags.push(("artist", author.name.clone()));
+    }
+
+    tags.retain(|(_, x)| !x.is_empty());
+    tags
+}
+
+/// FFMETADATA file with `tags` and `chapters` of a video `length` milliseconds long
+pub(crate) fn ffmetadata(tags: &[(&str, String)], chapters: &[Chapter], length: i64) -> String {
+    let mut metadata = ";FFMETADATA1\n".to_string();
+
+    for (key, value) in tags {
+        metadata.push_str(&format!("{key}={}\n", escape(value)));
+    }
+
+    // Chapters end where the next one starts
+    for (index, chapter) in chapters.iter().enumerate() {
+        let end = chapters
+            .get(index + 1)
+            .map_or(length, |x| x.start_time as i64);
+
+        metadata.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start_time,
+            end.max(chapter.start_time as i64),
+            escape(&chapter.title)
+        ));
+    }
+
+    metadata
+}
+
+/// Escape special characters of FFMETADATA values
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(char);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ffmetadata_with_chapters() {
+        let chapters = [
+            Chapter {
+                title: "Intro".to_string(),
+                start_time: 0,
+            },
+            Chapter {
+                title: "Outro".to_string(),
+                start_time: 60000,
+            },
+        ];
+
+        assert_eq!(
+            ffmetadata(&[("title", "Intro = start; #1".to_string())], &chapters, 90000),
+            ";FFMETADATA1\ntitle=Intro \\= start\\; \\#1\n[CHAPTER]\nTIMEBASE=1/1000\nSTART=0\nEND=60000\ntitle=Intro\n[CHAPTER]\nTIMEBASE=1/1000\nSTART=60000\nEND=90000\ntitle=Outro\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn serve_input_ranges() {
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let server = InputServer::start(
+            client.clone(),
+            HashMap::from([("metadata", MergeInput::Data(b"0123456789".to_vec()))]),
+        )
+        .await
+        .unwrap();
+
+        let response = client
+            .get(server.url("metadata"))
+            .header(reqwest::header::RANGE, "bytes=4-")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[reqwest::header::CONTENT_RANGE],
+            "bytes 4-9/10"
+        );
+        assert_eq!(response.text().await.unwrap(), "456789");
+
+        let response = client.get(server.url("video")).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn cover_by_container() {
+        let options = FFmpegMergeOptions::default();
+        assert!(supports_cover(&options, Path::new("video.mp4")));
+        assert!(supports_cover(&options, Path::new("video.mkv")));
+        assert!(!supports_cover(&options, Path::new("video.WebM")));
+
+        let options = FFmpegMergeOptions {
+            format: Some("webm".to_string()),
+            ..Default::default()
+        };
+        assert!(!supports_cover(&options, Path::new("video.mp4")));
+    }
+}
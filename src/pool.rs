@@ -0,0 +1,400 @@
+use async_trait::async_trait;
+use http::Extensions;
+use reqwest::{Client, Proxy, Request, Response, StatusCode};
+use reqwest_middleware::{ClientWithMiddleware, Extension, Middleware, Next};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    constants::{DEFAULT_POOL_ADDRESSES, DEFAULT_PROXY_COOLDOWN},
+    structs::{RequestOptions, VideoError},
+    utils::{client_builder, get_random_v6_ip},
+};
+
+/// When a pool hands out a new egress
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, derive_more::Display)]
+pub enum Rotation {
+    /// Every request, including retries, goes out through the next egress
+    #[default]
+    #[display("per request")]
+    PerRequest,
+    /// Each [`crate::Video`], also within a [`crate::VideoBatch`], keeps the egress picked for its first request.
+    /// Other clients like [`crate::search::YouTube`] keep the one picked when they are built
+    #[display("per video")]
+    PerVideo,
+}
+
+/// Proxies to rotate requests over. Proxies answering `429 Too Many Requests` are skipped for a cooldown.
+///
+/// Clones share the health of the proxies.
+///
+/// # Example
+/// ```ignore
+///     let video_options = VideoOptions {
+///         request_options: RequestOptions {
+///             proxy_pool: Some(
+///                 ProxyPool::new([
+///                     reqwest::Proxy::all("http://proxy-1:8080").unwrap(),
+///                     reqwest::Proxy::all("http://proxy-2:8080").unwrap(),
+///                 ])
+///                 .rotation(Rotation::PerRequest),
+///             ),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     };
+/// ```
+#[derive(Clone, Debug, derive_more::Display)]
+#[display("ProxyPool({} proxies, rotation: {rotation}, cooldown: {cooldown:?})", proxies.len())]
+pub struct ProxyPool {
+    proxies: Arc<Vec<Proxy>>,
+    unhealthy_until: Arc<Mutex<Vec<Option<Instant>>>>,
+    next: Arc<AtomicUsize>,
+    rotation: Rotation,
+    cooldown: Duration,
+}
+
+impl ProxyPool {
+    pub fn new(proxies: impl IntoIterator<Item = Proxy>) -> Self {
+        let proxies: Vec<Proxy> = proxies.into_iter().collect();
+
+        Self {
+            unhealthy_until: Arc::new(Mutex::new(vec![None; proxies.len()])),
+            proxies: Arc::new(proxies),
+            next: Arc::new(AtomicUsize::new(0)),
+            rotation: Rotation::default(),
+            cooldown: DEFAULT_PROXY_COOLDOWN,
+        }
+    }
+
+    /// Default is [`Rotation::PerRequest`]
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// How long a rate limited proxy is skipped. Default is 60 seconds
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.proxies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.proxies.is_empty()
+    }
+
+    /// Next healthy proxy in round robin order, the one recovering first if every proxy is unhealthy
+    fn pick(&self) -> Option<usize> {
+        if self.proxies.is_empty() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let unhealthy_until = self.unhealthy_until.lock().unwrap();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        (0..self.proxies.len())
+            .map(|offset| (start + offset) % self.proxies.len())
+            .find(|index| unhealthy_until[*index].map_or(true, |until| until <= now))
+            .or_else(|| {
+                (0..self.proxies.len()).min_by_key(|index| unhealthy_until[*index].unwrap_or(now))
+            })
+    }
+
+    fn mark_unhealthy(&self, index: usize) {
+        if let Some(until) = self.unhealthy_until.lock().unwrap().get_mut(index) {
+            *until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+/// Random addresses of an IPv6 block to send requests from.
+///
+/// A fixed set of addresses is drawn from the block when a client is built, see [`AddressPool::addresses`],
+/// requests cycle through them and each video takes the next one. This keeps connections reusable.
+///
+/// # Example
+/// ```ignore
+///     let video_options = VideoOptions {
+///         request_options: RequestOptions {
+///             address_pool: Some(AddressPool::new("2001:4::/48")?),
+///             ..Default::default()
+///         },
+///         ..Default::default()
+///     };
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, derive_more::Display)]
+#[display("AddressPool({ipv6_block}, {addresses} addresses, rotation: {rotation})")]
+pub struct AddressPool {
+    ipv6_block: String,
+    rotation: Rotation,
+    addresses: usize,
+}
+
+impl AddressPool {
+    /// Fails if `ipv6_block` is not a valid IPv6 block like `2001:4::/48`
+    pub fn new(ipv6_block: impl Into<String>) -> Result<Self, VideoError> {
+        let ipv6_block = ipv6_block.into();
+        get_random_v6_ip(ipv6_block.as_str())?;
+
+        Ok(Self {
+            ipv6_block,
+            rotation: Rotation::default(),
+            addresses: DEFAULT_POOL_ADDRESSES,
+        })
+    }
+
+    /// Default is [`Rotation::PerRequest`]
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// How many addresses are drawn from the block, each keeps its own connections. Default is 16
+    pub fn addresses(mut self, addresses: usize) -> Self {
+        self.addresses = addresses.max(1);
+        self
+    }
+
+    fn pick(&self) -> Option<IpAddr> {
+        get_random_v6_ip(self.ipv6_block.as_str()).ok()
+    }
+}
+
+/// Proxy and address kept for all requests once picked
+#[derive(Clone, Debug, Default)]
+struct Egress(Arc<OnceLock<(Option<usize>, Option<IpAddr>)>>);
+
+/// Egress of one [`crate::Video`], set on its requests so a shared client keeps one per video
+#[derive(Clone, Debug, Default)]
+pub(crate) struct VideoEgress(Egress);
+
+/// `client` sending its requests with a [`VideoEgress`] of their own
+pub(crate) fn video_client(client: ClientWithMiddleware) -> ClientWithMiddleware {
+    reqwest_middleware::ClientBuilder::from_client(client)
+        .with_init(Extension(VideoEgress::default()))
+        .build()
+}
+
+/// Sends every request through a client configured with an egress of the pools
+pub struct EgressMiddleware {
+    request_options: RequestOptions,
+    proxy_pool: Option<ProxyPool>,
+    address_pool: Option<AddressPool>,
+    // Egress of requests without a video rotating per video
+    fixed: Egress,
+    // Addresses drawn from the address pool once, requests and videos cycle through them
+    addresses: Vec<IpAddr>,
+    next_address: AtomicUsize,
+    // Clients of each proxy and address, reused to keep their connections
+    clients: Mutex<HashMap<(Option<usize>, Option<IpAddr>), Client>>,
+}
+
+impl EgressMiddleware {
+    pub fn new(request_options: &RequestOptions) -> Self {
+        let proxy_pool = request_options.proxy_pool.clone();
+        let address_pool = request_options.address_pool.clone();
+
+        let request_options = request_options.clone();
+
+        let addresses = address_pool
+            .as_ref()
+            .map(|pool| (0..pool.addresses).filter_map(|_| pool.pick()).collect())
+            .unwrap_or_default();
+
+        Self {
+            request_options,
+            proxy_pool,
+            address_pool,
+            fixed: Egress::default(),
+            addresses,
+            next_address: AtomicUsize::new(0),
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn next_address(&self) -> Option<IpAddr> {
+        (!self.addresses.is_empty()).then(|| {
+            let next = self.next_address.fetch_add(1, Ordering::Relaxed);
+            self.addresses[next % self.addresses.len()]
+        })
+    }
+
+    /// Proxy and address of a request, parts rotating per video come from `video`
+    fn egress(&self, video: Option<&VideoEgress>) -> (Option<usize>, Option<IpAddr>) {
+        let per_video = |rotation| rotation == Rotation::PerVideo;
+        let proxy_per_video = self
+            .proxy_pool
+            .as_ref()
+            .is_some_and(|pool| per_video(pool.rotation));
+        let address_per_video = self
+            .address_pool
+            .as_ref()
+            .is_some_and(|pool| per_video(pool.rotation));
+
+        let kept = if proxy_per_video || address_per_video {
+            let egress = video.map_or(&self.fixed, |video| &video.0);
+            Some(*egress.0.get_or_init(|| {
+                (
+                    self.proxy_pool.as_ref().and_then(ProxyPool::pick),
+                    self.next_address(),
+                )
+            }))
+        } else {
+            None
+        };
+
+        let proxy = match kept {
+            Some((proxy, _)) if proxy_per_video => proxy,
+            _ => self.proxy_pool.as_ref().and_then(ProxyPool::pick),
+        };
+        let address = match kept {
+            Some((_, address)) if address_per_video => address,
+            _ => self.next_address(),
+        };
+
+        (proxy, address)
+    }
+
+    fn client(&self, proxy: Option<usize>, address: Option<IpAddr>) -> Result<Client, VideoError> {
+        if let Some(client) = self.clients.lock().unwrap().get(&(proxy, address)) {
+            return Ok(client.clone());
+        }
+
+        let mut builder = client_builder(&self.request_options)?;
+
+        if let (Some(pool), Some(index)) = (&self.proxy_pool, proxy) {
+            builder = builder.proxy(pool.proxies[index].clone());
+        }
+
+        if let Some(address) = address {
+            builder = builder.local_address(address);
+        }
+
+        let client = builder.build().map_err(VideoError::Reqwest)?;
+
+        self.clients
+            .lock()
+            .unwrap()
+            .insert((proxy, address), client.clone());
+
+        Ok(client)
+    }
+}
+
+#[async_trait]
+impl Middleware for EgressMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let (proxy, address) = self.egress(extensions.get::<VideoEgress>());
+
+        let client = self.client(proxy, address).map_err(|err| match err {
+            VideoError::Reqwest(err) => reqwest_middleware::Error::Reqwest(err),
+            err => reqwest_middleware::Error::middleware(err),
+        })?;
+
+        let result = client
+            .execute(req)
+            .await
+            .map_err(reqwest_middleware::Error::Reqwest);
+
+        let rate_limited = match &result {
+            Ok(response) => response.status() == StatusCode::TOO_MANY_REQUESTS,
+            Err(err) => err.is_connect(),
+        };
+        if let (true, Some(pool), Some(index)) = (rate_limited, &self.proxy_pool, proxy) {
+            pool.mark_unhealthy(index);
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unhealthy_proxies_are_skipped() {
+        let pool = ProxyPool::new([
+            Proxy::all("http://127.0.0.1:1").unwrap(),
+            Proxy::all("http://127.0.0.1:2").unwrap(),
+            Proxy::all("http://127.0.0.1:3").unwrap(),
+        ]);
+
+        assert_eq!(
+            (0..4).map(|_| pool.pick().unwrap()).collect::<Vec<_>>(),
+            vec![0, 1, 2, 0]
+        );
+
+        pool.mark_unhealthy(1);
+        assert!((0..6).all(|_| pool.pick() != Some(1)));
+
+        pool.mark_unhealthy(0);
+        pool.mark_unhealthy(2);
+        // Everything is rate limited, the proxy which failed first recovers first
+        assert_eq!(pool.pick(), Some(1));
+
+        let recovering = pool.clone().cooldown(Duration::ZERO);
+        recovering.mark_unhealthy(0);
+        assert_eq!(pool.pick(), Some(0));
+    }
+
+    #[test]
+    fn test_egress_per_video() {
+        let middleware = EgressMiddleware::new(&RequestOptions {
+            proxy_pool: Some(
+                ProxyPool::new([
+                    Proxy::all("http://127.0.0.1:1").unwrap(),
+                    Proxy::all("http://127.0.0.1:2").unwrap(),
+                ])
+                .rotation(Rotation::PerVideo),
+            ),
+            ..Default::default()
+        });
+
+        let (first, second) = (VideoEgress::default(), VideoEgress::default());
+        let picked = middleware.egress(Some(&first));
+        assert_ne!(picked, middleware.egress(Some(&second)));
+        assert!((0..4).all(|_| middleware.egress(Some(&first)) == picked));
+        // Clones are requests of the same video
+        assert_eq!(middleware.egress(Some(&first.clone())), picked);
+    }
+
+    #[test]
+    fn test_rotating_addresses_reuse_clients() {
+        let middleware = EgressMiddleware::new(&RequestOptions {
+            address_pool: Some(AddressPool::new("2001:4::/48").unwrap().addresses(3)),
+            ..Default::default()
+        });
+
+        let addresses = middleware.addresses.clone();
+        assert_eq!(addresses.len(), 3);
+
+        for _ in 0..7 {
+            let (proxy, address) = middleware.egress(None);
+            middleware.client(proxy, address).unwrap();
+        }
+
+        let clients = middleware.clients.lock().unwrap();
+        assert_eq!(clients.len(), 3);
+        assert!(addresses
+            .iter()
+            .all(|address| clients.contains_key(&(None, Some(*address)))));
+    }
+}
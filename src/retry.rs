@@ -0,0 +1,274 @@
+use async_trait::async_trait;
+use http::Extensions;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next};
+use reqwest_retry::{default_on_request_failure, Retryable};
+use std::time::{Duration, SystemTime};
+
+use crate::structs::RetryPolicy;
+
+/// Retries web requests as described by a [`RetryPolicy`]
+pub struct RetryMiddleware {
+    policy: RetryPolicy,
+    max_retries: u32,
+}
+
+impl RetryMiddleware {
+    pub fn new(policy: RetryPolicy, max_retries: u32) -> Self {
+        Self {
+            policy,
+            max_retries,
+        }
+    }
+
+    /// Time to wait before retrying after `result`, `None` if it shouldn't be retried
+    fn retry_delay(
+        &self,
+        result: &reqwest_middleware::Result<Response>,
+        retry: u32,
+    ) -> Option<Duration> {
+        let response = match result {
+            Ok(response) => response,
+            Err(err) => {
+                return match default_on_request_failure(err) {
+                    Some(Retryable::Transient) => {
+                        Some(self.backoff(retry, self.policy.min_retry_interval))
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        let status = response.status();
+        let retryable = match &self.policy.retryable_statuses {
+            Some(statuses) => statuses.contains(&status.as_u16()),
+            None => {
+                matches!(
+                    status,
+                    StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+                ) || status.is_server_error()
+            }
+        };
+        if !retryable {
+            return None;
+        }
+
+        if self.policy.respect_retry_after {
+            if let Some(retry_after) = get_retry_after(response) {
+                return (retry_after <= self.policy.max_retry_after).then_some(retry_after);
+            }
+        }
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Some(
+                self.backoff(
+                    retry,
+                    self.policy
+                        .rate_limit_retry_interval
+                        .max(self.policy.min_retry_interval),
+                ),
+            );
+        }
+
+        Some(self.backoff(retry, self.policy.min_retry_interval))
+    }
+
+    fn backoff(&self, retry: u32, min_interval: Duration) -> Duration {
+        let max_interval = self.policy.max_retry_interval.max(min_interval);
+        let interval = min_interval
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(max_interval);
+
+        if self.policy.jitter && interval > min_interval {
+            rand::thread_rng().gen_range(min_interval..=interval)
+        } else {
+            interval
+        }
+    }
+}
+
+/// `Retry-After` in delay-seconds or HTTP-date form, dates in the past ask for no delay
+fn get_retry_after(response: &Response) -> Option<Duration> {
+    let retry_after = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = retry_after.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(retry_after).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+#[async_trait]
+impl Middleware for RetryMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let mut retry = 0;
+
+        loop {
+            // Streaming bodies can't be sent twice
+            let mut request = match req.try_clone() {
+                Some(request) => request,
+                None => return next.run(req, extensions).await,
+            };
+
+            if let Some(timeout) = self.policy.request_timeout {
+                *request.timeout_mut() = Some(timeout);
+            }
+
+            let result = next.clone().run(request, extensions).await;
+
+            if retry >= self.max_retries {
+                return result;
+            }
+
+            match self.retry_delay(&result, retry) {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return result,
+            }
+
+            retry += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server;
+    use reqwest_middleware::ClientBuilder;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    /// Serve `responses` one per connection, then `200 OK`
+    async fn serve(responses: Vec<&'static str>) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+
+        let counter = hits.clone();
+        let url = test_server::serve(move |_| {
+            let hit = counter.fetch_add(1, Ordering::SeqCst);
+            test_server::Response::new(responses.get(hit).copied().unwrap_or("200 OK"), "")
+        })
+        .await;
+
+        (url, hits)
+    }
+
+    fn client(policy: RetryPolicy, max_retries: u32) -> reqwest_middleware::ClientWithMiddleware {
+        ClientBuilder::new(reqwest::Client::new())
+            .with(RetryMiddleware::new(policy, max_retries))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_is_respected() {
+        let (url, hits) = serve(vec!["429 Too Many Requests\r\nRetry-After: 1"]).await;
+        let policy = RetryPolicy {
+            min_retry_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let instant = Instant::now();
+        let response = client(policy, 3).get(url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert!(instant.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_retryable_statuses() {
+        let (url, hits) = serve(vec!["403 Forbidden", "404 Not Found"]).await;
+        let policy = RetryPolicy {
+            min_retry_interval: Duration::from_millis(1),
+            retryable_statuses: Some(vec![403]),
+            ..Default::default()
+        };
+
+        let response = client(policy, 3).get(url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried_by_default() {
+        let (url, hits) = serve(vec!["403 Forbidden", "404 Not Found"]).await;
+        let policy = RetryPolicy {
+            min_retry_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+
+        let response = client(policy, 3).get(url).send().await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_after_forms() {
+        let response = |retry_after: String| {
+            Response::from(
+                http::Response::builder()
+                    .header(RETRY_AFTER, retry_after)
+                    .body("")
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(
+            get_retry_after(&response("120".to_string())),
+            Some(Duration::from_secs(120))
+        );
+
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(60));
+        let delay = get_retry_after(&response(date)).unwrap();
+        assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+
+        assert_eq!(
+            get_retry_after(&response("Wed, 21 Oct 2015 07:28:00 GMT".to_string())),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(get_retry_after(&response("soon".to_string())), None);
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let middleware = RetryMiddleware::new(
+            RetryPolicy {
+                min_retry_interval: Duration::from_secs(1),
+                max_retry_interval: Duration::from_secs(5),
+                jitter: false,
+                ..Default::default()
+            },
+            10,
+        );
+
+        assert_eq!(
+            middleware.backoff(0, Duration::from_secs(1)),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            middleware.backoff(2, Duration::from_secs(1)),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            middleware.backoff(20, Duration::from_secs(1)),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            middleware.backoff(0, Duration::from_secs(10)),
+            Duration::from_secs(10)
+        );
+    }
+}
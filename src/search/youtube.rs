@@ -11,7 +11,7 @@ pub use crate::structs::RequestOptions;
 use crate::{
     constants::DEFAULT_HEADERS,
     structs::VideoError,
-    utils::{get_html, get_random_v6_ip, time_to_ms},
+    utils::{get_html, get_random_v6_ip, retry_client, time_to_ms},
     Thumbnail,
 };
 
@@ -42,7 +42,7 @@ impl YouTube {
             .build()
             .map_err(VideoError::Reqwest)?;
 
-        let client = reqwest_middleware::ClientBuilder::new(client).build();
+        let client = retry_client(client, &RequestOptions::default());
 
         Ok(Self {
             client,
@@ -59,16 +59,21 @@ impl YouTube {
 
             // Assign request options to client
 
-            if let Some(proxy) = request_options.proxy.as_ref() {
+            // Pools take precedence, their egress is set per request
+            if let (Some(proxy), None) = (&request_options.proxy, &request_options.proxy_pool) {
                 client = client.proxy(proxy.clone());
             }
 
-            if let Some(ipv6_block) = request_options.ipv6_block.as_ref() {
+            if let (Some(ipv6_block), None) =
+                (&request_options.ipv6_block, &request_options.address_pool)
+            {
                 let ipv6 = get_random_v6_ip(ipv6_block)?;
                 client = client.local_address(ipv6);
             }
 
-            if let Some(cookie) = request_options.cookies.as_ref() {
+            if let Some(cookie_jar) = request_options.cookie_jar.as_ref() {
+                client = client.cookie_provider(Arc::new(cookie_jar.clone()));
+            } else if let Some(cookie) = request_options.cookies.as_ref() {
                 let host = "https://youtube.com".parse::<url::Url>().unwrap();
 
                 let jar = reqwest::cookie::Jar::default();
@@ -80,7 +85,7 @@ impl YouTube {
             client.build().map_err(VideoError::Reqwest)?
         };
 
-        let client = reqwest_middleware::ClientBuilder::new(client).build();
+        let client = retry_client(client, request_options);
 
         Ok(Self {
             client,
@@ -537,7 +542,13 @@ impl Playlist {
             client = client.local_address(ipv6);
         }
 
-        if options
+        if let Some(cookie_jar) = options
+            .request_options
+            .as_ref()
+            .and_then(|x| x.cookie_jar.as_ref())
+        {
+            client = client.cookie_provider(Arc::new(cookie_jar.clone()));
+        } else if options
             .request_options
             .as_ref()
             .map(|x| x.cookies.is_some())
@@ -559,7 +570,14 @@ impl Playlist {
         }
 
         let client = client.build().map_err(VideoError::Reqwest)?;
-        let client = reqwest_middleware::ClientBuilder::new(client).build();
+        let default_request_options = RequestOptions::default();
+        let client = retry_client(
+            client,
+            options
+                .request_options
+                .as_ref()
+                .unwrap_or(&default_request_options),
+        );
 
         let html_first = get_html(
             &client,
@@ -1255,7 +1273,7 @@ fn get_client_version(html: impl Into<String>) -> String {
         .split(r#""INNERTUBE_CONTEXT_CLIENT_VERSION":""#)
         .collect::<Vec<&str>>();
 
-    return match first_collect_for_client_version.get(1) {
+    match first_collect_for_client_version.get(1) {
         Some(x) => {
             let second_collect = x.split('"').collect::<Vec<&str>>();
             if !second_collect.is_empty() {
@@ -1302,7 +1320,7 @@ fn get_client_version(html: impl Into<String>) -> String {
                 None => DEFAULT_CLIENT_VERSOIN.to_string(),
             }
         }
-    };
+    }
 }
 
 fn get_api_key(html: impl Into<String>) -> String {
@@ -1312,7 +1330,7 @@ fn get_api_key(html: impl Into<String>) -> String {
         .split(r#""INNERTUBE_API_KEY":""#)
         .collect::<Vec<&str>>();
 
-    return match first_collect.get(1) {
+    match first_collect.get(1) {
         Some(x) => {
             let second_collect = x.split('"').collect::<Vec<&str>>();
             if !second_collect.is_empty() {
@@ -1355,7 +1373,7 @@ fn get_api_key(html: impl Into<String>) -> String {
                 None => DEFAULT_INNERTUBE_KEY.to_string(),
             }
         }
-    };
+    }
 }
 
 async fn make_request(
@@ -1393,11 +1411,8 @@ async fn make_request(
 
     let original_url = &request_options.original_url;
     let query = &request_options.query;
-    let filter = if request_options.filter.is_some() {
-        format!(
-            r#""params": "{}","#,
-            request_options.filter.as_ref().unwrap()
-        )
+    let filter = if let Some(filter) = &request_options.filter {
+        format!(r#""params": "{}","#, filter)
     } else {
         "".to_string()
     };
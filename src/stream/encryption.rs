@@ -3,7 +3,10 @@ use aes::cipher::{BlockDecryptMut, KeyIvInit};
 
 use m3u8_rs::Key;
 use reqwest::Url;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::RwLock;
 
+use super::sample_aes;
 use crate::utils::make_absolute_url;
 use crate::VideoError;
 
@@ -11,21 +14,54 @@ type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
 
 /// HLS encryption methods
 #[allow(dead_code)]
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Encryption {
     None,
     Aes128 { key_uri: Url, iv: [u8; 16] },
-    SampleAes,
+    SampleAes { key_uri: Url, iv: [u8; 16] },
+}
+
+/// Keys by URI, shared by the segments of a stream so each key is fetched once
+#[derive(Clone, Debug, Default)]
+pub struct KeyCache(Arc<RwLock<HashMap<Url, [u8; 16]>>>);
+
+impl KeyCache {
+    /// Key at `uri`, fetched on first use
+    pub async fn get(
+        &self,
+        client: &reqwest_middleware::ClientWithMiddleware,
+        uri: &Url,
+    ) -> Result<[u8; 16], VideoError> {
+        if let Some(key) = self.0.read().await.get(uri) {
+            return Ok(*key);
+        }
+
+        let body = client
+            .get(uri.clone())
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let key: [u8; 16] = body
+            .get(..16)
+            .and_then(|x| x.try_into().ok())
+            .ok_or_else(|| VideoError::DecryptionError("Invalid key length".to_string()))?;
+
+        self.0.write().await.insert(uri.clone(), key);
+
+        Ok(key)
+    }
 }
 
 impl Encryption {
     /// Check m3u8_key and return encryption.
     ///
-    /// If encrypted, will make a query to the designated url to fetch the key
+    /// Keys are fetched once decrypting, see [`KeyCache`]
     pub async fn new(m3u8_key: &Key, base_url: &str, seq: u64) -> Result<Self, VideoError> {
         let encryption = match &m3u8_key {
             k if k.method.to_string() == *"NONE" => Self::None,
-            k if k.method.to_string() == *"AES-128" => {
+            k if k.method.to_string() == *"AES-128" || k.method.to_string() == *"SAMPLE-AES" => {
                 if let Some(uri) = &k.uri {
                     // Bail if keyformat exists but is not "identity"
                     if let Some(keyformat) = &k.keyformat {
@@ -53,20 +89,19 @@ impl Encryption {
                             .copy_from_slice(&seq.to_be_bytes());
                     }
 
-                    Self::Aes128 { key_uri: uri, iv }
+                    if k.method.to_string() == *"SAMPLE-AES" {
+                        Self::SampleAes { key_uri: uri, iv }
+                    } else {
+                        Self::Aes128 { key_uri: uri, iv }
+                    }
                 } else {
                     // Bail if no uri is found
-                    return Err(VideoError::EncryptionError(
-                        "No URI found for AES-128 key".to_string(),
-                    ));
+                    return Err(VideoError::EncryptionError(format!(
+                        "No URI found for {} key",
+                        k.method
+                    )));
                 }
             }
-            k if k.method.to_string() == *"SAMPLE-AES" => {
-                return Err(VideoError::EncryptionError(format!(
-                    "Unimplemented encryption method: {}",
-                    k.method
-                )))
-            }
             k => {
                 return Err(VideoError::EncryptionError(format!(
                     "Invalid encryption method: {}",
@@ -78,23 +113,26 @@ impl Encryption {
         Ok(encryption)
     }
 
-    /// Decrypt the given data
+    /// Decrypt the given data, `init` is the initialization segment of fragmented MP4 segments
     pub async fn decrypt(
         &self,
         client: &reqwest_middleware::ClientWithMiddleware,
+        keys: &KeyCache,
         data: &[u8],
+        init: Option<&[u8]>,
     ) -> Result<Vec<u8>, VideoError> {
         let r = match self {
             Self::None => Vec::from(data),
             Self::Aes128 { key_uri, iv } => {
-                let body = client.get(key_uri.clone()).send().await?.bytes().await?;
-                let mut key = [0_u8; 16];
-                key.copy_from_slice(&body[..16]);
+                let key = keys.get(client, key_uri).await?;
                 Aes128CbcDec::new(&key.into(), iv.into())
                     .decrypt_padded_vec_mut::<Pkcs7>(data)
                     .map_err(|e| VideoError::DecryptionError(e.to_string()))?
             }
-            Self::SampleAes => unimplemented!(),
+            Self::SampleAes { key_uri, iv } => {
+                let key = keys.get(client, key_uri).await?;
+                sample_aes::decrypt(data, &key, iv, init)?
+            }
         };
 
         Ok(r)
@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use m3u8_rs::parse_media_playlist;
+
+use crate::structs::VideoError;
+
+/// Name of the playlist written next to the segments
+pub(crate) const LOCAL_PLAYLIST: &str = "index.m3u8";
+
+/// Segment written to disk, URIs are relative to the playlist
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct LocalSegment {
+    pub uri: String,
+    pub duration: Duration,
+    pub discontinuity: bool,
+    pub initialization: Option<String>,
+}
+
+/// Media playlist of segments recorded to a directory
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LocalPlaylist {
+    path: PathBuf,
+    pub segments: Vec<LocalSegment>,
+    /// Segments, target duration and end of the playlist on disk, new segments are appended to it
+    written: usize,
+    target_duration: u64,
+    ended: bool,
+}
+
+impl LocalPlaylist {
+    /// Playlist of `dir`, with the segments of a previous recording if there is one
+    pub fn open(dir: &Path) -> Result<Self, VideoError> {
+        let path = dir.join(LOCAL_PLAYLIST);
+        let mut playlist = Self {
+            path,
+            ..Default::default()
+        };
+
+        let Ok(content) = std::fs::read(&playlist.path) else {
+            return Ok(playlist);
+        };
+
+        let media_playlist = parse_media_playlist(&content)
+            .map_err(|e| VideoError::M3U8ParseError(e.to_string()))?
+            .1;
+        playlist.target_duration = media_playlist.target_duration;
+        playlist.ended = media_playlist.end_list;
+
+        // EXT-X-MAP applies until the next one
+        let mut initialization = None;
+        for segment in media_playlist.segments {
+            if let Some(map) = segment.map {
+                initialization = Some(map.uri);
+            }
+
+            playlist.segments.push(LocalSegment {
+                uri: segment.uri,
+                duration: Duration::from_secs_f32(segment.duration.max(0.0)),
+                discontinuity: segment.discontinuity,
+                initialization: initialization.clone(),
+            });
+        }
+        playlist.written = playlist.segments.len();
+
+        Ok(playlist)
+    }
+
+    pub fn contains(&self, uri: &str) -> bool {
+        self.segments.iter().any(|x| x.uri == uri)
+    }
+
+    /// Write the playlist, `ended` closes it with `EXT-X-ENDLIST`.
+    ///
+    /// Segments added since the last save are appended, the whole playlist is only rewritten when
+    /// the target duration grows or the playlist on disk was already closed
+    pub fn save(&mut self, ended: bool) -> Result<(), VideoError> {
+        let target_duration = self
+            .segments
+            .iter()
+            .map(|x| x.duration.as_secs_f64().ceil() as u64)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+
+        if self.written == 0 || self.ended || target_duration != self.target_duration {
+            write_file(&self.path, self.render(target_duration, ended).as_bytes())?;
+        } else {
+            let mut content = String::new();
+            for pair in self.segments[self.written - 1..].windows(2) {
+                push_segment(&mut content, Some(&pair[0]), &pair[1]);
+            }
+            if ended {
+                content.push_str("#EXT-X-ENDLIST\n");
+            }
+
+            append_file(&self.path, content.as_bytes())?;
+        }
+
+        self.written = self.segments.len();
+        self.target_duration = target_duration;
+        self.ended = ended;
+
+        Ok(())
+    }
+
+    fn render(&self, target_duration: u64, ended: bool) -> String {
+        let mut content = format!(
+            "#EXTM3U\n#EXT-X-VERSION:7\n#EXT-X-TARGETDURATION:{target_duration}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:EVENT\n"
+        );
+
+        let mut previous = None;
+        for segment in &self.segments {
+            push_segment(&mut content, previous, segment);
+            previous = Some(segment);
+        }
+
+        if ended {
+            content.push_str("#EXT-X-ENDLIST\n");
+        }
+
+        content
+    }
+}
+
+/// Playlist entry of `segment`, following `previous`
+fn push_segment(content: &mut String, previous: Option<&LocalSegment>, segment: &LocalSegment) {
+    if segment.discontinuity {
+        content.push_str("#EXT-X-DISCONTINUITY\n");
+    }
+
+    // Repeat the map after a discontinuity, players reset their state there
+    if let Some(uri) = &segment.initialization {
+        if segment.discontinuity
+            || previous.map_or(true, |x| x.initialization.as_ref() != Some(uri))
+        {
+            content.push_str(&format!("#EXT-X-MAP:URI=\"{uri}\"\n"));
+        }
+    }
+
+    content.push_str(&format!(
+        "#EXTINF:{:.3},\n{}\n",
+        segment.duration.as_secs_f64(),
+        segment.uri
+    ));
+}
+
+/// Extensions of recorded segments, see [`MediaFormat::extension`](super::media_format::MediaFormat::extension)
+const SEGMENT_EXTENSIONS: [&str; 9] = [
+    "ts", "mp4", "webm", "m4a", "aac", "mp3", "ac3", "eac3", "vtt",
+];
+
+/// Segment files of `dir` by segment id, see [`Segment::id`](super::segment::Segment::id).
+///
+/// Partial files left by an interrupted recording are removed, their segments are downloaded again
+pub(crate) fn segment_files(dir: &Path) -> HashMap<String, String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter_map(|name| {
+            let (id, extension) = name.rsplit_once('.')?;
+            if extension == PARTIAL_EXTENSION {
+                let _ = std::fs::remove_file(dir.join(&name));
+                return None;
+            }
+
+            if !SEGMENT_EXTENSIONS.contains(&extension) {
+                return None;
+            }
+            parse_id(id)?;
+            Some((id.to_string(), name))
+        })
+        .collect()
+}
+
+/// Discontinuity and media sequence of a segment id
+pub(crate) fn parse_id(id: &str) -> Option<(u64, u64)> {
+    let (discon_seq, seq) = id.strip_prefix('d')?.split_once('s')?;
+
+    Some((discon_seq.parse().ok()?, seq.parse().ok()?))
+}
+
+/// Extension of files being written by [`write_file`]
+const PARTIAL_EXTENSION: &str = "part";
+
+/// Write `data` to `path` through a temporary file, a file on disk is always complete
+pub(crate) fn write_file(path: &Path, data: &[u8]) -> Result<(), VideoError> {
+    let map_err = |err: std::io::Error| VideoError::DownloadError(err.to_string());
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(format!(".{PARTIAL_EXTENSION}"));
+
+    std::fs::write(&temp_path, data).map_err(map_err)?;
+    std::fs::rename(&temp_path, path).map_err(map_err)
+}
+
+/// Append `data` to the file at `path` in a single write
+fn append_file(path: &Path, data: &[u8]) -> Result<(), VideoError> {
+    use std::io::Write;
+
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|err| VideoError::DownloadError(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_segment_ids() {
+        assert_eq!(parse_id("d0000000002s0000000015"), Some((2, 15)));
+        assert_eq!(parse_id("init-d0000000002s0000000015"), None);
+        assert_eq!(parse_id("index"), None);
+    }
+
+    #[test]
+    fn skip_partial_segment_files() {
+        let dir = std::env::temp_dir().join(format!("rusty_ytdl_partial_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in [
+            "d0000000000s0000000000.ts",
+            "d0000000000s0000000001.ts.part",
+            "d0000000000s0000000002.txt",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let files = segment_files(&dir);
+        assert_eq!(
+            files,
+            HashMap::from([(
+                "d0000000000s0000000000".to_string(),
+                "d0000000000s0000000000.ts".to_string()
+            )])
+        );
+        assert!(!dir.join("d0000000000s0000000001.ts.part").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn save_and_open() {
+        let dir = std::env::temp_dir().join(format!("rusty_ytdl_playlist_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut playlist = LocalPlaylist::open(&dir).unwrap();
+        let init = Some("init-d0000000000s0000000000.mp4".to_string());
+        playlist.segments = vec![
+            LocalSegment {
+                uri: "d0000000000s0000000000.mp4".to_string(),
+                duration: Duration::from_millis(4000),
+                discontinuity: false,
+                initialization: init.clone(),
+            },
+            LocalSegment {
+                uri: "d0000000000s0000000001.mp4".to_string(),
+                duration: Duration::from_millis(4500),
+                discontinuity: false,
+                initialization: init.clone(),
+            },
+            LocalSegment {
+                uri: "d0000000001s0000000002.mp4".to_string(),
+                duration: Duration::from_millis(4000),
+                discontinuity: true,
+                initialization: init,
+            },
+        ];
+        playlist.save(true).unwrap();
+
+        let content = std::fs::read_to_string(dir.join(LOCAL_PLAYLIST)).unwrap();
+        assert!(content.contains("#EXT-X-TARGETDURATION:5\n"));
+        assert_eq!(content.matches("#EXT-X-MAP").count(), 2);
+        assert!(content.ends_with("#EXT-X-ENDLIST\n"));
+
+        assert_eq!(
+            LocalPlaylist::open(&dir).unwrap().segments,
+            playlist.segments
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn save_appends_segments() {
+        let dir = std::env::temp_dir().join(format!("rusty_ytdl_append_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOCAL_PLAYLIST);
+
+        let segment = |uri: &str, millis, discontinuity| LocalSegment {
+            uri: uri.to_string(),
+            duration: Duration::from_millis(millis),
+            discontinuity,
+            initialization: Some("init-d0000000000s0000000000.mp4".to_string()),
+        };
+
+        let mut playlist = LocalPlaylist::open(&dir).unwrap();
+        playlist
+            .segments
+            .push(segment("d0000000000s0000000000.mp4", 4000, false));
+        playlist.save(false).unwrap();
+
+        playlist
+            .segments
+            .push(segment("d0000000000s0000000001.mp4", 3500, false));
+        playlist
+            .segments
+            .push(segment("d0000000001s0000000002.mp4", 4000, true));
+        playlist.save(false).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            playlist.render(4, false)
+        );
+
+        // A longer segment raises the target duration of the whole playlist
+        playlist
+            .segments
+            .push(segment("d0000000001s0000000003.mp4", 6000, false));
+        playlist.save(true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            playlist.render(6, true)
+        );
+
+        // Resuming a closed playlist rewrites it without the end
+        let mut resumed = LocalPlaylist::open(&dir).unwrap();
+        assert_eq!(resumed.segments, playlist.segments);
+        resumed
+            .segments
+            .push(segment("d0000000001s0000000004.mp4", 4000, false));
+        resumed.save(false).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            resumed.render(6, false)
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,18 +1,10 @@
-#![allow(dead_code)]
-use std::process::Stdio;
-
-use serde::Deserialize;
-use tokio::{io::AsyncWriteExt, process};
-
-use crate::VideoError;
-
 #[non_exhaustive]
-#[allow(dead_code)]
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
 pub enum MediaFormat {
     // Containers
     MpegTs, // MPEG-2 transport stream
     FMp4,   // Fragmented MPEG-4
+    WebM,   // WebM
 
     // Audio
     Aac,  // Advanced audio coding
@@ -28,70 +20,42 @@ pub enum MediaFormat {
     Unknown,
 }
 
-impl MediaFormat {
-    pub async fn detect(data: Vec<u8>) -> Result<Self, VideoError> {
-        #[derive(Deserialize)]
-        struct FFProbeOuput {
-            format: FFProbeFormat,
-        }
-        #[derive(Deserialize)]
-        struct FFProbeFormat {
-            format_name: String,
-        }
-
-        // Call ffprobe to check format
-        let mut cmd = process::Command::new("ffprobe");
-        cmd.arg("-loglevel")
-            .arg("quiet")
-            .arg("-show_entries")
-            .arg("format=format_name")
-            .arg("-print_format")
-            .arg("json")
-            .arg("-")
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .kill_on_drop(true);
-
-        let mut child = cmd
-            .spawn()
-            .map_err(|e| VideoError::ChildProcessError(e.to_string()))?;
-
-        let mut stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| VideoError::ChildProcessError("Can't open ffprobe stdin".to_string()))?;
-
-        // Write to ffprobe stdin
-        tokio::spawn(async move { stdin.write_all(&data).await });
-
-        // Run ffprobe
-        let output = child
-            .wait_with_output()
-            .await
-            .map_err(|e| VideoError::ChildProcessError(e.to_string()))?;
-        let utf8_output = String::from_utf8(output.stdout)
-            .map_err(|e| VideoError::ChildProcessError(e.to_string()))?;
-
-        // Check ffprobe exit status
-        if !output.status.success() {
-            return Ok(Self::Unknown);
-        }
-
-        // Parse ffprobe output
-        let parsed: Result<FFProbeOuput, _> = serde_json::from_str(&utf8_output);
-        match parsed {
-            Err(_e) => Ok(Self::Unknown),
-            Ok(o) => {
-                let format = match o.format.format_name.as_str().trim() {
-                    "mpegts" => Self::MpegTs,
-                    "mp3" => Self::Mp3,
-                    "mov,mp4,m4a,3gp,3g2,mj2" => Self::FMp4,
-                    "webvtt" => Self::WebVtt,
-                    _ => Self::Unknown,
-                };
+/// Top level ISO-BMFF boxes a segment may start with
+const ISO_BMFF_BOXES: [&[u8; 4]; 7] = [
+    b"ftyp", b"styp", b"moof", b"moov", b"sidx", b"emsg", b"prft",
+];
 
-                Ok(format)
+impl MediaFormat {
+    /// Detect the format of a segment from its first bytes
+    pub fn detect(data: &[u8]) -> Self {
+        match data {
+            [0x47, ..] if data.get(188).map_or(true, |x| *x == 0x47) => Self::MpegTs,
+            [_, _, _, _, a, b, c, d, ..] if ISO_BMFF_BOXES.contains(&&[*a, *b, *c, *d]) => {
+                Self::FMp4
             }
+            [0x1a, 0x45, 0xdf, 0xa3, ..] => Self::WebM,
+            [0xef, 0xbb, 0xbf, rest @ ..] | rest if rest.starts_with(b"WEBVTT") => Self::WebVtt,
+            // Packed audio starts with an ID3 tag carrying its timestamp
+            [b'I', b'D', b'3', _, _, flags, a, b, c, d, ..] => {
+                let size = [a, b, c, d]
+                    .iter()
+                    .fold(0, |size, x| (size << 7) | (**x as usize & 0x7f));
+                let footer = if flags & 0x10 != 0 { 10 } else { 0 };
+
+                match Self::detect(data.get(10 + size + footer..).unwrap_or_default()) {
+                    Self::Unknown => Self::Mp3,
+                    format => format,
+                }
+            }
+            [0x0b, 0x77, _, _, _, bsid, ..] => match bsid >> 3 {
+                0..=10 => Self::Ac3,
+                11..=16 => Self::EAc3,
+                _ => Self::Unknown,
+            },
+            // Frame syncs, ADTS has layer 0 where MP3 has layer 3, 2 or 1
+            [0xff, b, ..] if b & 0xf6 == 0xf0 => Self::Adts,
+            [0xff, b, c, ..] if b & 0xe0 == 0xe0 && b & 0x06 != 0 && c >> 4 != 0x0f => Self::Mp3,
+            _ => Self::Unknown,
         }
     }
 
@@ -99,6 +63,7 @@ impl MediaFormat {
         match self {
             Self::MpegTs => "ts",
             Self::FMp4 => "mp4",
+            Self::WebM => "webm",
             Self::Aac => "m4a",
             Self::Adts => "aac",
             Self::Mp3 => "mp3",
@@ -112,3 +77,63 @@ impl MediaFormat {
         .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_formats() {
+        let mut ts = vec![0_u8; 376];
+        ts[0] = 0x47;
+        ts[188] = 0x47;
+        assert_eq!(MediaFormat::detect(&ts), MediaFormat::MpegTs);
+        ts[188] = 0;
+        assert_eq!(MediaFormat::detect(&ts), MediaFormat::Unknown);
+
+        assert_eq!(MediaFormat::detect(b"\0\0\0\x18styp"), MediaFormat::FMp4);
+        assert_eq!(MediaFormat::detect(b"\0\0\0\x18moof"), MediaFormat::FMp4);
+        assert_eq!(
+            MediaFormat::detect(&[0x1a, 0x45, 0xdf, 0xa3, 0x9f]),
+            MediaFormat::WebM
+        );
+        assert_eq!(
+            MediaFormat::detect(b"WEBVTT\n\n00:00.000 --> 00:01.000"),
+            MediaFormat::WebVtt
+        );
+        assert_eq!(
+            MediaFormat::detect(b"\xef\xbb\xbfWEBVTT\n"),
+            MediaFormat::WebVtt
+        );
+        assert_eq!(
+            MediaFormat::detect(&[0xff, 0xf1, 0x50, 0x80]),
+            MediaFormat::Adts
+        );
+        assert_eq!(
+            MediaFormat::detect(&[0xff, 0xfb, 0x90, 0x64]),
+            MediaFormat::Mp3
+        );
+        assert_eq!(
+            MediaFormat::detect(&[0x0b, 0x77, 0, 0, 0x14, 0x40]),
+            MediaFormat::Ac3
+        );
+        assert_eq!(
+            MediaFormat::detect(&[0x0b, 0x77, 0, 0, 0x14, 0x80]),
+            MediaFormat::EAc3
+        );
+        assert_eq!(MediaFormat::detect(b"<html>"), MediaFormat::Unknown);
+    }
+
+    #[test]
+    fn detect_behind_id3() {
+        let id3 = [b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, 2, 0xaa, 0xbb];
+
+        let adts = [&id3[..], &[0xff, 0xf1, 0x50, 0x80]].concat();
+        assert_eq!(MediaFormat::detect(&adts), MediaFormat::Adts);
+
+        let ac3 = [&id3[..], &[0x0b, 0x77, 0, 0, 0x14, 0x40]].concat();
+        assert_eq!(MediaFormat::detect(&ac3), MediaFormat::Ac3);
+
+        assert_eq!(MediaFormat::detect(&id3), MediaFormat::Mp3);
+    }
+}
@@ -1,13 +1,24 @@
 mod encryption;
 mod hashable_byte_range;
 mod remote_data;
+mod sample_aes;
 mod streams;
 
+#[cfg(feature = "live")]
+mod local_playlist;
 #[cfg(feature = "live")]
 mod media_format;
 #[cfg(feature = "live")]
 mod segment;
 
 #[cfg(feature = "live")]
-pub use streams::{LiveStream, LiveStreamOptions};
-pub use streams::{NonLiveStream, NonLiveStreamOptions, Stream};
+pub use media_format::MediaFormat;
+#[cfg(feature = "live")]
+pub use streams::{
+    DashLiveStream, DashLiveStreamOptions, LiveFromStart, LiveGap, LiveStream, LiveStreamOptions,
+    VariantSelection,
+};
+pub use streams::{LinkRefresher, NonLiveStream, NonLiveStreamOptions, Stream};
+
+#[cfg(feature = "ffmpeg")]
+pub(crate) use streams::{read_ffmpeg_stderr, wait_ffmpeg};
@@ -1,9 +1,10 @@
 #![allow(dead_code)]
 
 use m3u8_rs::ByteRange;
-use reqwest::header::{self, HeaderMap};
+use reqwest::header;
 
 use super::hashable_byte_range::HashableByteRange;
+use crate::constants::DEFAULT_HEADERS;
 use crate::VideoError;
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -32,7 +33,7 @@ impl RemoteData {
         client: &reqwest_middleware::ClientWithMiddleware,
     ) -> Result<(Vec<u8>, url::Url), VideoError> {
         // Add byte range headers if needed
-        let mut header_map = HeaderMap::new();
+        let mut header_map = DEFAULT_HEADERS.clone();
         if let Some(ref range) = self.byte_range_string() {
             header_map.insert(
                 header::RANGE,
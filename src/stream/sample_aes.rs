@@ -0,0 +1,1154 @@
+//! SAMPLE-AES decryption of MPEG-TS (H.264, AAC) and fragmented MP4 (`cbcs`) segments.
+//!
+//! SAMPLE-AES only encrypts parts of the media samples, the container is left in clear so the
+//! samples have to be located before they can be decrypted.
+
+use aes::cipher::{generic_array::GenericArray, BlockDecryptMut, KeyIvInit};
+use std::collections::HashMap;
+
+use crate::VideoError;
+
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+/// Adaptation field filling a packet after its length byte
+const TS_MAX_ADAPTATION_LENGTH: usize = 183;
+
+const STREAM_TYPE_AAC: u8 = 0x0f;
+const STREAM_TYPE_H264: u8 = 0x1b;
+const STREAM_TYPE_SAMPLE_AES_AAC: u8 = 0xcf;
+const STREAM_TYPE_SAMPLE_AES_H264: u8 = 0xdb;
+const STREAM_TYPE_SAMPLE_AES_AC3: u8 = 0xc1;
+const STREAM_TYPE_SAMPLE_AES_EAC3: u8 = 0xc2;
+
+/// Shortest section lengths with a complete header and CRC, and a program map with its info length
+const PAT_MIN_SECTION_LENGTH: usize = 9;
+const PMT_MIN_SECTION_LENGTH: usize = 13;
+
+/// Decrypt a SAMPLE-AES segment, `init` is the initialization segment of fragmented MP4 segments
+pub(crate) fn decrypt(
+    data: &[u8],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    init: Option<&[u8]>,
+) -> Result<Vec<u8>, VideoError> {
+    if data.first() == Some(&TS_SYNC_BYTE) {
+        decrypt_ts(data, key, iv)
+    } else if find_box(data, b"moof").is_some() {
+        decrypt_mp4(data, key, iv, init)
+    } else {
+        Err(VideoError::DecryptionError(
+            "Unknown SAMPLE-AES segment container".to_string(),
+        ))
+    }
+}
+
+/// Decrypt `data` in place with CBC, trailing bytes short of a block stay in clear
+fn decrypt_blocks(decryptor: &mut Aes128CbcDec, data: &mut [u8]) {
+    for block in data.chunks_exact_mut(16) {
+        decryptor.decrypt_block_mut(GenericArray::from_mut_slice(block));
+    }
+}
+
+// MPEG-TS
+
+struct TsPacket<'a> {
+    data: &'a [u8],
+    pid: u16,
+    unit_start: bool,
+    continuity: u8,
+    /// Adaptation field without its length byte
+    adaptation: Option<&'a [u8]>,
+    payload: &'a [u8],
+}
+
+impl<'a> TsPacket<'a> {
+    fn parse(data: &'a [u8]) -> Result<Self, VideoError> {
+        if data.len() != TS_PACKET_SIZE || data[0] != TS_SYNC_BYTE {
+            return Err(VideoError::DecryptionError(
+                "Invalid MPEG-TS packet".to_string(),
+            ));
+        }
+
+        let control = (data[3] >> 4) & 0b11;
+        let mut offset = 4;
+        let mut adaptation = None;
+        if control & 0b10 != 0 {
+            let length = data[4] as usize;
+            if 5 + length > TS_PACKET_SIZE {
+                return Err(VideoError::DecryptionError(
+                    "Invalid MPEG-TS adaptation field".to_string(),
+                ));
+            }
+            adaptation = Some(&data[5..5 + length]);
+            offset = 5 + length;
+        }
+
+        Ok(Self {
+            data,
+            pid: u16::from_be_bytes([data[1] & 0x1f, data[2]]),
+            unit_start: data[1] & 0x40 != 0,
+            continuity: data[3] & 0x0f,
+            adaptation,
+            payload: if control & 0b01 != 0 {
+                &data[offset..]
+            } else {
+                &[]
+            },
+        })
+    }
+
+    /// Section of a PSI packet starting at its table id, at least `min_length` long
+    fn section(&self, min_length: usize) -> Option<(usize, usize)> {
+        if !self.unit_start {
+            return None;
+        }
+
+        let start = TS_PACKET_SIZE - self.payload.len() + 1 + *self.payload.first()? as usize;
+        let length = u16::from_be_bytes([
+            *self.data.get(start + 1)? & 0x0f,
+            *self.data.get(start + 2)?,
+        ]) as usize;
+        let end = start + 3 + length;
+
+        (end <= TS_PACKET_SIZE && length >= min_length).then_some((start, end))
+    }
+}
+
+fn decrypt_ts(data: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Result<Vec<u8>, VideoError> {
+    if data.len() % TS_PACKET_SIZE != 0 {
+        return Err(VideoError::DecryptionError(
+            "MPEG-TS segment is not made of whole packets".to_string(),
+        ));
+    }
+
+    let packets = data
+        .chunks(TS_PACKET_SIZE)
+        .map(TsPacket::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Program map tables from the program association table
+    let mut pmt_pids = vec![];
+    for packet in packets.iter().filter(|x| x.pid == 0) {
+        if let Some((start, end)) = packet.section(PAT_MIN_SECTION_LENGTH) {
+            for program in packet.data[start + 8..end - 4].chunks_exact(4) {
+                if program[0..2] != [0, 0] {
+                    pmt_pids.push(u16::from_be_bytes([program[2] & 0x1f, program[3]]));
+                }
+            }
+        }
+    }
+
+    // Encrypted elementary streams from the program map tables
+    let mut streams = vec![];
+    for packet in packets.iter().filter(|x| pmt_pids.contains(&x.pid)) {
+        if let Some((start, end)) = packet.section(PMT_MIN_SECTION_LENGTH) {
+            for (_, stream_type, pid) in pmt_streams(packet.data, start, end) {
+                if matches!(
+                    stream_type,
+                    STREAM_TYPE_SAMPLE_AES_AC3 | STREAM_TYPE_SAMPLE_AES_EAC3
+                ) {
+                    return Err(VideoError::DecryptionError(
+                        "SAMPLE-AES AC-3 audio is not supported".to_string(),
+                    ));
+                }
+                if matches!(
+                    stream_type,
+                    STREAM_TYPE_SAMPLE_AES_H264 | STREAM_TYPE_SAMPLE_AES_AAC
+                ) && !streams.iter().any(|(x, _)| *x == pid)
+                {
+                    streams.push((pid, stream_type));
+                }
+            }
+        }
+    }
+
+    // Group the packets of each encrypted PES, packets continuing a PES of an earlier segment
+    // can't be decrypted and are left as they are
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut open = HashMap::new();
+    for (index, packet) in packets.iter().enumerate() {
+        if !streams.iter().any(|(pid, _)| *pid == packet.pid) {
+            continue;
+        }
+        if packet.unit_start {
+            groups.push(vec![index]);
+            open.insert(packet.pid, groups.len() - 1);
+        } else if let Some(group) = open.get(&packet.pid) {
+            groups[*group].push(index);
+        }
+    }
+
+    // Decrypted PES packets take the places of the packets they were read from
+    let mut replacements: HashMap<usize, Vec<u8>> = HashMap::new();
+    let mut continuity = HashMap::new();
+    for group in groups {
+        let first = &packets[group[0]];
+        let stream_type = streams
+            .iter()
+            .find(|(pid, _)| *pid == first.pid)
+            .map(|(_, x)| *x)
+            .unwrap_or_default();
+
+        let pes: Vec<u8> = group
+            .iter()
+            .flat_map(|x| packets[*x].payload.iter().copied())
+            .collect();
+        let pes = decrypt_pes(&pes, stream_type, key, iv)?;
+
+        let mut rebuilt = vec![];
+        let cc = continuity.entry(first.pid).or_insert(first.continuity);
+        write_pes(&mut rebuilt, first, &pes, cc)?;
+
+        let mut rebuilt = rebuilt.chunks(TS_PACKET_SIZE);
+        for (position, index) in group.iter().enumerate() {
+            let replacement = if position + 1 == group.len() {
+                rebuilt.by_ref().flatten().copied().collect()
+            } else {
+                rebuilt.next().map(|x| x.to_vec()).unwrap_or_default()
+            };
+            replacements.insert(*index, replacement);
+        }
+    }
+
+    let mut out = Vec::with_capacity(data.len());
+    for (index, packet) in packets.iter().enumerate() {
+        if let Some(replacement) = replacements.remove(&index) {
+            out.extend(replacement);
+        } else if pmt_pids.contains(&packet.pid) {
+            // Announce the decrypted stream types
+            let mut packet_data = packet.data.to_vec();
+            if let Some((start, end)) = packet.section(PMT_MIN_SECTION_LENGTH) {
+                for (offset, stream_type, _) in pmt_streams(packet.data, start, end) {
+                    packet_data[offset] = match stream_type {
+                        STREAM_TYPE_SAMPLE_AES_H264 => STREAM_TYPE_H264,
+                        STREAM_TYPE_SAMPLE_AES_AAC => STREAM_TYPE_AAC,
+                        stream_type => stream_type,
+                    };
+                }
+                let crc = crc32_mpeg2(&packet_data[start..end - 4]);
+                packet_data[end - 4..end].copy_from_slice(&crc.to_be_bytes());
+            }
+            out.extend(packet_data);
+        } else {
+            out.extend(packet.data);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Stream type offsets, stream types and elementary PIDs of a program map table section
+fn pmt_streams(data: &[u8], start: usize, end: usize) -> Vec<(usize, u8, u16)> {
+    let mut streams = vec![];
+    let Some(info) = data.get(start + 10..start + 12) else {
+        return streams;
+    };
+
+    let mut offset = start + 12 + (u16::from_be_bytes([info[0] & 0x0f, info[1]]) as usize);
+    while offset + 5 <= end - 4 {
+        let pid = u16::from_be_bytes([data[offset + 1] & 0x1f, data[offset + 2]]);
+        let info_length = u16::from_be_bytes([data[offset + 3] & 0x0f, data[offset + 4]]) as usize;
+        streams.push((offset, data[offset], pid));
+        offset += 5 + info_length;
+    }
+
+    streams
+}
+
+fn decrypt_pes(
+    pes: &[u8],
+    stream_type: u8,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) -> Result<Vec<u8>, VideoError> {
+    if pes.len() < 9 || pes[0..3] != [0, 0, 1] {
+        return Err(VideoError::DecryptionError(
+            "Invalid PES packet".to_string(),
+        ));
+    }
+
+    let header_length = 9 + pes[8] as usize;
+    if header_length > pes.len() {
+        return Err(VideoError::DecryptionError(
+            "Invalid PES header".to_string(),
+        ));
+    }
+
+    let mut es = pes[header_length..].to_vec();
+    let es = match stream_type {
+        STREAM_TYPE_SAMPLE_AES_H264 => decrypt_h264(&es, key, iv),
+        _ => {
+            decrypt_adts(&mut es, key, iv);
+            es
+        }
+    };
+
+    let mut out = pes[..header_length].to_vec();
+    // Unbounded PES packets keep a zero length
+    if out[4..6] != [0, 0] {
+        let length = u16::try_from(header_length - 6 + es.len()).unwrap_or(0);
+        out[4..6].copy_from_slice(&length.to_be_bytes());
+    }
+    out.extend(es);
+
+    Ok(out)
+}
+
+/// Split a PES into packets of `first`'s stream, the first one keeps its adaptation field
+fn write_pes(
+    out: &mut Vec<u8>,
+    first: &TsPacket,
+    pes: &[u8],
+    continuity: &mut u8,
+) -> Result<(), VideoError> {
+    let mut remaining = pes;
+    let mut adaptation = first.adaptation;
+    let mut unit_start = true;
+
+    if adaptation.is_some_and(|x| x.len() > TS_MAX_ADAPTATION_LENGTH) {
+        return Err(VideoError::DecryptionError(
+            "Invalid MPEG-TS adaptation field".to_string(),
+        ));
+    }
+
+    // An adaptation field filling the whole packet goes in an adaptation-only packet of its own,
+    // which keeps the continuity counter of the previous packet
+    if let Some(field) = adaptation.filter(|x| x.len() == TS_MAX_ADAPTATION_LENGTH) {
+        let mut packet = [0xff_u8; TS_PACKET_SIZE];
+        packet[0] = TS_SYNC_BYTE;
+        packet[1] = first.data[1] & 0xbf;
+        packet[2] = first.data[2];
+        packet[3] = (first.data[3] & 0xc0) | (0b10 << 4) | (continuity.wrapping_sub(1) & 0x0f);
+        packet[4] = field.len() as u8;
+        packet[5..].copy_from_slice(field);
+
+        out.extend(packet);
+        adaptation = None;
+    }
+
+    while !remaining.is_empty() {
+        let capacity = 184 - adaptation.map_or(0, |x| x.len() + 1);
+        let (payload, rest) = remaining.split_at(remaining.len().min(capacity));
+        remaining = rest;
+
+        let mut packet = [0xff_u8; TS_PACKET_SIZE];
+        packet[0] = TS_SYNC_BYTE;
+        packet[1] = (first.data[1] & 0xbf) | if unit_start { 0x40 } else { 0 };
+        packet[2] = first.data[2];
+
+        // Adaptation field fills the space the payload leaves
+        let adaptation_size = 184 - payload.len();
+        let control = if adaptation_size > 0 { 0b11 } else { 0b01 };
+        packet[3] = (first.data[3] & 0xc0) | (control << 4) | *continuity;
+        if adaptation_size > 0 {
+            packet[4] = (adaptation_size - 1) as u8;
+            let field = adaptation.unwrap_or_default();
+            packet[5..5 + field.len()].copy_from_slice(field);
+            // Stuffing needs the flags byte in front of it
+            if field.is_empty() && adaptation_size > 1 {
+                packet[5] = 0;
+            }
+        }
+        packet[4 + adaptation_size..].copy_from_slice(payload);
+
+        out.extend(packet);
+        *continuity = (*continuity + 1) & 0x0f;
+        adaptation = None;
+        unit_start = false;
+    }
+
+    Ok(())
+}
+
+/// CRC of PSI sections
+fn crc32_mpeg2(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffff_u32;
+    for byte in data {
+        crc ^= (*byte as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x80000000 != 0 {
+                (crc << 1) ^ 0x04c11db7
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Decrypt the slices of an Annex B H.264 stream
+fn decrypt_h264(es: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let mut starts = vec![];
+    let mut index = 0;
+    while index + 3 <= es.len() {
+        if es[index..index + 3] == [0, 0, 1] {
+            starts.push(index + 3);
+            index += 3;
+        } else {
+            index += 1;
+        }
+    }
+
+    let mut out = Vec::with_capacity(es.len());
+    let mut copied = 0;
+    for (i, &start) in starts.iter().enumerate() {
+        let mut end = starts.get(i + 1).map_or(es.len(), |next| next - 3);
+        // Zeros in front of the next start code are not part of the unit
+        while end > start && es[end - 1] == 0 {
+            end -= 1;
+        }
+
+        out.extend(&es[copied..start]);
+        out.extend(decrypt_nal_unit(&es[start..end], key, iv));
+        copied = end;
+    }
+    out.extend(&es[copied..]);
+
+    out
+}
+
+/// Slices longer than 48 bytes are encrypted after a 32 byte leader, one block in ten
+fn decrypt_nal_unit(nal: &[u8], key: &[u8; 16], iv: &[u8; 16]) -> Vec<u8> {
+    let nal_type = nal.first().map_or(0, |x| x & 0x1f);
+    if !matches!(nal_type, 1 | 5) || nal.len() <= 48 {
+        return nal.to_vec();
+    }
+
+    let mut unit = remove_emulation_prevention(nal);
+    let mut decryptor = Aes128CbcDec::new(key.into(), iv.into());
+
+    let mut offset = 32;
+    while offset < unit.len() {
+        if unit.len() - offset > 16 {
+            decrypt_blocks(&mut decryptor, &mut unit[offset..offset + 16]);
+            offset += 16;
+        }
+        offset += (unit.len() - offset).min(144);
+    }
+
+    add_emulation_prevention(&unit)
+}
+
+fn remove_emulation_prevention(nal: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+fn add_emulation_prevention(unit: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(unit.len() + unit.len() / 64);
+    let mut zeros = 0;
+    for &byte in unit {
+        if zeros >= 2 && byte <= 3 {
+            out.push(3);
+            zeros = 0;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+/// Decrypt the frames of an ADTS stream in place, each frame is encrypted after a 16 byte leader
+fn decrypt_adts(es: &mut [u8], key: &[u8; 16], iv: &[u8; 16]) {
+    let mut index = 0;
+    while index + 7 <= es.len() {
+        if es[index] != 0xff || es[index + 1] & 0xf0 != 0xf0 {
+            index += 1;
+            continue;
+        }
+
+        let header_length = if es[index + 1] & 1 == 1 { 7 } else { 9 };
+        let frame_length = ((es[index + 3] as usize & 0b11) << 11)
+            | ((es[index + 4] as usize) << 3)
+            | (es[index + 5] as usize >> 5);
+        if frame_length < header_length || index + frame_length > es.len() {
+            break;
+        }
+
+        let payload = &mut es[index + header_length..index + frame_length];
+        if payload.len() > 16 {
+            let mut decryptor = Aes128CbcDec::new(key.into(), iv.into());
+            decrypt_blocks(&mut decryptor, &mut payload[16..]);
+        }
+
+        index += frame_length;
+    }
+}
+
+// Fragmented MP4
+
+/// Encryption defaults of a track from the `tenc` box
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TrackEncryption {
+    crypt_blocks: usize,
+    skip_blocks: usize,
+    iv_size: usize,
+    constant_iv: Option<[u8; 16]>,
+}
+
+/// Box type, content start and box end of the boxes in `data`
+fn boxes(data: &[u8]) -> Vec<([u8; 4], usize, usize)> {
+    let mut boxes = vec![];
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap()) as u64;
+        let name: [u8; 4] = data[offset + 4..offset + 8].try_into().unwrap();
+        let (size, header) = match size {
+            0 => ((data.len() - offset) as u64, 8),
+            1 if offset + 16 <= data.len() => (
+                u64::from_be_bytes(data[offset + 8..offset + 16].try_into().unwrap()),
+                16,
+            ),
+            size => (size, 8),
+        };
+        if size < header as u64 || offset as u64 + size > data.len() as u64 {
+            break;
+        }
+
+        let end = offset + size as usize;
+        boxes.push((name, offset + header, end));
+        offset = end;
+    }
+    boxes
+}
+
+/// Content range of the first `name` box directly in `data`
+fn find_box(data: &[u8], name: &[u8; 4]) -> Option<(usize, usize)> {
+    boxes(data)
+        .into_iter()
+        .find(|(x, _, _)| x == name)
+        .map(|(_, start, end)| (start, end))
+}
+
+/// Find the `tenc` box of the first protected track in an initialization segment
+fn track_encryption(init: &[u8]) -> Result<Option<TrackEncryption>, VideoError> {
+    // Containers on the way to `tenc`, sample entries have fixed fields in front of their boxes
+    fn search(data: &[u8]) -> Result<Option<TrackEncryption>, VideoError> {
+        for (name, start, end) in boxes(data) {
+            let content = &data[start..end];
+            match &name {
+                b"tenc" if content.len() >= 24 => {
+                    let version = content[0];
+                    let (crypt_blocks, skip_blocks) = if version > 0 {
+                        ((content[5] >> 4) as usize, (content[5] & 0x0f) as usize)
+                    } else {
+                        (0, 0)
+                    };
+                    let iv_size = content[7] as usize;
+                    if ![0, 8, 16].contains(&iv_size) {
+                        return Err(VideoError::DecryptionError(format!(
+                            "Invalid tenc IV size: {iv_size}"
+                        )));
+                    }
+                    let constant_iv = if iv_size == 0 {
+                        content.get(24).and_then(|size| {
+                            let iv = content.get(25..25 + *size as usize)?;
+                            let mut constant_iv = [0_u8; 16];
+                            constant_iv[..iv.len().min(16)]
+                                .copy_from_slice(&iv[..iv.len().min(16)]);
+                            Some(constant_iv)
+                        })
+                    } else {
+                        None
+                    };
+
+                    return Ok(Some(TrackEncryption {
+                        crypt_blocks,
+                        skip_blocks,
+                        iv_size,
+                        constant_iv,
+                    }));
+                }
+                b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" | b"sinf" | b"schi" => {
+                    if let Some(found) = search(content)? {
+                        return Ok(Some(found));
+                    }
+                }
+                b"stsd" if content.len() > 8 => {
+                    if let Some(found) = search(&content[8..])? {
+                        return Ok(Some(found));
+                    }
+                }
+                b"encv" if content.len() > 78 => {
+                    if let Some(found) = search(&content[78..])? {
+                        return Ok(Some(found));
+                    }
+                }
+                b"enca" if content.len() > 28 => {
+                    if let Some(found) = search(&content[28..])? {
+                        return Ok(Some(found));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    search(init)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn decrypt_mp4(
+    data: &[u8],
+    key: &[u8; 16],
+    iv: &[u8; 16],
+    init: Option<&[u8]>,
+) -> Result<Vec<u8>, VideoError> {
+    let invalid = || VideoError::DecryptionError("Invalid fragmented MP4 segment".to_string());
+
+    // Without the initialization segment assume the pattern of video tracks
+    let track = init.map(track_encryption).transpose()?.flatten();
+    let (crypt_blocks, skip_blocks) = track.map_or((1, 9), |x| (x.crypt_blocks, x.skip_blocks));
+
+    let mut out = data.to_vec();
+
+    for (name, moof_content, moof_end) in boxes(data) {
+        if &name != b"moof" {
+            continue;
+        }
+        let moof_start = moof_content - 8;
+        let moof = &data[moof_content..moof_end];
+
+        for (name, start, end) in boxes(moof) {
+            if &name != b"traf" {
+                continue;
+            }
+            let traf = &moof[start..end];
+
+            // Sample sizes and data offset
+            let (tfhd_start, _) = find_box(traf, b"tfhd").ok_or_else(invalid)?;
+            let tfhd_flags = read_u32(traf, tfhd_start).ok_or_else(invalid)? & 0xffffff;
+            let mut field = tfhd_start + 8;
+            let mut base_offset = moof_start as u64;
+            if tfhd_flags & 0x1 != 0 {
+                base_offset = u64::from_be_bytes(
+                    traf.get(field..field + 8)
+                        .ok_or_else(invalid)?
+                        .try_into()
+                        .unwrap(),
+                );
+                field += 8;
+            }
+            for flag in [0x2, 0x8] {
+                if tfhd_flags & flag != 0 {
+                    field += 4;
+                }
+            }
+            let default_size = if tfhd_flags & 0x10 != 0 {
+                read_u32(traf, field).ok_or_else(invalid)?
+            } else {
+                0
+            };
+
+            let (trun_start, _) = find_box(traf, b"trun").ok_or_else(invalid)?;
+            let trun_flags = read_u32(traf, trun_start).ok_or_else(invalid)? & 0xffffff;
+            let sample_count = read_u32(traf, trun_start + 4).ok_or_else(invalid)? as usize;
+            let mut field = trun_start + 8;
+            let mut data_offset = base_offset;
+            if trun_flags & 0x1 != 0 {
+                data_offset = data_offset
+                    .checked_add_signed(read_u32(traf, field).ok_or_else(invalid)? as i32 as i64)
+                    .ok_or_else(invalid)?;
+                field += 4;
+            }
+            if trun_flags & 0x4 != 0 {
+                field += 4;
+            }
+            // Counts come from the segment, each sample needs its entry or media bytes
+            let entry_size = [0x100, 0x200, 0x400, 0x800]
+                .into_iter()
+                .filter(|flag| trun_flags & flag != 0)
+                .count()
+                * 4;
+            let max_samples = traf
+                .len()
+                .saturating_sub(field)
+                .checked_div(entry_size)
+                .unwrap_or(data.len() / (default_size as usize).max(1));
+            if sample_count > max_samples {
+                return Err(invalid());
+            }
+            let mut sizes = Vec::with_capacity(sample_count);
+            for _ in 0..sample_count {
+                if trun_flags & 0x100 != 0 {
+                    field += 4;
+                }
+                if trun_flags & 0x200 != 0 {
+                    sizes.push(read_u32(traf, field).ok_or_else(invalid)? as usize);
+                    field += 4;
+                } else {
+                    sizes.push(default_size as usize);
+                }
+                for flag in [0x400, 0x800] {
+                    if trun_flags & flag != 0 {
+                        field += 4;
+                    }
+                }
+            }
+
+            let samples = find_box(traf, b"senc")
+                .map(|(start, end)| parse_senc(&traf[start..end], sample_count, track))
+                .transpose()?;
+
+            let mut offset = data_offset as usize;
+            for (index, size) in sizes.into_iter().enumerate() {
+                let sample = out.get_mut(offset..offset + size).ok_or_else(invalid)?;
+                offset += size;
+
+                let (sample_iv, subsamples) = samples
+                    .as_ref()
+                    .and_then(|x| x.get(index))
+                    .cloned()
+                    .unwrap_or((None, vec![]));
+                let sample_iv = sample_iv
+                    .or(track.and_then(|x| x.constant_iv))
+                    .unwrap_or(*iv);
+
+                decrypt_cbcs_sample(
+                    sample,
+                    &subsamples,
+                    crypt_blocks,
+                    skip_blocks,
+                    key,
+                    &sample_iv,
+                )?;
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+/// Sample IV and subsamples (clear, protected bytes) from a `senc` box
+type SampleEncryption = (Option<[u8; 16]>, Vec<(usize, usize)>);
+
+fn parse_senc(
+    senc: &[u8],
+    sample_count: usize,
+    track: Option<TrackEncryption>,
+) -> Result<Vec<SampleEncryption>, VideoError> {
+    let flags = read_u32(senc, 0).unwrap_or(0) & 0xffffff;
+    let count = read_u32(senc, 4).unwrap_or(0) as usize;
+    // Every sample of the run has an entry, checked before trusting the count
+    if count != sample_count {
+        return Err(VideoError::DecryptionError("Invalid senc box".to_string()));
+    }
+
+    let parse = |iv_size: usize| -> Option<Vec<SampleEncryption>> {
+        let mut samples = Vec::with_capacity(count);
+        let mut offset = 8;
+        for _ in 0..count {
+            let iv = if iv_size > 0 {
+                let mut iv = [0_u8; 16];
+                iv[..iv_size].copy_from_slice(senc.get(offset..offset + iv_size)?);
+                offset += iv_size;
+                Some(iv)
+            } else {
+                None
+            };
+
+            let mut subsamples = vec![];
+            if flags & 0x2 != 0 {
+                let subsample_count =
+                    u16::from_be_bytes(senc.get(offset..offset + 2)?.try_into().ok()?);
+                offset += 2;
+                for _ in 0..subsample_count {
+                    let clear = u16::from_be_bytes(senc.get(offset..offset + 2)?.try_into().ok()?);
+                    let protected = read_u32(senc, offset + 2)?;
+                    subsamples.push((clear as usize, protected as usize));
+                    offset += 6;
+                }
+            }
+
+            samples.push((iv, subsamples));
+        }
+        (offset == senc.len()).then_some(samples)
+    };
+
+    // The IV size is in the initialization segment, otherwise take the one that fits the box
+    let samples = match track {
+        Some(track) => parse(track.iv_size),
+        None => [0, 8, 16].into_iter().find_map(parse),
+    };
+
+    samples
+        .filter(|x| x.len() == sample_count)
+        .ok_or_else(|| VideoError::DecryptionError("Invalid senc box".to_string()))
+}
+
+/// Decrypt a `cbcs` sample in place, the CBC chain restarts at each protected range
+fn decrypt_cbcs_sample(
+    sample: &mut [u8],
+    subsamples: &[(usize, usize)],
+    crypt_blocks: usize,
+    skip_blocks: usize,
+    key: &[u8; 16],
+    iv: &[u8; 16],
+) -> Result<(), VideoError> {
+    let whole_sample = [(0, sample.len())];
+    let subsamples = if subsamples.is_empty() {
+        &whole_sample[..]
+    } else {
+        subsamples
+    };
+
+    // Without a pattern every block is encrypted, a pattern without encrypted blocks is taken as none
+    let (crypt_blocks, skip_blocks) = if crypt_blocks == 0 {
+        (1, 0)
+    } else {
+        (crypt_blocks, skip_blocks)
+    };
+
+    let mut offset = 0;
+    for &(clear, protected) in subsamples {
+        offset += clear;
+        let range = sample.get_mut(offset..offset + protected).ok_or_else(|| {
+            VideoError::DecryptionError("Subsamples exceed the sample".to_string())
+        })?;
+        offset += protected;
+
+        let mut decryptor = Aes128CbcDec::new(key.into(), iv.into());
+        let mut position = 0;
+        while range.len() - position >= 16 * crypt_blocks {
+            decrypt_blocks(
+                &mut decryptor,
+                &mut range[position..position + 16 * crypt_blocks],
+            );
+            position += 16 * crypt_blocks;
+            position += (range.len() - position).min(16 * skip_blocks);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::{BlockEncryptMut, KeyIvInit};
+
+    type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+    const KEY: [u8; 16] = [7; 16];
+    const IV: [u8; 16] = [9; 16];
+
+    fn encrypt_blocks(encryptor: &mut Aes128CbcEnc, data: &mut [u8]) {
+        for block in data.chunks_exact_mut(16) {
+            encryptor.encrypt_block_mut(GenericArray::from_mut_slice(block));
+        }
+    }
+
+    /// Slice NAL unit with emulation prevention bytes
+    fn slice(length: usize) -> Vec<u8> {
+        let unit: Vec<u8> = std::iter::once(0x65)
+            .chain((1..length).map(|x| if x % 40 < 3 { 0 } else { x as u8 | 0x10 }))
+            .collect();
+        add_emulation_prevention(&unit)
+    }
+
+    fn encrypt_nal_unit(nal: &[u8]) -> Vec<u8> {
+        let mut unit = remove_emulation_prevention(nal);
+        let mut encryptor = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        let mut offset = 32;
+        while offset < unit.len() {
+            if unit.len() - offset > 16 {
+                encrypt_blocks(&mut encryptor, &mut unit[offset..offset + 16]);
+                offset += 16;
+            }
+            offset += (unit.len() - offset).min(144);
+        }
+        add_emulation_prevention(&unit)
+    }
+
+    fn adts_frame(payload_length: usize) -> Vec<u8> {
+        let length = payload_length + 7;
+        let mut frame = vec![
+            0xff,
+            0xf1,
+            0x50,
+            0x80 | (length >> 11) as u8,
+            (length >> 3) as u8,
+            ((length & 0b111) << 5) as u8 | 0x1f,
+            0xfc,
+        ];
+        frame.extend((0..payload_length).map(|x| x as u8));
+        frame
+    }
+
+    fn encrypt_adts_frame(frame: &mut [u8]) {
+        let mut encryptor = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        encrypt_blocks(&mut encryptor, &mut frame[7 + 16..]);
+    }
+
+    fn psi_packet(pid: u16, section: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0x47, 0x40 | (pid >> 8) as u8, pid as u8, 0x10, 0];
+        packet.extend(section);
+        packet.extend(crc32_mpeg2(section).to_be_bytes());
+        packet.resize(TS_PACKET_SIZE, 0xff);
+        packet
+    }
+
+    fn pes_packets(pid: u16, es: &[u8]) -> Vec<u8> {
+        let mut pes = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0, 0];
+        pes.extend(es);
+
+        let first = TsPacket {
+            data: &[0x47, (pid >> 8) as u8, pid as u8, 0x10],
+            pid,
+            unit_start: true,
+            continuity: 0,
+            adaptation: None,
+            payload: &[],
+        };
+        let mut out = vec![];
+        write_pes(&mut out, &first, &pes, &mut 0).unwrap();
+        out
+    }
+
+    #[test]
+    fn decrypt_ts_h264() {
+        let plain: Vec<u8> = [
+            vec![0, 0, 0, 1, 0x09, 0xf0],
+            vec![0, 0, 1],
+            slice(400),
+            vec![0, 0, 1],
+            slice(30),
+        ]
+        .concat();
+        let encrypted: Vec<u8> = [
+            vec![0, 0, 0, 1, 0x09, 0xf0],
+            vec![0, 0, 1],
+            encrypt_nal_unit(&slice(400)),
+            vec![0, 0, 1],
+            slice(30),
+        ]
+        .concat();
+
+        let pat = [0x00, 0xb0, 0x0d, 0, 1, 0xc1, 0, 0, 0, 1, 0xf0, 0x00];
+        let pmt = [
+            0x02, 0xb0, 0x12, 0, 1, 0xc1, 0, 0, 0xe1, 0x00, 0xf0, 0x00, 0xdb, 0xe1, 0x00, 0xf0,
+            0x00,
+        ];
+        let segment = [
+            psi_packet(0, &pat),
+            psi_packet(0x1000, &pmt),
+            pes_packets(0x100, &encrypted),
+        ]
+        .concat();
+
+        let decrypted = decrypt(&segment, &KEY, &IV, None).unwrap();
+
+        let packets: Vec<TsPacket> = decrypted
+            .chunks(TS_PACKET_SIZE)
+            .map(|x| TsPacket::parse(x).unwrap())
+            .collect();
+        let (start, end) = packets[1].section(PMT_MIN_SECTION_LENGTH).unwrap();
+        assert_eq!(
+            pmt_streams(packets[1].data, start, end)[0].1,
+            STREAM_TYPE_H264
+        );
+        assert_eq!(
+            &packets[1].data[end - 4..end],
+            crc32_mpeg2(&packets[1].data[start..end - 4]).to_be_bytes()
+        );
+
+        let pes: Vec<u8> = packets[2..]
+            .iter()
+            .enumerate()
+            .flat_map(|(index, x)| {
+                assert_eq!(x.continuity as usize, index);
+                x.payload.to_vec()
+            })
+            .collect();
+        assert_eq!(pes[9..], plain);
+    }
+
+    #[test]
+    fn decrypt_ts_aac() {
+        let plain = [adts_frame(100), adts_frame(10), adts_frame(64)].concat();
+        let mut encrypted = [adts_frame(100), adts_frame(10), adts_frame(64)];
+        encrypt_adts_frame(&mut encrypted[0]);
+        encrypt_adts_frame(&mut encrypted[2]);
+
+        let mut es = encrypted.concat();
+        assert_ne!(es, plain);
+
+        decrypt_adts(&mut es, &KEY, &IV);
+        assert_eq!(es, plain);
+    }
+
+    fn mp4_box(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        [
+            &((content.len() + 8) as u32).to_be_bytes(),
+            &name[..],
+            content,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn cbcs_pattern_without_crypt_blocks() {
+        let sample: Vec<u8> = (0..64).collect();
+
+        let mut encrypted = sample.clone();
+        let mut encryptor = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        encrypt_blocks(&mut encryptor, &mut encrypted);
+
+        decrypt_cbcs_sample(&mut encrypted, &[], 0, 9, &KEY, &IV).unwrap();
+        assert_eq!(encrypted, sample);
+    }
+
+    #[test]
+    fn short_pat_section() {
+        // PAT with a section length of 5, too short for its header and CRC
+        let mut packet = vec![TS_SYNC_BYTE, 0x40, 0x00, 0x10, 0x00, 0x00, 0xb0, 0x05];
+        packet.resize(TS_PACKET_SIZE, 0xff);
+
+        assert_eq!(decrypt(&packet, &KEY, &IV, None).unwrap(), packet);
+    }
+
+    #[test]
+    fn pes_after_full_adaptation_field() {
+        let field = [0_u8; TS_MAX_ADAPTATION_LENGTH];
+        let first = TsPacket {
+            data: &[0x47, 0x01, 0x00, 0x30],
+            pid: 0x100,
+            unit_start: true,
+            continuity: 0,
+            adaptation: Some(&field),
+            payload: &[],
+        };
+        let pes = slice(200);
+
+        let mut out = vec![];
+        write_pes(&mut out, &first, &pes, &mut 0).unwrap();
+
+        let packets: Vec<TsPacket> = out
+            .chunks(TS_PACKET_SIZE)
+            .map(|x| TsPacket::parse(x).unwrap())
+            .collect();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].adaptation, Some(&field[..]));
+        assert!(packets[0].payload.is_empty() && !packets[0].unit_start);
+        assert!(packets[1].unit_start);
+        assert_eq!(
+            packets
+                .iter()
+                .flat_map(|x| x.payload.iter().copied())
+                .collect::<Vec<u8>>(),
+            pes
+        );
+
+        let field = [0_u8; TS_MAX_ADAPTATION_LENGTH + 1];
+        let first = TsPacket {
+            adaptation: Some(&field),
+            ..first
+        };
+        assert!(write_pes(&mut vec![], &first, &pes, &mut 0).is_err());
+    }
+
+    #[test]
+    fn invalid_tenc_iv_size() {
+        let tenc = [&[0, 0, 0, 0, 0, 0, 1, 200][..], &[0; 16]].concat();
+        let sinf = mp4_box(b"sinf", &mp4_box(b"schi", &mp4_box(b"tenc", &tenc)));
+
+        assert!(track_encryption(&sinf).is_err());
+    }
+
+    #[test]
+    fn sample_count_beyond_trun() {
+        let traf = mp4_box(
+            b"traf",
+            &[
+                mp4_box(b"tfhd", &[0, 0, 0, 0, 0, 0, 0, 1]),
+                mp4_box(b"trun", &[0, 0, 0x02, 0x00, 0xff, 0xff, 0xff, 0xff]),
+            ]
+            .concat(),
+        );
+        let segment = [mp4_box(b"moof", &traf), mp4_box(b"mdat", &[0; 32])].concat();
+
+        assert!(decrypt_mp4(&segment, &KEY, &IV, None).is_err());
+    }
+
+    #[test]
+    fn decrypt_fmp4_cbcs() {
+        let samples: Vec<Vec<u8>> = vec![
+            (0..500).map(|x| x as u8).collect(),
+            (0..40).map(|x| x as u8).collect(),
+        ];
+
+        // First sample has 20 clear bytes before its protected range, pattern 1:9
+        let mut encrypted = samples.clone();
+        let protected = &mut encrypted[0][20..];
+        let mut encryptor = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        let mut position = 0;
+        while protected.len() - position >= 16 {
+            encrypt_blocks(&mut encryptor, &mut protected[position..position + 16]);
+            position += 16;
+            position += (protected.len() - position).min(144);
+        }
+        let mut encryptor = Aes128CbcEnc::new(&KEY.into(), &IV.into());
+        encrypt_blocks(&mut encryptor, &mut encrypted[1][..16]);
+
+        let tenc = [&[1, 0, 0, 0, 0, 0x19, 1, 0][..], &[0; 16], &[16], &IV].concat();
+        let sinf = mp4_box(b"sinf", &mp4_box(b"schi", &mp4_box(b"tenc", &tenc)));
+        let encv = mp4_box(b"encv", &[&[0; 78][..], &sinf].concat());
+        let stsd = mp4_box(b"stsd", &[&[0; 8][..], &encv].concat());
+        let init = [b"stbl", b"minf", b"mdia", b"trak", b"moov"]
+            .into_iter()
+            .fold(stsd, |content, name| mp4_box(name, &content));
+
+        let senc = [
+            &[0, 0, 0, 2, 0, 0, 0, 2][..],
+            &[0, 1, 0, 20, 0, 0, 0x01, 0xe0],
+            &[0, 1, 0, 0, 0, 0, 0, 40],
+        ]
+        .concat();
+        let traf = |data_offset: u32| {
+            mp4_box(
+                b"traf",
+                &[
+                    mp4_box(b"tfhd", &[0, 0x02, 0, 0, 0, 0, 0, 1]),
+                    mp4_box(
+                        b"trun",
+                        &[
+                            &[0, 0, 0x02, 0x01, 0, 0, 0, 2][..],
+                            &data_offset.to_be_bytes(),
+                            &500_u32.to_be_bytes(),
+                            &40_u32.to_be_bytes(),
+                        ]
+                        .concat(),
+                    ),
+                    mp4_box(b"senc", &senc),
+                ]
+                .concat(),
+            )
+        };
+        let moof_size = mp4_box(b"moof", &traf(0)).len() as u32;
+        let segment = [
+            mp4_box(b"moof", &traf(moof_size + 8)),
+            mp4_box(b"mdat", &encrypted.concat()),
+        ]
+        .concat();
+
+        let decrypted = decrypt(&segment, &KEY, &[0; 16], Some(&init)).unwrap();
+        assert_eq!(decrypted[moof_size as usize + 8..], samples.concat());
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(dead_code)]
+use super::encryption::Encryption;
 use super::media_format::MediaFormat;
 use super::remote_data::RemoteData;
+use std::time::Duration;
 
 /// Type of media segment
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -8,8 +10,11 @@ pub struct Segment {
     pub data: RemoteData,
     pub discon_seq: u64,
     pub seq: u64,
+    pub duration: Duration,
     pub format: MediaFormat,
     pub initialization: Option<RemoteData>,
+    /// AES-128 applies to the initialization segment when its key precedes the `EXT-X-MAP`
+    pub initialization_encryption: Encryption,
 }
 
 impl Segment {
@@ -0,0 +1,199 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use reqwest::StatusCode;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::RwLock, time::Instant};
+use url::Url;
+
+use crate::constants::{
+    DEFAULT_HEADERS, DEFAULT_LIVE_SEGMENT_DURATION, DEFAULT_LIVE_STALL_TIMEOUT,
+};
+use crate::stream::streams::{LinkRefresher, Stream};
+use crate::structs::{RequestOptions, VideoError};
+use crate::utils::build_client;
+
+/// Latest segment number the server has, sent with every segment
+const HEAD_SEQUENCE_HEADER: &str = "x-head-seqnum";
+
+pub struct DashLiveStreamOptions {
+    pub client: Option<reqwest_middleware::ClientWithMiddleware>,
+    /// URL of a live adaptive format, segments are requested with its `sq` parameter
+    pub url: String,
+    /// First segment to download, `0` is the earliest segment of the stream
+    pub start_sequence: u64,
+    /// Duration of a segment, how long to wait at the live edge. Default is 5 seconds
+    pub target_duration: Option<Duration>,
+    /// Stream ends once requests at the live edge found no new segment for this long. Default is 60 seconds
+    pub end_timeout: Option<Duration>,
+    /// Resolves a fresh `url` when the current one is rejected with `403`
+    pub refresher: Option<Arc<dyn LinkRefresher>>,
+}
+
+/// Video and audio of a live stream recorded from the start, see [`crate::Video::stream_live_from_start`]
+pub struct LiveFromStart {
+    pub video: DashLiveStream,
+    pub audio: DashLiveStream,
+}
+
+/// Live adaptive format downloaded segment by segment from `start_sequence`.
+///
+/// Segments already behind the live edge are downloaded back to back, then the live edge is followed
+/// until no new segment appears for the end timeout.
+pub struct DashLiveStream {
+    client: reqwest_middleware::ClientWithMiddleware,
+    url: RwLock<String>,
+    refresher: Option<Arc<dyn LinkRefresher>>,
+    target_duration: Duration,
+    end_timeout: Duration,
+
+    next_sequence: RwLock<u64>,
+    head_sequence: RwLock<Option<u64>>,
+    /// First unanswered request at the live edge since the last segment
+    stalled_since: RwLock<Option<Instant>>,
+}
+
+enum SegmentResponse {
+    Data(Bytes),
+    /// Not available (yet), the live edge may have moved meanwhile
+    Missing,
+}
+
+impl DashLiveStream {
+    pub fn new(options: DashLiveStreamOptions) -> Result<Self, VideoError> {
+        let client = if let Some(client) = options.client {
+            client
+        } else {
+            build_client(&RequestOptions::default())?
+        };
+
+        Ok(Self {
+            client,
+            url: RwLock::new(options.url),
+            refresher: options.refresher,
+            target_duration: options
+                .target_duration
+                .unwrap_or(DEFAULT_LIVE_SEGMENT_DURATION),
+            end_timeout: options.end_timeout.unwrap_or(DEFAULT_LIVE_STALL_TIMEOUT),
+            next_sequence: RwLock::new(options.start_sequence),
+            head_sequence: RwLock::new(None),
+            stalled_since: RwLock::new(None),
+        })
+    }
+
+    /// Number of the next segment [`Stream::chunk`] returns
+    pub async fn next_sequence(&self) -> u64 {
+        *self.next_sequence.read().await
+    }
+
+    /// Latest segment number reported by the server, `None` before the first response
+    pub async fn head_sequence(&self) -> Option<u64> {
+        *self.head_sequence.read().await
+    }
+
+    async fn send(&self, sequence: u64) -> Result<reqwest::Response, VideoError> {
+        let url = sequence_url(&self.url.read().await, sequence)?;
+
+        self.client
+            .get(url)
+            .headers(DEFAULT_HEADERS.clone())
+            .send()
+            .await
+            .map_err(VideoError::ReqwestMiddleware)
+    }
+
+    async fn fetch(&self, sequence: u64) -> Result<SegmentResponse, VideoError> {
+        let mut response = self.send(sequence).await?;
+
+        // Media links expire during long streams, continue with a fresh one
+        if response.status() == StatusCode::FORBIDDEN {
+            if let Some(refresher) = &self.refresher {
+                *self.url.write().await = refresher.refresh().await?;
+                response = self.send(sequence).await?;
+            }
+        }
+
+        if let Some(head) = response
+            .headers()
+            .get(HEAD_SEQUENCE_HEADER)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse::<u64>().ok())
+        {
+            let mut head_sequence = self.head_sequence.write().await;
+            *head_sequence = Some(head_sequence.map_or(head, |current| current.max(head)));
+        }
+
+        // Forbidden links and other client errors are not missing segments, skipping them would lose data
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::NO_CONTENT
+        ) {
+            return Ok(SegmentResponse::Missing);
+        }
+
+        let mut response = response.error_for_status().map_err(VideoError::Reqwest)?;
+
+        let mut buf: BytesMut = BytesMut::new();
+        while let Some(chunk) = response.chunk().await.map_err(VideoError::Reqwest)? {
+            buf.extend(chunk);
+        }
+
+        Ok(SegmentResponse::Data(buf.into()))
+    }
+}
+
+#[async_trait]
+impl Stream for DashLiveStream {
+    async fn chunk(&self) -> Result<Option<Bytes>, VideoError> {
+        loop {
+            let sequence = self.next_sequence().await;
+
+            match self.fetch(sequence).await? {
+                SegmentResponse::Data(data) => {
+                    *self.next_sequence.write().await = sequence + 1;
+                    *self.stalled_since.write().await = None;
+
+                    return Ok(Some(data));
+                }
+                // Segments behind the live edge which are gone can't come back, skip them
+                SegmentResponse::Missing
+                    if self
+                        .head_sequence()
+                        .await
+                        .is_some_and(|head| sequence < head) =>
+                {
+                    *self.next_sequence.write().await = sequence + 1;
+                }
+                // Wait for the segment to be published, the stream ended if none came for the end timeout
+                SegmentResponse::Missing => {
+                    let stalled_since = *self
+                        .stalled_since
+                        .write()
+                        .await
+                        .get_or_insert_with(Instant::now);
+                    if stalled_since.elapsed() >= self.end_timeout {
+                        return Ok(None);
+                    }
+
+                    tokio::time::sleep(self.target_duration).await;
+                }
+            }
+        }
+    }
+}
+
+/// `url` requesting segment `sequence`
+fn sequence_url(url: &str, sequence: u64) -> Result<String, VideoError> {
+    let mut url = Url::parse(url)?;
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != "sq")
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .append_pair("sq", &sequence.to_string());
+
+    Ok(url.to_string())
+}
@@ -1,69 +1,638 @@
-use crate::constants::{DEFAULT_HEADERS, DEFAULT_MAX_RETRIES};
+use crate::constants::{
+    DEFAULT_LIVE_PREFETCH, DEFAULT_LIVE_SEGMENT_RETRIES, DEFAULT_LIVE_STALL_TIMEOUT,
+};
 use crate::stream::{
-    encryption::Encryption, media_format::MediaFormat, remote_data::RemoteData, segment::Segment,
+    encryption::{Encryption, KeyCache},
+    local_playlist::{parse_id, segment_files, write_file, LocalPlaylist, LocalSegment},
+    media_format::MediaFormat,
+    remote_data::RemoteData,
+    segment::Segment,
     streams::Stream,
 };
-use crate::structs::{CustomRetryableStrategy, VideoError};
-use crate::utils::{get_html, make_absolute_url};
+use crate::structs::{RequestOptions, VideoError};
+use crate::utils::{build_client, get_html, make_absolute_url};
 
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use m3u8_rs::parse_media_playlist;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::RwLock;
+use futures::{future::BoxFuture, stream::FuturesOrdered, StreamExt};
+use m3u8_rs::{
+    is_master_playlist, parse_master_playlist_res, parse_media_playlist, AlternativeMediaType,
+    MasterPlaylist,
+};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::Instant,
+};
 
+/// Share of the measured throughput an adaptive variant may use
+const ADAPTIVE_HEADROOM: f64 = 0.8;
+
+/// Variant of a master playlist a [`LiveStream`] plays
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum VariantSelection {
+    /// Variant with the highest bandwidth
+    #[default]
+    Highest,
+    /// Variant with the lowest bandwidth
+    Lowest,
+    /// Highest variant not exceeding the bandwidth in bits per second
+    MaxBandwidth(u64),
+    /// Highest variant not exceeding the height in pixels
+    MaxHeight(u64),
+}
+
+#[derive(Default)]
 pub struct LiveStreamOptions {
     pub client: Option<reqwest_middleware::ClientWithMiddleware>,
+    /// Media playlist or master playlist URL
     pub stream_url: String,
+    /// Stream fails with [`VideoError::LiveStreamStalled`] once the playlist did not change for this long. Default is 60 seconds
+    pub stall_timeout: Option<Duration>,
+    /// Variant played when `stream_url` is a master playlist
+    pub variant: VariantSelection,
+    /// Switch between the variants allowed by `variant` based on the measured throughput, and fall back
+    /// to a lower variant when a segment fails to download
+    pub adaptive: bool,
+    /// Language of the audio rendition [`LiveStream::audio_rendition`] plays, the default rendition otherwise
+    pub audio_language: Option<String>,
+    /// Segments downloaded concurrently ahead of the consumer, they are still returned in order. Default is 3
+    pub prefetch: Option<usize>,
+    /// Attempts to download a failed segment again before the stream fails. Default is 2
+    pub segment_retries: Option<u32>,
+    /// Fetch segments missed between playlist reloads through URLs with their sequence number,
+    /// derived from the URI of the next segment. See [`LiveStream::gaps`]
+    pub recover_gaps: bool,
+}
+
+/// Segments missing from a [`LiveStream`], from `start` to `end` as `(discon_seq, seq)` inclusive.
+///
+/// Ends with different discontinuity sequences mean the gap spans a discontinuity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiveGap {
+    pub start: (u64, u64),
+    pub end: (u64, u64),
+    /// Every missed segment was fetched through its sequence number URL
+    pub recovered: bool,
+}
+
+/// Downloaded segment with the wall-clock time it added to the downloads, `None` for skipped segments
+type Prefetched = (
+    Segment,
+    Encryption,
+    Result<Option<(Vec<u8>, Duration)>, VideoError>,
+);
+
+/// Aborts a spawned segment download once it is no longer awaited
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Variant {
+    url: String,
+    bandwidth: u64,
+    audio_url: Option<String>,
 }
 
 pub struct LiveStream {
     client: reqwest_middleware::ClientWithMiddleware,
     stream_url: String,
-
-    last_refresh: RwLock<u128>,
+    stall_timeout: Duration,
+    variant_selection: VariantSelection,
+    adaptive: bool,
+    audio_language: Option<String>,
+    prefetch: usize,
+    segment_retries: u32,
+
+    /// Media playlist being played, `None` until `stream_url` is resolved
+    media_url: RwLock<Option<String>>,
+    /// Media playlist fetched while resolving `stream_url`, used by the first refresh
+    resolved_playlist: RwLock<Option<String>>,
+    /// Allowed variants of the master playlist by ascending bandwidth
+    variants: RwLock<Vec<Variant>>,
+    variant: RwLock<usize>,
+    /// Measured download throughput in bits per second
+    throughput: RwLock<Option<f64>>,
+    /// Time the last segment download finished, shared by the concurrent downloads
+    last_download: Arc<std::sync::Mutex<Option<Instant>>>,
+    keys: KeyCache,
+    /// Last downloaded initialization segment
+    initialization: RwLock<Option<(RemoteData, Vec<u8>)>>,
+    /// Initialization segment and discontinuity sequence last written to the output
+    emitted_initialization: RwLock<Option<(RemoteData, u64)>>,
+    /// Format of the last downloaded segment
+    format: RwLock<Option<MediaFormat>>,
+    /// Ids of segments recorded already, they are not downloaded again
+    skipped: RwLock<HashSet<String>>,
+    recover_gaps: bool,
+    gaps: RwLock<Vec<LiveGap>>,
+
+    /// Earliest time the playlist may be reloaded, `None` before the first load
+    next_reload: RwLock<Option<Instant>>,
+    /// Last time the playlist changed
+    last_change: RwLock<Instant>,
+    last_playlist: RwLock<Option<String>>,
     segments: RwLock<Vec<(Segment, Encryption)>>,
+    /// Segment downloads in playlist order
+    inflight: Mutex<FuturesOrdered<BoxFuture<'static, Prefetched>>>,
     is_end: RwLock<bool>,
     last_seg: RwLock<Option<(u64, u64)>>,
 }
 
 impl LiveStream {
     pub fn new(options: LiveStreamOptions) -> Result<Self, VideoError> {
-        let client = if options.client.is_some() {
-            options.client.unwrap()
+        let client = if let Some(client) = options.client {
+            client
         } else {
-            let client = reqwest::Client::builder()
-                .build()
-                .map_err(VideoError::Reqwest)?;
-
-            let retry_policy = reqwest_retry::policies::ExponentialBackoff::builder()
-                .retry_bounds(
-                    std::time::Duration::from_millis(1000),
-                    std::time::Duration::from_millis(30000),
-                )
-                .build_with_max_retries(DEFAULT_MAX_RETRIES);
-            reqwest_middleware::ClientBuilder::new(client)
-                .with(
-                    reqwest_retry::RetryTransientMiddleware::new_with_policy_and_strategy(
-                        retry_policy,
-                        CustomRetryableStrategy,
-                    ),
-                )
-                .build()
+            build_client(&RequestOptions::default())?
         };
 
         Ok(Self {
             client,
             stream_url: options.stream_url,
-            last_refresh: RwLock::new(0),
+            stall_timeout: options.stall_timeout.unwrap_or(DEFAULT_LIVE_STALL_TIMEOUT),
+            variant_selection: options.variant,
+            adaptive: options.adaptive,
+            audio_language: options.audio_language,
+            prefetch: options.prefetch.unwrap_or(DEFAULT_LIVE_PREFETCH).max(1),
+            segment_retries: options
+                .segment_retries
+                .unwrap_or(DEFAULT_LIVE_SEGMENT_RETRIES),
+            media_url: RwLock::new(None),
+            resolved_playlist: RwLock::new(None),
+            variants: RwLock::new(vec![]),
+            variant: RwLock::new(0),
+            throughput: RwLock::new(None),
+            last_download: Arc::new(std::sync::Mutex::new(None)),
+            keys: KeyCache::default(),
+            initialization: RwLock::new(None),
+            emitted_initialization: RwLock::new(None),
+            format: RwLock::new(None),
+            skipped: RwLock::new(HashSet::new()),
+            recover_gaps: options.recover_gaps,
+            gaps: RwLock::new(vec![]),
+            next_reload: RwLock::new(None),
+            last_change: RwLock::new(Instant::now()),
+            last_playlist: RwLock::new(None),
             segments: RwLock::new(vec![]),
+            inflight: Mutex::new(FuturesOrdered::new()),
             is_end: RwLock::new(false),
             last_seg: RwLock::new(None),
         })
     }
 
-    async fn last_refresh(&self) -> u128 {
-        *self.last_refresh.read().await
+    /// Media playlist currently played, a variant when the stream is a master playlist
+    pub async fn media_url(&self) -> Result<String, VideoError> {
+        if let Some(url) = &*self.media_url.read().await {
+            return Ok(url.clone());
+        }
+
+        let body = get_html(&self.client, &self.stream_url, None).await?;
+
+        let url = if is_master_playlist(body.as_bytes()) {
+            let master = parse_master_playlist_res(body.as_bytes())
+                .map_err(|e| VideoError::M3U8ParseError(e.to_string()))?;
+
+            let variants = self.select_variants(&master)?;
+            let Some(variant) = variants.last() else {
+                return Err(VideoError::M3U8ParseError(
+                    "Master playlist has no variants".to_string(),
+                ));
+            };
+
+            let url = variant.url.clone();
+            *self.variant.write().await = variants.len() - 1;
+            *self.variants.write().await = variants;
+            url
+        } else {
+            *self.resolved_playlist.write().await = Some(body);
+            self.stream_url.clone()
+        };
+
+        *self.media_url.write().await = Some(url.clone());
+
+        Ok(url)
+    }
+
+    /// Format of the downloaded segments, `None` before the first chunk. See [`MediaFormat::extension`]
+    pub async fn format(&self) -> Option<MediaFormat> {
+        self.format.read().await.clone()
+    }
+
+    /// Record the stream to `dir` with one file per segment instead of a single byte stream.
+    ///
+    /// Segments are written decrypted as `<Segment::id()>.<MediaFormat::extension()>`, initialization
+    /// segments as `init-<Segment::id()>.<extension>`, next to a local `index.m3u8` referring to them.
+    /// Every segment is appended to the playlist, which ends with `EXT-X-ENDLIST` once the stream ended.
+    ///
+    /// Recording to the same directory again resumes, segments already on disk are not downloaded again.
+    pub async fn download_segments<P: AsRef<Path>>(&self, dir: P) -> Result<(), VideoError> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| VideoError::DownloadError(e.to_string()))?;
+
+        let files = segment_files(dir);
+        *self.skipped.write().await = files.keys().cloned().collect();
+
+        let mut playlist = LocalPlaylist::open(dir)?;
+        // Last initialization segment written and its file name
+        let mut initialization: Option<(RemoteData, String)> = None;
+
+        while let Some((segment, encryption, data)) = self.next_segment().await? {
+            // Skipped segments keep their file, unless it vanished since it was listed
+            let existing = match data {
+                Some(_) => None,
+                None => files
+                    .get(&segment.id())
+                    .filter(|uri| dir.join(uri).is_file())
+                    .cloned(),
+            };
+
+            let uri = match existing {
+                Some(uri) => uri,
+                None => {
+                    let data = match data {
+                        Some(data) => data,
+                        None => {
+                            let (buf, _) = segment.data.fetch(&self.client).await?;
+                            self.decrypt(&segment, &encryption, &buf).await?
+                        }
+                    };
+                    let extension = MediaFormat::detect(&data).extension();
+                    let uri = format!("{}.{}", segment.id(), extension);
+                    write_file(&dir.join(&uri), &data)?;
+                    uri
+                }
+            };
+
+            if playlist.contains(&uri) {
+                continue;
+            }
+
+            let init_uri = match &segment.initialization {
+                Some(init) => match &initialization {
+                    Some((written, uri)) if written == init => Some(uri.clone()),
+                    _ => {
+                        let data = self.initialization(&segment).await?.unwrap_or_default();
+                        let extension = MediaFormat::detect(&data).extension();
+                        let uri = format!("init-{}.{}", segment.id(), extension);
+                        write_file(&dir.join(&uri), &data)?;
+                        initialization = Some((init.clone(), uri.clone()));
+                        Some(uri)
+                    }
+                },
+                None => None,
+            };
+
+            let discontinuity = playlist
+                .segments
+                .last()
+                .and_then(|x| parse_id(x.uri.split('.').next()?))
+                .is_some_and(|(discon_seq, _)| discon_seq != segment.discon_seq);
+
+            playlist.segments.push(LocalSegment {
+                uri,
+                duration: segment.duration,
+                discontinuity,
+                initialization: init_uri,
+            });
+            playlist.save(false)?;
+        }
+
+        playlist.save(true)
+    }
+
+    /// Sequence gaps detected so far, segments which left the playlist before it was reloaded.
+    ///
+    /// A stream without gaps recorded every segment between the first and the last one.
+    pub async fn gaps(&self) -> Vec<LiveGap> {
+        self.gaps.read().await.clone()
+    }
+
+    /// Mark the gap of `segment` as not recovered, `false` if the segment is not a missed one
+    async fn unrecovered(&self, segment: &Segment) -> bool {
+        let position = (segment.discon_seq, segment.seq);
+        let mut gaps = self.gaps.write().await;
+        let Some(gap) = gaps
+            .iter_mut()
+            .find(|x| x.recovered && x.start <= position && position <= x.end)
+        else {
+            return false;
+        };
+
+        gap.recovered = false;
+        true
+    }
+
+    /// Separate audio rendition (`EXT-X-MEDIA`) of the played variant.
+    ///
+    /// Variants with a separate audio rendition carry no audio, record both streams to get it.
+    /// `None` when the stream is a media playlist or the variant has its audio muxed in.
+    pub async fn audio_rendition(&self) -> Result<Option<LiveStream>, VideoError> {
+        self.media_url().await?;
+
+        let variant = *self.variant.read().await;
+        let Some(audio_url) = self
+            .variants
+            .read()
+            .await
+            .get(variant)
+            .and_then(|x| x.audio_url.clone())
+        else {
+            return Ok(None);
+        };
+
+        let mut audio = LiveStream::new(LiveStreamOptions {
+            client: Some(self.client.clone()),
+            stream_url: audio_url,
+            stall_timeout: Some(self.stall_timeout),
+            prefetch: Some(self.prefetch),
+            segment_retries: Some(self.segment_retries),
+            recover_gaps: self.recover_gaps,
+            ..Default::default()
+        })?;
+        audio.keys = self.keys.clone();
+
+        Ok(Some(audio))
+    }
+
+    /// Variants allowed by the selection by ascending bandwidth, the last one is played first
+    fn select_variants(&self, master: &MasterPlaylist) -> Result<Vec<Variant>, VideoError> {
+        let mut variants = vec![];
+        for variant in master.variants.iter().filter(|x| !x.is_i_frame) {
+            let allowed = match &self.variant_selection {
+                VariantSelection::Highest | VariantSelection::Lowest => true,
+                VariantSelection::MaxBandwidth(bandwidth) => variant.bandwidth <= *bandwidth,
+                VariantSelection::MaxHeight(height) => variant
+                    .resolution
+                    .as_ref()
+                    .map_or(true, |x| x.height <= *height),
+            };
+
+            // Audio renditions of the variant's group, by language, then default, then first
+            let renditions = master.alternatives.iter().filter(|x| {
+                x.media_type == AlternativeMediaType::Audio
+                    && x.uri.is_some()
+                    && Some(&x.group_id) == variant.audio.as_ref()
+            });
+            let rendition = renditions
+                .clone()
+                .find(|x| self.audio_language.is_some() && x.language == self.audio_language)
+                .or_else(|| renditions.clone().find(|x| x.default))
+                .or_else(|| renditions.clone().next());
+
+            let audio_url = match rendition.and_then(|x| x.uri.as_ref()) {
+                Some(uri) => Some(make_absolute_url(&self.stream_url, uri)?.to_string()),
+                None => None,
+            };
+
+            variants.push((
+                allowed,
+                Variant {
+                    url: make_absolute_url(&self.stream_url, &variant.uri)?.to_string(),
+                    bandwidth: variant.bandwidth,
+                    audio_url,
+                },
+            ));
+        }
+
+        variants.sort_by_key(|(_, x)| x.bandwidth);
+
+        let mut allowed: Vec<Variant> = variants
+            .iter()
+            .filter(|(allowed, _)| *allowed)
+            .map(|(_, x)| x.clone())
+            .collect();
+
+        // Nothing within the limits, play the lowest variant
+        if allowed.is_empty() {
+            allowed.extend(variants.into_iter().map(|(_, x)| x).take(1));
+        }
+
+        if self.variant_selection == VariantSelection::Lowest {
+            allowed.truncate(1);
+        }
+
+        Ok(allowed)
+    }
+
+    /// Switch to the highest variant the measured throughput allows
+    async fn adapt(&self) {
+        let Some(throughput) = *self.throughput.read().await else {
+            return;
+        };
+
+        let variants = self.variants.read().await;
+        let target = variants
+            .iter()
+            .rposition(|x| x.bandwidth as f64 <= throughput * ADAPTIVE_HEADROOM)
+            .unwrap_or(0);
+
+        let mut variant = self.variant.write().await;
+        if !variants.is_empty() && target != *variant {
+            *variant = target;
+            *self.media_url.write().await = Some(variants[target].url.clone());
+        }
+    }
+
+    /// Continue from `segment` on the next lower variant, `err` is returned if there is none
+    async fn step_down(&self, segment: &Segment, err: VideoError) -> Result<(), VideoError> {
+        let mut variant = self.variant.write().await;
+        if !self.adaptive || *variant == 0 {
+            return Err(err);
+        }
+
+        *variant -= 1;
+        *self.media_url.write().await = Some(self.variants.read().await[*variant].url.clone());
+
+        // Queue the segment again from the lower variant
+        self.segments.write().await.clear();
+        *self.last_seg.write().await = segment
+            .seq
+            .checked_sub(1)
+            .map(|seq| (segment.discon_seq, seq));
+        *self.is_end.write().await = false;
+        *self.next_reload.write().await = None;
+        *self.throughput.write().await = None;
+
+        Ok(())
+    }
+
+    /// Download `segment` in a task of its own, trying again after a growing delay when it fails.
+    ///
+    /// The measured time only counts while no other download finished, throughput samples cover
+    /// the bytes of all concurrent downloads over wall-clock time
+    fn download(&self, segment: Segment, encryption: Encryption) -> BoxFuture<'static, Prefetched> {
+        let client = self.client.clone();
+        let retries = self.segment_retries;
+        let last_download = self.last_download.clone();
+        let data = segment.data.clone();
+
+        let task = tokio::spawn(async move {
+            let started = Instant::now();
+            let mut attempt = 0;
+            loop {
+                match data.fetch(&client).await {
+                    Ok((data, _)) => {
+                        let finished = Instant::now();
+                        let mut last_download = last_download.lock().unwrap();
+                        let from = last_download.map_or(started, |x| x.max(started));
+                        *last_download = Some(finished);
+
+                        break Ok(Some((data, finished - from)));
+                    }
+                    Err(_) if attempt < retries => {
+                        attempt += 1;
+                        tokio::time::sleep(Duration::from_millis(250 << attempt)).await;
+                    }
+                    Err(err) => break Err(err),
+                }
+            }
+        });
+
+        Box::pin(async move {
+            // Dropped downloads, e.g. after stepping down, don't keep running
+            let mut task = AbortOnDrop(task);
+            let result = (&mut task.0)
+                .await
+                .unwrap_or_else(|err| Err(VideoError::DownloadError(err.to_string())));
+
+            (segment, encryption, result)
+        })
+    }
+
+    /// Initialization segment of `segment`, downloaded once while it stays the same
+    async fn initialization(&self, segment: &Segment) -> Result<Option<Vec<u8>>, VideoError> {
+        let Some(init) = &segment.initialization else {
+            return Ok(None);
+        };
+
+        if let Some((cached, data)) = &*self.initialization.read().await {
+            if cached == init {
+                return Ok(Some(data.clone()));
+            }
+        }
+
+        let (data, _) = init.fetch(&self.client).await?;
+        let data = segment
+            .initialization_encryption
+            .decrypt(&self.client, &self.keys, &data, None)
+            .await?;
+        *self.initialization.write().await = Some((init.clone(), data.clone()));
+
+        Ok(Some(data))
+    }
+
+    /// Next segment in playlist order with its decrypted data, `None` as data when the segment is skipped
+    async fn next_segment(
+        &self,
+    ) -> Result<Option<(Segment, Encryption, Option<Vec<u8>>)>, VideoError> {
+        let mut inflight = self.inflight.lock().await;
+
+        loop {
+            // Keep up to `prefetch` segments downloading
+            let queued = {
+                let mut segments = self.segments.write().await;
+                let count = self
+                    .prefetch
+                    .saturating_sub(inflight.len())
+                    .min(segments.len());
+                segments.drain(..count).collect::<Vec<_>>()
+            };
+            let skipped = self.skipped.read().await;
+            for (segment, encryption) in queued {
+                if skipped.contains(&segment.id()) {
+                    inflight.push_back(Box::pin(futures::future::ready((
+                        segment,
+                        encryption,
+                        Ok(None),
+                    ))));
+                } else {
+                    inflight.push_back(self.download(segment, encryption));
+                }
+            }
+            drop(skipped);
+
+            if inflight.is_empty() {
+                // Reload the playlist until it has new segments or the stream ends
+                if self.is_end().await {
+                    return Ok(None);
+                }
+
+                if let Some(next_reload) = *self.next_reload.read().await {
+                    tokio::time::sleep_until(next_reload).await;
+                }
+
+                self.refresh_playlist().await?;
+                continue;
+            }
+
+            // Look for new segments meanwhile once the playlist is due
+            let reload_due = self
+                .next_reload
+                .read()
+                .await
+                .map_or(true, |x| x <= Instant::now());
+            if reload_due && self.segments().await.is_empty() && !self.is_end().await {
+                self.refresh_playlist().await?;
+            }
+
+            let Some((segment, encryption, result)) = inflight.next().await else {
+                continue;
+            };
+
+            let (buf, elapsed) = match result {
+                Ok(Some(downloaded)) => downloaded,
+                Ok(None) => return Ok(Some((segment, encryption, None))),
+                // Missed segment which could not be recovered, the gap stays
+                Err(_) if self.unrecovered(&segment).await => continue,
+                // Continue on a lower variant instead of ending the stream
+                Err(err) => {
+                    self.step_down(&segment, err).await?;
+                    *inflight = FuturesOrdered::new();
+                    continue;
+                }
+            };
+
+            if self.adaptive {
+                let seconds = elapsed.as_secs_f64().max(0.001);
+                let sample = (buf.len() * 8) as f64 / seconds;
+                let mut throughput = self.throughput.write().await;
+                *throughput = Some(throughput.map_or(sample, |x| (x + sample) / 2.0));
+            }
+
+            let buf = self.decrypt(&segment, &encryption, &buf).await?;
+
+            return Ok(Some((segment, encryption, Some(buf))));
+        }
+    }
+
+    /// Decrypt the data bytes of `segment`, SAMPLE-AES fragmented MP4 segments need the encryption
+    /// info of their initialization segment
+    async fn decrypt(
+        &self,
+        segment: &Segment,
+        encryption: &Encryption,
+        buf: &[u8],
+    ) -> Result<Vec<u8>, VideoError> {
+        let init = match encryption {
+            Encryption::SampleAes { .. } => self.initialization(segment).await?,
+            _ => None,
+        };
+        let buf = encryption
+            .decrypt(&self.client, &self.keys, buf, init.as_deref())
+            .await?;
+
+        *self.format.write().await = Some(MediaFormat::detect(&buf));
+
+        Ok(buf)
     }
 
     async fn segments(&self) -> Vec<(Segment, Encryption)> {
@@ -79,17 +648,46 @@ impl LiveStream {
     }
 
     async fn refresh_playlist(&self) -> Result<(), VideoError> {
-        let body = get_html(&self.client, &self.stream_url, None).await?;
+        if self.adaptive {
+            self.adapt().await;
+        }
+
+        let media_url = self.media_url().await?;
+        let body = match self.resolved_playlist.write().await.take() {
+            Some(body) => body,
+            None => get_html(&self.client, &media_url, None).await?,
+        };
 
         let media_playlist = parse_media_playlist(body.as_bytes())
             .map_err(|e| VideoError::M3U8ParseError(e.to_string()))?
             .1;
 
+        // Reload after a target duration, only half of it if the playlist did not change (RFC 8216 6.3.4)
+        let target_duration = Duration::from_secs(media_playlist.target_duration.max(1));
+        let changed = self.last_playlist.read().await.as_deref() != Some(body.as_str());
+        let now = Instant::now();
+
+        *self.next_reload.write().await = Some(if changed {
+            now + target_duration
+        } else {
+            now + target_duration / 2
+        });
+
+        if changed {
+            *self.last_change.write().await = now;
+            *self.last_playlist.write().await = Some(body.clone());
+        } else if self.last_change.read().await.elapsed() >= self.stall_timeout {
+            return Err(VideoError::LiveStreamStalled);
+        }
+
         let mut cur_init = None;
+        let mut cur_init_encryption = Encryption::None;
 
         // Loop through media segments
         let mut discon_offset = 0;
-        let mut encryption = Encryption::None;
+        let mut key = None;
+        // End of the previous byte range, where a range without offset starts
+        let mut range_end: Option<(&str, u64)> = None;
         for (seq, segment) in (media_playlist.media_sequence..).zip(media_playlist.segments.iter())
         {
             // Calculate segment discontinuity
@@ -98,6 +696,38 @@ impl LiveStream {
             }
             let discon_seq = media_playlist.discontinuity_sequence + discon_offset;
 
+            // Tags apply to the following segments, including those downloaded already
+            if let Some(segment_key) = &segment.key {
+                key = Some(segment_key);
+            }
+
+            if let Some(map) = &segment.map {
+                cur_init = Some(RemoteData::new(
+                    make_absolute_url(&media_url, &map.uri)?,
+                    map.byte_range.clone(),
+                ));
+                // SAMPLE-AES leaves the initialization segment in the clear (RFC 8216 4.3.2.5)
+                cur_init_encryption = match key {
+                    Some(key) => match Encryption::new(key, &media_url, seq).await? {
+                        aes @ Encryption::Aes128 { .. } => aes,
+                        _ => Encryption::None,
+                    },
+                    None => Encryption::None,
+                };
+            }
+
+            let byte_range = segment.byte_range.clone().map(|mut range| {
+                if range.offset.is_none() {
+                    range.offset = range_end
+                        .filter(|(uri, _)| *uri == segment.uri)
+                        .map(|(_, end)| end);
+                }
+                range
+            });
+            range_end = byte_range
+                .as_ref()
+                .map(|x| (segment.uri.as_str(), x.offset.unwrap_or(0) + x.length));
+
             // Skip segment if already downloaded
             if let Some(s) = self.last_seg().await {
                 if s >= (discon_seq, seq) {
@@ -106,35 +736,73 @@ impl LiveStream {
             }
 
             // Check encryption
-            if let Some(key) = &segment.key {
-                encryption = Encryption::new(key, &self.stream_url, seq).await?;
+            let encryption = match key {
+                Some(key) => Encryption::new(key, &media_url, seq).await?,
+                None => Encryption::None,
+            };
+
+            // Segments between the last one and this one left the playlist before it was reloaded
+            if let Some((last_discon_seq, last_seq)) = self.last_seg().await {
+                if seq > last_seq + 1 {
+                    let mut gap = LiveGap {
+                        start: (last_discon_seq, last_seq + 1),
+                        end: (discon_seq, seq - 1),
+                        recovered: false,
+                    };
+
+                    // Missed segments are only known within the same discontinuity, when the URI
+                    // contains the sequence number
+                    if self.recover_gaps && last_discon_seq == discon_seq && byte_range.is_none() {
+                        let mut missed = vec![];
+                        for missed_seq in gap.start.1..=gap.end.1 {
+                            let Some(uri) = sequence_uri(&segment.uri, seq, missed_seq) else {
+                                break;
+                            };
+                            let encryption = match key {
+                                Some(key) => Encryption::new(key, &media_url, missed_seq).await?,
+                                None => Encryption::None,
+                            };
+                            missed.push((
+                                Segment {
+                                    data: RemoteData::new(
+                                        make_absolute_url(&media_url, &uri)?,
+                                        None,
+                                    ),
+                                    discon_seq,
+                                    seq: missed_seq,
+                                    duration: target_duration,
+                                    format: MediaFormat::Unknown,
+                                    initialization: cur_init.clone(),
+                                    initialization_encryption: cur_init_encryption.clone(),
+                                },
+                                encryption,
+                            ));
+                        }
+
+                        gap.recovered = !missed.is_empty();
+                        self.segments.write().await.extend(missed);
+                    }
+
+                    self.gaps.write().await.push(gap);
+                }
             }
 
             // Segment is new
             let mut mut_last_seg = self.last_seg.write().await;
             *mut_last_seg = Some((discon_seq, seq));
+            drop(mut_last_seg);
 
             // Parse URL
-            let seg_url = make_absolute_url(&self.stream_url, &segment.uri)?;
-
-            // Make Initialization
-            let init = if let Some(map) = &segment.map {
-                let init = RemoteData::new(
-                    make_absolute_url(&self.stream_url, &map.uri)?,
-                    map.byte_range.clone(),
-                );
-                cur_init = Some(init.clone());
-                Some(init)
-            } else {
-                cur_init.clone()
-            };
+            let seg_url = make_absolute_url(&media_url, &segment.uri)?;
 
             let segment = Segment {
-                data: RemoteData::new(seg_url, segment.byte_range.clone()),
+                data: RemoteData::new(seg_url, byte_range),
                 discon_seq,
                 seq,
+                duration: Duration::from_secs_f32(segment.duration.max(0.0)),
                 format: MediaFormat::Unknown,
-                initialization: init,
+                initialization: cur_init.clone(),
+                initialization_encryption: cur_init_encryption.clone(),
             };
 
             // if segments already in segment vector skip it
@@ -149,15 +817,6 @@ impl LiveStream {
             }
         }
 
-        // Set last refresh to check refresh playlist functionality
-        let mut last_refresh = self.last_refresh.write().await;
-        let start = SystemTime::now();
-        *last_refresh = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();
-        drop(last_refresh);
-
         // Set is_end bool to control chunk function
         // if stream ended
         if media_playlist.end_list {
@@ -172,76 +831,80 @@ impl LiveStream {
 #[async_trait]
 impl Stream for LiveStream {
     async fn chunk(&self) -> Result<Option<Bytes>, VideoError> {
-        let segments = self.segments().await;
-
-        // if stream end and no segments left end it
-        if self.is_end().await && segments.is_empty() {
-            return Ok(None);
-        }
-
-        let live_seconds = 20000; // refresh millis
-
-        let start = SystemTime::now();
-        let current_time = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();
+        loop {
+            let Some((segment, _, buf)) = self.next_segment().await? else {
+                return Ok(None);
+            };
+            let Some(buf) = buf else {
+                continue;
+            };
 
-        let sleep_time = current_time - self.last_refresh().await;
+            // Each initialization segment goes in front of its first media segment, and again after a
+            // discontinuity
+            let mut data = BytesMut::new();
+            if let Some(init) = &segment.initialization {
+                let emitted = (init.clone(), segment.discon_seq);
+                if self.emitted_initialization.read().await.as_ref() != Some(&emitted) {
+                    if let Some(init) = self.initialization(&segment).await? {
+                        data.extend(init);
+                    }
+                    *self.emitted_initialization.write().await = Some(emitted);
+                }
+            }
+            data.extend(buf);
 
-        // Sleep until to wait new segments uploaded to get new segments
-        if sleep_time < live_seconds && segments.is_empty() && !self.is_end().await {
-            tokio::time::sleep_until(
-                tokio::time::Instant::now()
-                    + Duration::from_millis((live_seconds - sleep_time) as u64),
-            )
-            .await;
+            return Ok(Some(data.into()));
         }
+    }
+}
 
-        let start = SystemTime::now();
-        let current_time = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();
-
-        // if last refresh bigger than live_seconds refresh playlist
-        if current_time - self.last_refresh().await >= live_seconds && !self.is_end().await {
-            self.refresh_playlist().await?;
+/// `uri` of segment `known` with its sequence number replaced by `seq`, keeping zero padding.
+/// `None` when the URI does not contain the sequence number
+fn sequence_uri(uri: &str, known: u64, seq: u64) -> Option<String> {
+    let bytes = uri.as_bytes();
+    let mut end = uri.len();
+
+    // Last number in the URI with the value of the known sequence number
+    while end > 0 {
+        if !bytes[end - 1].is_ascii_digit() {
+            end -= 1;
+            continue;
         }
 
-        // cannot get any segments return empty buffer array
-        let segments = self.segments().await;
-        if segments.is_empty() {
-            return Ok(Some(Bytes::new()));
+        let mut start = end;
+        while start > 0 && bytes[start - 1].is_ascii_digit() {
+            start -= 1;
         }
 
-        let first_segment = segments.first().unwrap();
-
-        let headers = DEFAULT_HEADERS.clone();
-
-        let mut response = self
-            .client
-            .get(first_segment.0.url().as_str())
-            .headers(headers)
-            .send()
-            .await
-            .map_err(VideoError::ReqwestMiddleware)?
-            .error_for_status()
-            .map_err(VideoError::Reqwest)?;
-
-        let mut buf: BytesMut = BytesMut::new();
-
-        while let Some(chunk) = response.chunk().await.map_err(VideoError::Reqwest)? {
-            buf.extend(chunk);
+        let number = &uri[start..end];
+        if number.parse::<u64>().ok() == Some(known) {
+            let width = if number.starts_with('0') {
+                number.len()
+            } else {
+                0
+            };
+            return Some(format!("{}{:0width$}{}", &uri[..start], seq, &uri[end..]));
         }
+        end = start;
+    }
 
-        // Decrypt data bytes
-        buf = BytesMut::from_iter(first_segment.1.decrypt(&self.client, &buf).await?);
-
-        // Delete downloaded segment from segments array
-        let mut segment_vector = self.segments.write().await;
-        segment_vector.remove(0);
+    None
+}
 
-        Ok(Some(buf.into()))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequence_uris() {
+        assert_eq!(
+            sequence_uri("segment-12.ts?v=3", 12, 9).as_deref(),
+            Some("segment-9.ts?v=3")
+        );
+        assert_eq!(
+            sequence_uri("/sq/0042/chunk.ts", 42, 40).as_deref(),
+            Some("/sq/0040/chunk.ts")
+        );
+        assert_eq!(sequence_uri("chunk-a.ts", 12, 9), None);
     }
 }
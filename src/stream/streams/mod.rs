@@ -1,4 +1,6 @@
 #[cfg(feature = "live")]
+mod dash_live;
+#[cfg(feature = "live")]
 mod live;
 mod non_live;
 
@@ -9,27 +11,37 @@ use bytes::Bytes;
 use bytes::BytesMut;
 
 #[cfg(feature = "ffmpeg")]
-use std::{process::Stdio, sync::Arc};
+use std::{collections::VecDeque, process::Stdio, sync::Arc};
 
 #[cfg(feature = "ffmpeg")]
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    process::{Child, Command},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, Command},
     sync::{
         mpsc::{channel, Receiver},
-        Mutex, Notify,
+        oneshot, Mutex, Notify, RwLock,
     },
     task::JoinHandle,
 };
 
 #[cfg(feature = "live")]
-pub use live::{LiveStream, LiveStreamOptions};
+pub use dash_live::{DashLiveStream, DashLiveStreamOptions, LiveFromStart};
+#[cfg(feature = "live")]
+pub use live::{LiveGap, LiveStream, LiveStreamOptions, VariantSelection};
 pub use non_live::{NonLiveStream, NonLiveStreamOptions};
 
 #[cfg(feature = "ffmpeg")]
-use crate::constants::DEFAULT_HEADERS;
+use crate::constants::{DEFAULT_HEADERS, FFMPEG_STDERR_LINES};
+#[cfg(feature = "ffmpeg")]
+use crate::structs::FFmpegProgress;
 use crate::VideoError;
 
+/// Resolves a fresh media link of the same format once the current one expired
+#[async_trait]
+pub trait LinkRefresher: Send + Sync {
+    async fn refresh(&self) -> Result<String, VideoError>;
+}
+
 #[async_trait]
 pub trait Stream {
     /// Stream a chunk of the [`Bytes`]
@@ -43,6 +55,12 @@ pub trait Stream {
     fn content_length(&self) -> usize {
         0
     }
+
+    /// Last progress ffmpeg reported, `None` without [`FFmpegArgs`](crate::FFmpegArgs) or before the first report
+    #[cfg(feature = "ffmpeg")]
+    async fn ffmpeg_progress(&self) -> Option<FFmpegProgress> {
+        None
+    }
 }
 
 #[cfg(feature = "ffmpeg")]
@@ -54,13 +72,17 @@ pub struct FFmpegStreamOptions {
     pub start: u64,
     pub end: u64,
     pub ffmpeg_args: Vec<String>,
+    /// Updated with each progress report of ffmpeg
+    pub progress: Arc<RwLock<Option<FFmpegProgress>>>,
+    /// Resolves a fresh `link` when the current one is rejected with `403`
+    pub refresher: Option<Arc<dyn LinkRefresher>>,
 }
 
 #[cfg(feature = "ffmpeg")]
 pub(crate) struct FFmpegStream {
-    pub refined_data_reciever: Option<Arc<Mutex<Receiver<Bytes>>>>,
+    /// Output of ffmpeg, ends with an error if ffmpeg failed
+    pub refined_data_reciever: Option<Arc<Mutex<Receiver<Result<Bytes, VideoError>>>>>,
     download_notify: Arc<Notify>,
-    ffmpeg_child: Child,
 
     tasks: Vec<JoinHandle<Result<(), VideoError>>>,
 }
@@ -69,19 +91,24 @@ pub(crate) struct FFmpegStream {
 impl FFmpegStream {
     pub fn new(options: FFmpegStreamOptions) -> Result<Self, VideoError> {
         let (tx, mut rx) = channel::<Bytes>(16384);
-        let (refined_tx, refined_rx) = channel::<Bytes>(16384);
+        let (refined_tx, refined_rx) = channel::<Result<Bytes, VideoError>>(16384);
 
         // Spawn FFmpeg process
         let mut ffmpeg_child = Command::new("ffmpeg")
             .args(&options.ffmpeg_args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .kill_on_drop(true)
             .spawn()
             .map_err(|x| VideoError::FFmpeg(x.to_string()))?;
 
         let mut stdin = ffmpeg_child.stdin.take().unwrap();
         let mut stdout = ffmpeg_child.stdout.take().unwrap();
+        let stderr = ffmpeg_child.stderr.take().unwrap();
+
+        let read_stderr_task = read_ffmpeg_stderr(stderr, options.progress.clone());
+        let (download_result_tx, download_result_rx) = oneshot::channel();
 
         let read_stdout_task = tokio::spawn(async move {
             let mut buffer = vec![0u8; 16384];
@@ -91,7 +118,8 @@ impl FFmpegStream {
                         break;
                     }
                     n => {
-                        if let Err(_err) = refined_tx.send(Bytes::from(buffer[..n].to_vec())).await
+                        if let Err(_err) =
+                            refined_tx.send(Ok(Bytes::from(buffer[..n].to_vec()))).await
                         {
                             return Err(VideoError::FFmpeg("channel closed".to_string()));
                             // Error or channel closed
@@ -100,6 +128,14 @@ impl FFmpegStream {
                 }
             }
 
+            // Output ended, a failed ffmpeg only looks like a short stream otherwise
+            if let Err(err) = wait_ffmpeg(ffmpeg_child, read_stderr_task).await {
+                let _ = refined_tx.send(Err(err)).await;
+            // ffmpeg ends normally when its input is cut off by a failed download
+            } else if let Ok(Err(err)) = download_result_rx.await {
+                let _ = refined_tx.send(Err(err)).await;
+            }
+
             Ok(())
         });
 
@@ -116,78 +152,156 @@ impl FFmpegStream {
         let download_notify_task = download_notify.clone();
 
         let download_task = tokio::spawn(async move {
-            let mut end = options.end;
-            let mut start = options.start;
-            let content_length = options.content_length;
-            let client = options.client;
-            let link = options.link;
-            let dl_chunk_size = options.dl_chunk_size;
-
-            download_notify_task.notified().await;
-
-            loop {
-                // Nothing else remain send break to finish
-                if end == 0 {
-                    break;
-                }
+            let result = download_ffmpeg_input(options, tx, download_notify_task).await;
+            let _ = download_result_tx.send(result);
+            Ok(())
+        });
 
-                if end >= content_length {
-                    end = 0;
-                }
+        Ok(Self {
+            refined_data_reciever: Some(Arc::new(Mutex::new(refined_rx))),
+            download_notify,
+            tasks: vec![download_task, write_stdin_task, read_stdout_task],
+        })
+    }
 
-                let mut headers = DEFAULT_HEADERS.clone();
+    pub fn start_download(&self) {
+        self.download_notify.notify_one();
+    }
+}
 
-                let range_end = if end == 0 {
-                    "".to_string()
-                } else {
-                    end.to_string()
-                };
+/// Download the input of ffmpeg chunk by chunk into `tx` once notified
+#[cfg(feature = "ffmpeg")]
+async fn download_ffmpeg_input(
+    options: FFmpegStreamOptions,
+    tx: tokio::sync::mpsc::Sender<Bytes>,
+    download_notify: Arc<Notify>,
+) -> Result<(), VideoError> {
+    let mut end = options.end;
+    let mut start = options.start;
+    let content_length = options.content_length;
+    let client = options.client;
+    let mut link = options.link;
+    let refresher = options.refresher;
+    let dl_chunk_size = options.dl_chunk_size;
+
+    download_notify.notified().await;
+
+    loop {
+        // Nothing else remain send break to finish
+        if end == 0 {
+            break;
+        }
 
-                headers.insert(
-                    reqwest::header::RANGE,
-                    format!("bytes={}-{}", start, range_end).parse().unwrap(),
-                );
+        if end >= content_length {
+            end = 0;
+        }
 
-                let mut response = client
+        let mut headers = DEFAULT_HEADERS.clone();
+
+        let range_end = if end == 0 {
+            "".to_string()
+        } else {
+            end.to_string()
+        };
+
+        headers.insert(
+            reqwest::header::RANGE,
+            format!("bytes={}-{}", start, range_end).parse().unwrap(),
+        );
+
+        let mut response = client
+            .get(&link)
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(VideoError::ReqwestMiddleware)?;
+
+        // Media links expire, resume the same range with a fresh one
+        if response.status() == reqwest::StatusCode::FORBIDDEN {
+            if let Some(refresher) = &refresher {
+                link = refresher.refresh().await?;
+                response = client
                     .get(&link)
                     .headers(headers)
                     .send()
                     .await
-                    .map_err(VideoError::ReqwestMiddleware)?
-                    .error_for_status()
-                    .map_err(VideoError::Reqwest)?;
+                    .map_err(VideoError::ReqwestMiddleware)?;
+            }
+        }
 
-                let mut buf: BytesMut = BytesMut::new();
+        let mut response = response.error_for_status().map_err(VideoError::Reqwest)?;
 
-                while let Some(chunk) = response.chunk().await.map_err(VideoError::Reqwest)? {
-                    buf.extend(chunk);
-                }
+        let mut buf: BytesMut = BytesMut::new();
 
-                if end != 0 {
-                    start = end + 1;
+        while let Some(chunk) = response.chunk().await.map_err(VideoError::Reqwest)? {
+            buf.extend(chunk);
+        }
 
-                    end += dl_chunk_size;
-                }
+        if end != 0 {
+            start = end + 1;
 
-                tx.send(buf.into())
-                    .await
-                    .map_err(|x| VideoError::FFmpeg(x.to_string()))?;
+            end += dl_chunk_size;
+        }
+
+        // ffmpeg stopped reading, its own result is reported
+        if tx.send(buf.into()).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read ffmpeg's stderr, publishing progress reports to `progress` once complete.
+/// Returns the last lines of everything else for errors
+#[cfg(feature = "ffmpeg")]
+pub(crate) fn read_ffmpeg_stderr(
+    stderr: ChildStderr,
+    progress: Arc<RwLock<Option<FFmpegProgress>>>,
+) -> JoinHandle<String> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut report = FFmpegProgress::default();
+        let mut tail = VecDeque::with_capacity(FFMPEG_STDERR_LINES);
+
+        while let Ok(Some(line)) = lines.next_line().await {
+            if report.update(&line) {
+                if line.starts_with("progress=") {
+                    *progress.write().await = Some(report.clone());
+                }
+            } else if !line.trim().is_empty() {
+                if tail.len() == FFMPEG_STDERR_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
             }
+        }
 
-            Ok(())
-        });
+        Vec::from(tail).join("\n")
+    })
+}
 
-        Ok(Self {
-            refined_data_reciever: Some(Arc::new(Mutex::new(refined_rx))),
-            download_notify,
-            ffmpeg_child,
-            tasks: vec![download_task, write_stdin_task, read_stdout_task],
-        })
+/// Wait for ffmpeg to exit, [`VideoError::FFmpeg`] with the end of its log if it failed
+#[cfg(feature = "ffmpeg")]
+pub(crate) async fn wait_ffmpeg(
+    mut child: Child,
+    stderr: JoinHandle<String>,
+) -> Result<(), VideoError> {
+    let status = child
+        .wait()
+        .await
+        .map_err(|x| VideoError::FFmpeg(x.to_string()))?;
+    let stderr = stderr.await.unwrap_or_default();
+
+    if status.success() {
+        return Ok(());
     }
 
-    pub fn start_download(&self) {
-        self.download_notify.notify_one();
-    }
+    Err(VideoError::FFmpeg(if stderr.is_empty() {
+        format!("ffmpeg {status}")
+    } else {
+        format!("ffmpeg {status}: {stderr}")
+    }))
 }
 
 #[cfg(feature = "ffmpeg")]
@@ -199,3 +313,32 @@ impl Drop for FFmpegStream {
         }
     }
 }
+
+#[cfg(all(test, feature = "ffmpeg"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_progress_report() {
+        let mut progress = FFmpegProgress::default();
+        let report = "frame=120\nfps=59.94\nstream_0_0_q=28.0\nbitrate= 128.1kbits/s\ntotal_size=262188\nout_time_us=16373878\nout_time_ms=16373878\nout_time=00:00:16.373878\ndup_frames=0\ndrop_frames=0\nspeed=2.51x\nprogress=end";
+
+        assert!(report.lines().all(|line| progress.update(line)));
+        assert!(!progress.update("[mp3 @ 0x55d5] Invalid data found"));
+        assert_eq!(
+            progress,
+            FFmpegProgress {
+                time: Some(Duration::from_micros(16373878)),
+                speed: Some(2.51),
+                bitrate: Some(128.1),
+                total_size: Some(262188),
+                frame: Some(120),
+                ended: true,
+            }
+        );
+
+        assert!(progress.update("bitrate=N/A"));
+        assert_eq!(progress.bitrate, None);
+    }
+}
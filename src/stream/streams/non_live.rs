@@ -1,23 +1,27 @@
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-
-#[cfg(feature = "ffmpeg")]
-use std::sync::Arc;
+use reqwest::{header::HeaderMap, StatusCode};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 #[cfg(feature = "ffmpeg")]
 use tokio::sync::Mutex;
 use tokio::sync::RwLock;
 
-use crate::constants::{DEFAULT_HEADERS, DEFAULT_MAX_RETRIES};
-use crate::stream::streams::Stream;
-use crate::structs::{CustomRetryableStrategy, VideoError};
+use crate::constants::{DEFAULT_DL_CHUNK_SIZE, DEFAULT_HEADERS, LINK_EXPIRY_MARGIN};
+use crate::stream::streams::{LinkRefresher, Stream};
+use crate::structs::{RequestOptions, VideoError};
+use crate::utils::build_client;
 
 #[cfg(feature = "ffmpeg")]
-use crate::structs::FFmpegArgs;
+use crate::structs::{FFmpegArgs, FFmpegProgress};
 
 #[cfg(feature = "ffmpeg")]
 use super::{FFmpegStream, FFmpegStreamOptions};
 
+/// Fill the fields not set with `..Default::default()`, more may be added
 pub struct NonLiveStreamOptions {
     pub client: Option<reqwest_middleware::ClientWithMiddleware>,
     pub link: String,
@@ -25,13 +29,32 @@ pub struct NonLiveStreamOptions {
     pub dl_chunk_size: u64,
     pub start: u64,
     pub end: u64,
+    /// Resolves a fresh `link` when the current one is rejected with `403` or about to expire
+    pub refresher: Option<Arc<dyn LinkRefresher>>,
 
     #[cfg(feature = "ffmpeg")]
     pub ffmpeg_args: Option<FFmpegArgs>,
 }
 
+impl Default for NonLiveStreamOptions {
+    fn default() -> Self {
+        Self {
+            client: None,
+            link: String::new(),
+            content_length: 0,
+            dl_chunk_size: DEFAULT_DL_CHUNK_SIZE,
+            start: 0,
+            end: DEFAULT_DL_CHUNK_SIZE,
+            refresher: None,
+            #[cfg(feature = "ffmpeg")]
+            ffmpeg_args: None,
+        }
+    }
+}
+
 pub struct NonLiveStream {
-    link: String,
+    link: RwLock<String>,
+    refresher: Option<Arc<dyn LinkRefresher>>,
     content_length: u64,
     dl_chunk_size: u64,
     start: RwLock<u64>,
@@ -46,40 +69,30 @@ pub struct NonLiveStream {
 
     #[cfg(feature = "ffmpeg")]
     ffmpeg_stream: Arc<Mutex<Option<FFmpegStream>>>,
+
+    #[cfg(feature = "ffmpeg")]
+    ffmpeg_progress: Arc<RwLock<Option<FFmpegProgress>>>,
 }
 
 impl NonLiveStream {
     pub fn new(options: NonLiveStreamOptions) -> Result<Self, VideoError> {
-        let client = if options.client.is_some() {
-            options.client.unwrap()
+        let client = if let Some(client) = options.client {
+            client
         } else {
-            let client = reqwest::Client::builder()
-                .build()
-                .map_err(VideoError::Reqwest)?;
-
-            let retry_policy = reqwest_retry::policies::ExponentialBackoff::builder()
-                .retry_bounds(
-                    std::time::Duration::from_millis(1000),
-                    std::time::Duration::from_millis(30000),
-                )
-                .build_with_max_retries(DEFAULT_MAX_RETRIES);
-            reqwest_middleware::ClientBuilder::new(client)
-                .with(
-                    reqwest_retry::RetryTransientMiddleware::new_with_policy_and_strategy(
-                        retry_policy,
-                        CustomRetryableStrategy,
-                    ),
-                )
-                .build()
+            build_client(&RequestOptions::default())?
         };
 
         #[cfg(feature = "ffmpeg")]
         {
-            let ffmpeg_args = options
-                .ffmpeg_args
-                .clone()
-                .map(|x| x.build())
-                .unwrap_or_default();
+            let ffmpeg_args = match &options.ffmpeg_args {
+                Some(ffmpeg_args) => {
+                    ffmpeg_args.validate()?;
+                    ffmpeg_args.build()
+                }
+                None => vec![],
+            };
+
+            let ffmpeg_progress = Arc::new(RwLock::new(None));
 
             let ffmpeg_stream = if !ffmpeg_args.is_empty() {
                 Arc::new(Mutex::new(Some(FFmpegStream::new(FFmpegStreamOptions {
@@ -90,6 +103,8 @@ impl NonLiveStream {
                     start: options.start,
                     end: options.end,
                     ffmpeg_args: ffmpeg_args.clone(),
+                    progress: ffmpeg_progress.clone(),
+                    refresher: options.refresher.clone(),
                 })?)))
             } else {
                 Arc::new(Mutex::new(None))
@@ -97,7 +112,8 @@ impl NonLiveStream {
 
             Ok(Self {
                 client,
-                link: options.link,
+                link: RwLock::new(options.link),
+                refresher: options.refresher,
                 content_length: options.content_length,
                 dl_chunk_size: options.dl_chunk_size,
                 start: RwLock::new(options.start),
@@ -106,6 +122,7 @@ impl NonLiveStream {
                 end_static: options.end,
                 ffmpeg_args,
                 ffmpeg_stream,
+                ffmpeg_progress,
             })
         }
 
@@ -113,7 +130,8 @@ impl NonLiveStream {
         {
             Ok(Self {
                 client,
-                link: options.link,
+                link: RwLock::new(options.link),
+                refresher: options.refresher,
                 content_length: options.content_length,
                 dl_chunk_size: options.dl_chunk_size,
                 start: RwLock::new(options.start),
@@ -135,6 +153,48 @@ impl NonLiveStream {
     async fn start_index(&self) -> u64 {
         *self.start.read().await
     }
+
+    async fn send(&self, headers: &HeaderMap) -> Result<reqwest::Response, VideoError> {
+        let link = self.link.read().await.clone();
+
+        self.client
+            .get(link)
+            .headers(headers.clone())
+            .send()
+            .await
+            .map_err(VideoError::ReqwestMiddleware)
+    }
+
+    async fn refresh_link(&self) -> Result<(), VideoError> {
+        if let Some(refresher) = &self.refresher {
+            let link = refresher.refresh().await?;
+            *self.link.write().await = link;
+        }
+
+        Ok(())
+    }
+
+    /// Link expires within [`LINK_EXPIRY_MARGIN`], only checked if the link can be refreshed
+    async fn is_link_expiring(&self) -> bool {
+        if self.refresher.is_none() {
+            return false;
+        }
+
+        let expire = url::Url::parse(&self.link.read().await)
+            .ok()
+            .and_then(|link| {
+                link.query_pairs()
+                    .find(|(name, _)| name == "expire")
+                    .and_then(|(_, value)| value.parse::<u64>().ok())
+            });
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        expire.is_some_and(|expire| expire <= now + LINK_EXPIRY_MARGIN.as_secs())
+    }
 }
 
 #[async_trait]
@@ -153,19 +213,21 @@ impl Stream for NonLiveStream {
                         let byte_value = reciever.recv().await;
 
                         // reset ffmpeg_stream for reuse
-                        if byte_value.is_none() {
+                        if !matches!(byte_value, Some(Ok(_))) {
                             *ffmpeg_stream = FFmpegStream::new(FFmpegStreamOptions {
                                 client: self.client.clone(),
-                                link: self.link.clone(),
+                                link: self.link.read().await.clone(),
                                 content_length: self.content_length,
                                 dl_chunk_size: self.dl_chunk_size,
                                 start: self.start_static,
                                 end: self.end_static,
                                 ffmpeg_args: self.ffmpeg_args.clone(),
+                                progress: self.ffmpeg_progress.clone(),
+                                refresher: self.refresher.clone(),
                             })?;
                         }
 
-                        return Ok(byte_value);
+                        return byte_value.transpose();
                     }
                 }
             }
@@ -205,15 +267,19 @@ impl Stream for NonLiveStream {
                 .unwrap(),
         );
 
-        let mut response = self
-            .client
-            .get(&self.link)
-            .headers(headers)
-            .send()
-            .await
-            .map_err(VideoError::ReqwestMiddleware)?
-            .error_for_status()
-            .map_err(VideoError::Reqwest)?;
+        if self.is_link_expiring().await {
+            self.refresh_link().await?;
+        }
+
+        let mut response = self.send(&headers).await?;
+
+        // Media links expire, resume the same range with a fresh one
+        if response.status() == StatusCode::FORBIDDEN && self.refresher.is_some() {
+            self.refresh_link().await?;
+            response = self.send(&headers).await?;
+        }
+
+        let mut response = response.error_for_status().map_err(VideoError::Reqwest)?;
 
         let mut buf: BytesMut = BytesMut::new();
 
@@ -234,4 +300,9 @@ impl Stream for NonLiveStream {
     fn content_length(&self) -> usize {
         self.content_length() as usize
     }
+
+    #[cfg(feature = "ffmpeg")]
+    async fn ffmpeg_progress(&self) -> Option<FFmpegProgress> {
+        self.ffmpeg_progress.read().await.clone()
+    }
 }
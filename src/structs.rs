@@ -10,6 +10,18 @@ use std::{
     ops::{Bound, RangeBounds},
     str::FromStr,
     sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    auth::OAuth,
+    constants::{
+        DEFAULT_JS_LOOP_ITERATION_LIMIT, DEFAULT_JS_RECURSION_LIMIT, DEFAULT_JS_STACK_SIZE_LIMIT,
+        DEFAULT_MAX_RETRY_AFTER, DEFAULT_MAX_RETRY_INTERVAL, DEFAULT_MIN_RETRY_INTERVAL,
+    },
+    cookies::CookieJar,
+    interpreter::{BoaRuntime, JsRuntime},
+    pool::{AddressPool, ProxyPool},
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +35,9 @@ pub struct VideoInfo {
     pub related_videos: Vec<RelatedVideo>,
     #[serde(rename = "videoDetails")]
     pub video_details: VideoDetails,
+    /// Diagnostics about how the format URLs were deciphered
+    #[serde(rename = "cipherReport", default)]
+    pub cipher_report: CipherReport,
 }
 
 #[derive(Clone, derive_more::Display)]
@@ -137,6 +152,7 @@ pub struct VideoOptions {
     pub download_options: DownloadOptions,
     #[derivative(PartialEq = "ignore")]
     pub request_options: RequestOptions,
+    pub cipher_options: CipherOptions,
 }
 
 impl Default for VideoOptions {
@@ -146,6 +162,7 @@ impl Default for VideoOptions {
             filter: VideoSearchOptions::Audio,
             download_options: DownloadOptions::default(),
             request_options: RequestOptions::default(),
+            cipher_options: CipherOptions::default(),
         }
     }
 }
@@ -168,6 +185,125 @@ impl From<VideoOptions> for Cow<'static, VideoOptions> {
 pub struct DownloadOptions {
     /// Maximum chunk size on per request
     pub dl_chunk_size: Option<u64>,
+    /// Tag files written by [`Video::download`](crate::Video::download) with the details of the video:
+    /// ID3v2 for MP3, `ilst` atoms for MP4 and Tags for WebM/Matroska. Other formats are left untouched
+    pub tags: Option<TagOptions>,
+}
+
+/// What is written into downloaded files besides title, author, publish date, description and URL,
+/// see [`DownloadOptions::tags`]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TagOptions {
+    /// Embed the largest JPEG or PNG thumbnail as cover art. Default is `true`
+    pub cover_art: bool,
+    /// Embed the chapters of the video. Default is `true`
+    pub chapters: bool,
+}
+
+impl Default for TagOptions {
+    fn default() -> Self {
+        Self {
+            cover_art: true,
+            chapters: true,
+        }
+    }
+}
+
+/// Signature and n-parameter deciphering options
+#[derive(Clone, derive_more::Display, derivative::Derivative)]
+#[display(
+    "CipherOptions(strict: {strict}, JS runtime: {}, JS limits: {js_limits})",
+    js_runtime.name()
+)]
+#[derivative(Debug, PartialEq)]
+pub struct CipherOptions {
+    /// Fail with [`VideoError::CipherExtractionFailed`] instead of returning untransformed
+    /// format URLs when the player functions can't be found or executed
+    pub strict: bool,
+    /// Runtime budget of the player functions
+    pub js_limits: JsLimits,
+    /// Engine running the player functions. Default is [`BoaRuntime`]
+    #[derivative(PartialEq = "ignore")]
+    pub js_runtime: Arc<dyn JsRuntime>,
+}
+
+impl Default for CipherOptions {
+    fn default() -> Self {
+        CipherOptions {
+            strict: false,
+            js_limits: JsLimits::default(),
+            js_runtime: Arc::new(BoaRuntime),
+        }
+    }
+}
+
+/// Runtime budget of the JavaScript extracted from the player, exceeding it aborts the call
+/// with [`VideoError::JsBudgetExceeded`]
+#[derive(Clone, PartialEq, Debug, derive_more::Display)]
+#[display(
+    "JsLimits(loop iterations: {loop_iteration_limit}, recursion: {recursion_limit}, stack size: {stack_size_limit})"
+)]
+pub struct JsLimits {
+    /// Maximum iterations of a single loop
+    pub loop_iteration_limit: u64,
+    /// Maximum depth of nested function calls
+    pub recursion_limit: usize,
+    /// Maximum number of values on the interpreter stack
+    pub stack_size_limit: usize,
+}
+
+impl Default for JsLimits {
+    fn default() -> Self {
+        JsLimits {
+            loop_iteration_limit: DEFAULT_JS_LOOP_ITERATION_LIMIT,
+            recursion_limit: DEFAULT_JS_RECURSION_LIMIT,
+            stack_size_limit: DEFAULT_JS_STACK_SIZE_LIMIT,
+        }
+    }
+}
+
+/// Retry behaviour of web requests, the number of retries is set by [`RequestOptions::max_retries`]
+#[derive(Clone, PartialEq, Debug, derive_more::Display)]
+#[display(
+    "RetryPolicy(interval: {min_retry_interval:?}..{max_retry_interval:?}, retryable statuses: {retryable_statuses:?})"
+)]
+pub struct RetryPolicy {
+    /// Backoff before the first retry, doubled on every further retry
+    pub min_retry_interval: Duration,
+    /// Upper bound of the backoff
+    pub max_retry_interval: Duration,
+    /// Randomize each backoff between [`RetryPolicy::min_retry_interval`] and the computed backoff
+    pub jitter: bool,
+    /// Response statuses which are retried. `None` retries `408 Request Timeout`, `429 Too Many Requests`
+    /// and server errors.
+    ///
+    /// Other client errors such as `403 Forbidden` are no longer retried by default, retrying them
+    /// rarely helps and expired stream links are renewed by a [`LinkRefresher`](crate::stream::LinkRefresher)
+    /// instead. `Some((400..600).collect())` retries every error status like earlier versions did
+    pub retryable_statuses: Option<Vec<u16>>,
+    /// Minimum backoff after a `429 Too Many Requests` response without `Retry-After`
+    pub rate_limit_retry_interval: Duration,
+    /// Wait as long as the `Retry-After` header of a retryable response asks, in seconds or as an HTTP date
+    pub respect_retry_after: bool,
+    /// Longest `Retry-After` to wait for, longer ones are not retried
+    pub max_retry_after: Duration,
+    /// Timeout of every single attempt, overrides the client timeout if set
+    pub request_timeout: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            min_retry_interval: DEFAULT_MIN_RETRY_INTERVAL,
+            max_retry_interval: DEFAULT_MAX_RETRY_INTERVAL,
+            jitter: true,
+            retryable_statuses: None,
+            rate_limit_retry_interval: DEFAULT_MIN_RETRY_INTERVAL,
+            respect_retry_after: true,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            request_timeout: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, derive_more::Display)]
@@ -175,7 +311,7 @@ pub struct DownloadOptions {
     "RequestOptions(cookies: {cookies:?}, IPv6: {ipv6_block:?}, max retries: {max_retries:?})"
 )]
 pub struct RequestOptions {
-    /// [`reqwest::Client`] to on use request. If provided in the request options `proxy`, `cookies`, and `ipv6_block` will be ignored
+    /// [`reqwest::Client`] to on use request. If provided in the request options `proxy`, `cookies`, `cookie_jar` and `ipv6_block` will be ignored
     ///
     /// # Example
     ///
@@ -210,13 +346,28 @@ pub struct RequestOptions {
     ///     };
     /// ```
     pub proxy: Option<reqwest::Proxy>,
-    /// Cookies String
+    /// Cookies String, ignored if `cookie_jar` is provided
     ///
     /// # Example
     /// ```ignore
     /// Some("key1=value1; key2=value2; key3=value3".to_string())
     /// ```
     pub cookies: Option<String>,
+    /// [`CookieJar`] storing the cookies set by YouTube, shared by every client built from these options
+    ///
+    /// # Example
+    /// ```ignore
+    ///     let video_options = VideoOptions {
+    ///         request_options: RequestOptions {
+    ///             cookie_jar: Some(CookieJar::load("cookies.txt")?.autosave(true)),
+    ///             ..Default::default()
+    ///         },
+    ///         ..Default::default()
+    ///     };
+    /// ```
+    pub cookie_jar: Option<CookieJar>,
+    /// [`OAuth`] login whose access token is sent with innertube requests. Applies to provided clients too
+    pub auth: Option<OAuth>,
     /// Custom IPv6 String
     ///
     /// # Example
@@ -233,6 +384,10 @@ pub struct RequestOptions {
     ///     };
     /// ```
     pub ipv6_block: Option<String>,
+    /// Proxies to rotate requests over, takes precedence over [`RequestOptions::proxy`]. See [`crate::ProxyPool`]
+    pub proxy_pool: Option<ProxyPool>,
+    /// IPv6 block to rotate the source address of requests over, takes precedence over [`RequestOptions::ipv6_block`]. See [`crate::AddressPool`]
+    pub address_pool: Option<AddressPool>,
     /// Number of retries to allow per web request (ie, per chunk downloaded)
     /// Default is [`crate::constants::DEFAULT_MAX_RETRIES`].
     ///
@@ -248,6 +403,23 @@ pub struct RequestOptions {
     ///     };
     /// ```
     pub max_retries: Option<u32>,
+    /// Backoff and retryability of web requests, see [`RetryPolicy`]
+    ///
+    /// # Example
+    /// ```ignore
+    ///     // Wait at least 10 seconds before retrying a rate limited request
+    ///     let video_options = VideoOptions {
+    ///          request_options: RequestOptions {
+    ///               retry_policy: RetryPolicy {
+    ///                    rate_limit_retry_interval: Duration::from_secs(10),
+    ///                    ..Default::default()
+    ///               },
+    ///                ..Default::default()
+    ///          },
+    ///          ..Default::default()
+    ///     };
+    /// ```
+    pub retry_policy: RetryPolicy,
     /// Supply a YouTube Proof of Origin token. Use at your own risk.
     /// See https://github.com/yt-dlp/yt-dlp/wiki/Extractors#po-token-guide for more information.
     pub po_token: Option<String>,
@@ -267,6 +439,41 @@ pub enum VideoError {
     /// Video player response errors
     #[error("Player Response Error: {0}")]
     VideoPlayerResponseError(String),
+    /// Age restricted video which can't be played without logging in
+    #[error("Video is age restricted, login required")]
+    AgeRestricted { reason: Option<String> },
+    /// Video is not available in the country of the request
+    #[error("Video is not available in this country, available in {} countries", available_countries.len())]
+    GeoBlocked {
+        /// ISO 3166 codes of the countries the video is available in
+        available_countries: Vec<String>,
+        reason: Option<String>,
+    },
+    /// Video is only available to members of the channel
+    #[error("Video is available to channel members only")]
+    MembersOnly { reason: Option<String> },
+    /// Premiere or live stream which has not started yet
+    #[error("Premiere or live stream has not started yet")]
+    PremiereScheduled {
+        /// Unix timestamp of the scheduled start
+        scheduled_start_time: Option<u64>,
+        reason: Option<String>,
+    },
+    /// Video was taken down
+    #[error("Video was removed ({kind})")]
+    VideoRemoved {
+        kind: RemovalKind,
+        reason: Option<String>,
+    },
+    /// YouTube requires logging in, usually to confirm the request is not from a bot
+    #[error("Login required: {}", reason.as_deref().unwrap_or("unknown reason"))]
+    LoginRequired { reason: Option<String> },
+    /// Rental or otherwise paid video
+    #[error("Video requires payment")]
+    PaymentRequired { reason: Option<String> },
+    /// Live stream ended without a recording to play
+    #[error("Live stream ended without a recording")]
+    LiveEndedWithoutArchive { reason: Option<String> },
     /// Reqwest error
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
@@ -315,15 +522,158 @@ pub enum VideoError {
     /// Downloading live streams not supported, compile with `live` feature to enable
     #[error("Downloading live streams not supported, compile with `live` feature to enable")]
     LiveStreamNotSupported,
+    /// Live stream playlist did not change within the stall timeout
+    #[error("Live stream stalled, the playlist did not change within the stall timeout")]
+    LiveStreamStalled,
     /// Provided cookie contains invalid header value characters, an error is returned. Only visible ASCII characters (32-127) are permitted.
     #[error("Provided cookie contains invalid header value characters, an error is returned. Only visible ASCII characters (32-127) are permitted")]
     CookieError,
+    /// Cookie file could not be read, parsed or written
+    #[error("Cookie jar error: {0}")]
+    CookieJarError(String),
+    /// OAuth device-code flow or token refresh failed
+    #[error("OAuth error: {0}")]
+    OAuthError(String),
+    /// [`crate::OAuth`] has no token, the device has to be authorized with [`crate::OAuth::login`] first
+    #[error("Not authenticated, log in with OAuth first")]
+    NotAuthenticated,
+    /// Signature or n-parameter deciphering failed, only returned with [`CipherOptions::strict`]
+    #[error(
+        "Cipher extraction failed for player {}",
+        .0.player_version.as_deref().unwrap_or("unknown")
+    )]
+    CipherExtractionFailed(Box<CipherReport>),
+    /// Player JavaScript exceeded the runtime budget set in [`JsLimits`]
+    #[error("Player JavaScript exceeded its runtime budget")]
+    JsBudgetExceeded,
+    /// Player JavaScript threw or returned an unexpected value
+    #[error("JavaScript execution error: {0}")]
+    JsExecutionError(String),
+    /// Downloaded file could not be tagged, see [`DownloadOptions::tags`]
+    #[error("Tagging error: {0}")]
+    TaggingError(String),
+    /// FFmpeg arguments which cannot process a stream, see [`FFmpegArgs::validate`]
+    #[error("Invalid FFmpeg arguments: {0}")]
+    #[cfg(feature = "ffmpeg")]
+    InvalidFFmpegArgs(String),
     /// FFmpeg command error
     #[error("FFmpeg command error: {0}")]
     #[cfg(feature = "ffmpeg")]
     FFmpeg(String),
 }
 
+impl VideoError {
+    /// Whether the same request may succeed when retried later or from another address.
+    ///
+    /// Playability errors like [`VideoError::VideoRemoved`] are permanent. The bot check of [`VideoError::LoginRequired`],
+    /// a [`VideoError::PremiereScheduled`] past its start time and transient network errors are not
+    pub fn is_retryable(&self) -> bool {
+        fn is_transient(err: &reqwest::Error) -> bool {
+            err.is_timeout()
+                || err.is_connect()
+                || err.is_request()
+                || err.status().is_some_and(|status| {
+                    status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+                })
+        }
+
+        match self {
+            VideoError::Reqwest(err) => is_transient(err),
+            VideoError::ReqwestMiddleware(reqwest_middleware::Error::Reqwest(err)) => {
+                is_transient(err)
+            }
+            VideoError::LoginRequired { reason } => reason
+                .as_deref()
+                .is_some_and(|reason| reason.contains("not a bot")),
+            VideoError::PremiereScheduled {
+                scheduled_start_time,
+                ..
+            } => scheduled_start_time.is_some_and(|start| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .is_ok_and(|now| now.as_secs() >= start)
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// Why a video was taken down
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, derive_more::Display)]
+pub enum RemovalKind {
+    /// Copyright claim
+    #[display("copyright")]
+    Copyright,
+    /// Violation of the Terms of Service or Community Guidelines
+    #[display("terms of service")]
+    TermsOfService,
+    /// Removed by the uploader or with the channel
+    #[display("other")]
+    Other,
+}
+
+/// How a player function was located in the player script
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, derive_more::Display)]
+pub enum CipherStrategy {
+    /// Found through the call site the player uses to apply it
+    #[display("call site")]
+    CallSite,
+    /// Call site points to an array, name resolved through the array declaration
+    #[display("array lookup")]
+    ArrayLookup,
+    /// Found by scanning function bodies for the `enhanced_except_` marker
+    #[display("sentinel scan")]
+    SentinelScan,
+}
+
+/// Signature and n-parameter deciphering diagnostics of a player
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CipherReport {
+    /// Player script URL
+    #[serde(rename = "playerUrl")]
+    pub player_url: String,
+    /// Player version taken from the player script URL
+    #[serde(rename = "playerVersion")]
+    pub player_version: Option<String>,
+    /// Name of the signature decipher function if found
+    #[serde(rename = "decipherFunction")]
+    pub decipher_function: Option<String>,
+    /// How the signature decipher function was located, `None` if it wasn't. The signature function
+    /// is only found through its call site, see [`CipherStrategy::CallSite`]
+    #[serde(rename = "decipherStrategy")]
+    pub decipher_strategy: Option<CipherStrategy>,
+    /// Name of the n-parameter transform function if found
+    #[serde(rename = "nTransformFunction")]
+    pub n_transform_function: Option<String>,
+    /// How the n-parameter transform function was located, `None` if it wasn't. Its call site is
+    /// tried first, [`CipherStrategy::ArrayLookup`] when the call site indexes an array and
+    /// [`CipherStrategy::SentinelScan`] as a fallback when the call site pattern didn't match
+    #[serde(rename = "nTransformStrategy")]
+    pub n_transform_strategy: Option<CipherStrategy>,
+    /// n-parameter transform function returned the `enhanced_except_` sentinel
+    #[serde(rename = "nTransformSentinel")]
+    pub n_transform_sentinel: bool,
+    /// Number of formats left with an undeciphered signature
+    #[serde(rename = "decipherFailures")]
+    pub decipher_failures: usize,
+    /// Number of formats left with an untransformed n-parameter
+    #[serde(rename = "nTransformFailures")]
+    pub n_transform_failures: usize,
+    /// Name of the [`JsRuntime`] which ran the player functions
+    #[serde(rename = "jsRuntime", default)]
+    pub js_runtime: Option<String>,
+    /// First error raised while running the player functions
+    #[serde(rename = "jsError", default)]
+    pub js_error: Option<String>,
+}
+
+impl CipherReport {
+    /// Every format URL was deciphered and transformed successfully
+    pub fn is_ok(&self) -> bool {
+        self.decipher_failures == 0 && self.n_transform_failures == 0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VideoFormat {
     /// Video format itag number
@@ -368,6 +718,9 @@ pub struct VideoFormat {
     pub audio_bitrate: Option<u64>, // LIVE HLS VIDEO ONLY
     #[serde(rename = "loudnessDb")]
     pub loudness_db: Option<f64>, // AUDIO ONLY
+    /// Segment duration of live adaptive formats, their segments are requested by `sq` number
+    #[serde(rename = "targetDurationSec", default)]
+    pub target_duration_sec: Option<f64>, // LIVE ADAPTIVE ONLY
     /// Video format URL
     pub url: String,
     /// Video format has video or not
@@ -416,6 +769,7 @@ impl From<StreamingDataFormat> for VideoFormat {
             audio_channels: value.audio_channels,
             audio_bitrate: value.audio_bitrate,
             loudness_db: value.loudness_db,
+            target_duration_sec: value.target_duration_sec,
             url: value.url.clone().unwrap_or_default(),
             has_video: false,
             has_audio: false,
@@ -496,6 +850,12 @@ pub struct VideoDetails {
     pub is_unplugged_corpus: bool,
     #[serde(rename = "isLiveContent")]
     pub is_live_content: bool,
+    /// Live stream or premiere which has not started yet, it has no formats until it does
+    #[serde(rename = "isUpcoming", default)]
+    pub is_upcoming: bool,
+    /// Unix timestamp an upcoming live stream or premiere is scheduled to start at
+    #[serde(rename = "scheduledStartTime", default)]
+    pub scheduled_start_time: Option<u64>,
     pub thumbnails: Vec<Thumbnail>,
 }
 
@@ -759,59 +1119,356 @@ impl<'de> Deserialize<'de> for MimeType {
     }
 }
 
+/// Formats ffmpeg cannot write to a pipe, they write several files
 #[cfg(feature = "ffmpeg")]
-#[derive(Debug, Clone, PartialEq, Eq)]
+const FFMPEG_FILE_FORMATS: [&str; 5] = ["image2", "hls", "dash", "segment", "stream_segment"];
+
+/// MP4 based formats, writing them to a pipe needs fragmentation (`-movflags`)
+#[cfg(feature = "ffmpeg")]
+const FFMPEG_MP4_FORMATS: [&str; 7] = ["mp4", "mov", "ipod", "ismv", "3gp", "3g2", "f4v"];
+
+/// Named [`FFmpegArgs`] for common outputs, see [`FFmpegArgs::preset`]
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FFmpegPreset {
+    /// MP3 audio at 320 kbit/s
+    Mp3_320k,
+    /// Mono Opus audio at 32 kbit/s in Ogg, for speech
+    OpusVoice,
+    /// H.264 video scaled to 720p with AAC audio in fragmented MP4
+    H264_720p,
+}
+
+/// Arguments of ffmpeg processing a stream from stdin to stdout.
+///
+/// Set the fields or chain the builder methods, starting from [`FFmpegArgs::default`] or a
+/// [`FFmpegArgs::preset`]. Arguments without any option set leave the stream untouched.
+/// # Example
+/// ```ignore
+///     let args = FFmpegArgs::preset(FFmpegPreset::Mp3_320k)
+///         .start(Duration::from_secs(30))
+///         .end(Duration::from_secs(90))
+///         .metadata("title", "Chorus");
+/// ```
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct FFmpegArgs {
+    /// Output format (`-f`), required since ffmpeg cannot guess it for a pipe
     pub format: Option<String>,
     pub audio_filter: Option<String>,
     pub video_filter: Option<String>,
+    /// Audio encoder (`-c:a`), e.g. `libmp3lame`, `libopus` or `copy`
+    pub audio_codec: Option<String>,
+    /// Video encoder (`-c:v`), e.g. `libx264` or `copy`
+    pub video_codec: Option<String>,
+    /// Audio bitrate (`-b:a`), e.g. `320k`
+    pub audio_bitrate: Option<String>,
+    /// Video bitrate (`-b:v`), e.g. `2500k`
+    pub video_bitrate: Option<String>,
+    /// Audio sample rate in Hz (`-ar`)
+    pub sample_rate: Option<u32>,
+    /// Audio channels (`-ac`)
+    pub channels: Option<u32>,
+    /// Position the output starts at (`-ss`)
+    pub start: Option<Duration>,
+    /// Position the output ends at (`-to`)
+    pub end: Option<Duration>,
+    /// Drop the video (`-vn`)
+    pub no_video: bool,
+    /// Drop the audio (`-an`)
+    pub no_audio: bool,
+    /// Metadata tags of the output (`-metadata`)
+    pub metadata: Vec<(String, String)>,
+    /// MP4 muxer flags (`-movflags`), e.g. `frag_keyframe+empty_moov` to write MP4 to a pipe
+    pub movflags: Option<String>,
 }
 
 #[cfg(feature = "ffmpeg")]
 impl FFmpegArgs {
-    pub fn build(&self) -> Vec<String> {
-        let mut args: Vec<String> = vec![];
+    /// Arguments of a named preset, the builder methods adjust them further
+    pub fn preset(preset: FFmpegPreset) -> Self {
+        match preset {
+            FFmpegPreset::Mp3_320k => Self::default()
+                .format("mp3")
+                .no_video(true)
+                .audio_codec("libmp3lame")
+                .audio_bitrate("320k"),
+            FFmpegPreset::OpusVoice => Self::default()
+                .format("ogg")
+                .no_video(true)
+                .audio_codec("libopus")
+                .audio_bitrate("32k")
+                .sample_rate(48000)
+                .channels(1),
+            FFmpegPreset::H264_720p => Self::default()
+                .format("mp4")
+                .movflags("frag_keyframe+empty_moov")
+                .video_codec("libx264")
+                .video_filter("scale=-2:720")
+                .audio_codec("aac")
+                .audio_bitrate("128k"),
+        }
+    }
+
+    pub fn format(mut self, format: impl Into<String>) -> Self {
+        self.format = Some(format.into());
+        self
+    }
+
+    pub fn audio_filter(mut self, audio_filter: impl Into<String>) -> Self {
+        self.audio_filter = Some(audio_filter.into());
+        self
+    }
+
+    pub fn video_filter(mut self, video_filter: impl Into<String>) -> Self {
+        self.video_filter = Some(video_filter.into());
+        self
+    }
+
+    pub fn audio_codec(mut self, audio_codec: impl Into<String>) -> Self {
+        self.audio_codec = Some(audio_codec.into());
+        self
+    }
+
+    pub fn video_codec(mut self, video_codec: impl Into<String>) -> Self {
+        self.video_codec = Some(video_codec.into());
+        self
+    }
+
+    pub fn audio_bitrate(mut self, audio_bitrate: impl Into<String>) -> Self {
+        self.audio_bitrate = Some(audio_bitrate.into());
+        self
+    }
+
+    pub fn video_bitrate(mut self, video_bitrate: impl Into<String>) -> Self {
+        self.video_bitrate = Some(video_bitrate.into());
+        self
+    }
+
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub fn channels(mut self, channels: u32) -> Self {
+        self.channels = Some(channels);
+        self
+    }
+
+    pub fn start(mut self, start: Duration) -> Self {
+        self.start = Some(start);
+        self
+    }
+
+    pub fn end(mut self, end: Duration) -> Self {
+        self.end = Some(end);
+        self
+    }
+
+    pub fn no_video(mut self, no_video: bool) -> Self {
+        self.no_video = no_video;
+        self
+    }
+
+    pub fn no_audio(mut self, no_audio: bool) -> Self {
+        self.no_audio = no_audio;
+        self
+    }
+
+    /// Add a metadata tag
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn movflags(mut self, movflags: impl Into<String>) -> Self {
+        self.movflags = Some(movflags.into());
+        self
+    }
+
+    /// Check ffmpeg can write the output to a pipe and the options do not contradict each other
+    pub fn validate(&self) -> Result<(), VideoError> {
+        if *self == Self::default() {
+            return Ok(());
+        }
+
+        let invalid = |reason: &str| Err(VideoError::InvalidFFmpegArgs(reason.to_string()));
+
+        let Some(format) = &self.format else {
+            return invalid("output format is required, ffmpeg cannot guess it for a pipe");
+        };
+
+        if FFMPEG_FILE_FORMATS.contains(&format.as_str()) {
+            return invalid(&format!("{format} writes files and cannot be piped"));
+        }
+
+        let fragmented = self.movflags.as_deref().is_some_and(|flags| {
+            flags
+                .split('+')
+                .any(|flag| matches!(flag, "frag_keyframe" | "empty_moov" | "frag_custom"))
+        });
+        if FFMPEG_MP4_FORMATS.contains(&format.as_str()) && !fragmented {
+            return invalid(&format!(
+                "{format} needs fragmentation to be piped, set movflags to frag_keyframe+empty_moov"
+            ));
+        }
+
+        if self.no_video && self.no_audio {
+            return invalid("output has neither video nor audio");
+        }
 
-        if let Some(format) = &self.format {
-            args.push("-f".to_string());
-            args.push(format.to_string());
+        let video = self.video_codec.is_some()
+            || self.video_bitrate.is_some()
+            || self.video_filter.is_some();
+        if self.no_video && video {
+            return invalid("video options are set while the video is dropped");
         }
 
-        if let Some(audio_filter) = &self.audio_filter {
-            args.push("-af".to_string());
-            args.push(audio_filter.to_string());
+        let audio = self.audio_codec.is_some()
+            || self.audio_bitrate.is_some()
+            || self.audio_filter.is_some()
+            || self.sample_rate.is_some()
+            || self.channels.is_some();
+        if self.no_audio && audio {
+            return invalid("audio options are set while the audio is dropped");
         }
 
-        if let Some(video_filter) = &self.video_filter {
-            args.push("-vf".to_string());
-            args.push(video_filter.to_string());
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start >= end {
+                return invalid("start is not before end");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(&self) -> Vec<String> {
+        if *self == Self::default() {
+            return vec![];
         }
 
-        if self.format.is_some() || self.audio_filter.is_some() || self.video_filter.is_some() {
-            args = [
-                vec![
-                    // input as stdin
-                    "-i".to_string(),
-                    // aliases of pipe:0
-                    "-".to_string(),
-                    // loggers
-                    "-analyzeduration".to_string(),
-                    "0".to_string(),
-                    "-loglevel".to_string(),
-                    "0".to_string(),
-                ],
-                args,
-            ]
-            .concat();
-
-            // pipe to stdout
-            args.push("pipe:1".to_string());
+        let mut args: Vec<String> = vec![
+            // input as stdin
+            "-i".to_string(),
+            // aliases of pipe:0
+            "-".to_string(),
+            // loggers, errors and progress go to stderr
+            "-analyzeduration".to_string(),
+            "0".to_string(),
+            "-loglevel".to_string(),
+            "error".to_string(),
+            "-nostats".to_string(),
+            "-progress".to_string(),
+            "pipe:2".to_string(),
+        ];
+
+        let mut push = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value);
+            }
+        };
+
+        let seconds = |x: Duration| format!("{:.3}", x.as_secs_f64());
+        push("-ss", self.start.map(seconds));
+        push("-to", self.end.map(seconds));
+
+        push("-c:v", self.video_codec.clone());
+        push("-b:v", self.video_bitrate.clone());
+        push("-vf", self.video_filter.clone());
+
+        push("-c:a", self.audio_codec.clone());
+        push("-b:a", self.audio_bitrate.clone());
+        push("-ar", self.sample_rate.map(|x| x.to_string()));
+        push("-ac", self.channels.map(|x| x.to_string()));
+        push("-af", self.audio_filter.clone());
+
+        for (key, value) in &self.metadata {
+            push("-metadata", Some(format!("{key}={value}")));
         }
 
+        push("-movflags", self.movflags.clone());
+        push("-f", self.format.clone());
+
+        if self.no_video {
+            args.push("-vn".to_string());
+        }
+        if self.no_audio {
+            args.push("-an".to_string());
+        }
+
+        // pipe to stdout
+        args.push("pipe:1".to_string());
+
         args
     }
 }
 
+/// Options of [`Video::download_merged_with_ffmpeg`](crate::Video::download_merged_with_ffmpeg)
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FFmpegMergeOptions {
+    /// Output container (`-f`), ffmpeg guesses it from the file extension otherwise
+    pub format: Option<String>,
+    /// Encoder of the video, e.g. `libx264`. Copied without re-encoding by default
+    pub video_codec: Option<String>,
+    /// Encoder of the audio, e.g. `aac`. Copied without re-encoding by default
+    pub audio_codec: Option<String>,
+    /// Embed title, author, description, publish date and URL of the video
+    pub metadata: bool,
+    /// Embed the chapters of the video
+    pub chapters: bool,
+    /// Embed the largest thumbnail as cover art, for containers supporting it like MP4 and Matroska.
+    /// Skipped for WebM, Ogg and WAV outputs
+    pub thumbnail: bool,
+}
+
+/// Progress ffmpeg reports (`-progress`) while processing a stream with [`FFmpegArgs`]
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FFmpegProgress {
+    /// Position of the output
+    pub time: Option<Duration>,
+    /// Processing speed relative to playback speed
+    pub speed: Option<f64>,
+    /// Output bitrate in kbit/s
+    pub bitrate: Option<f64>,
+    /// Bytes written to the output
+    pub total_size: Option<u64>,
+    /// Video frames written to the output
+    pub frame: Option<u64>,
+    /// ffmpeg processed the whole input
+    pub ended: bool,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl FFmpegProgress {
+    /// Apply a `key=value` line of the progress output, `false` if the line is not one
+    pub(crate) fn update(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return false;
+        };
+
+        let value = value.trim();
+        match key {
+            "frame" => self.frame = value.parse().ok(),
+            "bitrate" => self.bitrate = value.trim_end_matches("kbits/s").parse().ok(),
+            "total_size" => self.total_size = value.parse().ok(),
+            // `out_time_ms` is in microseconds as well
+            "out_time_us" | "out_time_ms" => {
+                self.time = value.parse().ok().map(Duration::from_micros)
+            }
+            "speed" => self.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => self.ended = value == "end",
+            "fps" | "out_time" | "dup_frames" | "drop_frames" => {}
+            _ if key.starts_with("stream_") => {}
+            _ => return false,
+        }
+
+        true
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct PlayerResponse {
     #[serde(rename = "streamingData")]
@@ -936,6 +1593,8 @@ pub struct PlayerResponseVideoDetails {
     pub is_unplugged_corpus: Option<bool>,
     #[serde(rename = "isLiveContent")]
     pub is_live_content: Option<bool>,
+    #[serde(rename = "isUpcoming")]
+    pub is_upcoming: Option<bool>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -994,6 +1653,8 @@ pub struct StreamingDataFormat {
     pub audio_bitrate: Option<u64>, // LIVE HLS VIDEO ONLY
     #[serde(rename = "loudnessDb")]
     pub loudness_db: Option<f64>, // AUDIO ONLY
+    #[serde(rename = "targetDurationSec")]
+    pub target_duration_sec: Option<f64>, // LIVE ADAPTIVE ONLY
     /// Video format URL
     pub url: Option<String>,
     #[serde(rename = "signatureCipher")]
@@ -1015,14 +1676,21 @@ pub struct StreamingDataFormatColorInfo {
 pub struct PlayabilityStatus {
     pub status: Option<String>,
     pub reason: Option<String>,
+    pub messages: Option<Vec<String>>,
     #[serde(rename = "errorScreen")]
     pub error_screen: Option<ErrorScreen>,
+    #[serde(rename = "liveStreamability")]
+    pub live_streamability: Option<serde_json::Value>,
+    #[serde(rename = "desktopLegacyAgeGateReason")]
+    pub desktop_legacy_age_gate_reason: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ErrorScreen {
     #[serde(rename = "playerLegacyDesktopYpcOfferRenderer")]
-    pub player_legacy_desktop_ypc_offer_renderer: Option<String>,
+    pub player_legacy_desktop_ypc_offer_renderer: Option<serde_json::Value>,
+    #[serde(rename = "playerErrorMessageRenderer")]
+    pub player_error_message_renderer: Option<serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -1032,36 +1700,3 @@ pub struct YTConfig {
     #[serde(rename = "WEB_PLAYER_CONTEXT_CONFIGS")]
     pub web_player_context_configs: Option<serde_json::Value>,
 }
-
-pub struct CustomRetryableStrategy;
-
-impl reqwest_retry::RetryableStrategy for CustomRetryableStrategy {
-    fn handle(
-        &self,
-        res: &reqwest_middleware::Result<reqwest::Response>,
-    ) -> Option<reqwest_retry::Retryable> {
-        match res {
-            // retry if 201
-            Ok(success) => custom_on_request_success(success),
-            Err(error) => reqwest_retry::default_on_request_failure(error),
-        }
-    }
-}
-
-/// Custom request success retry strategy.
-///
-/// Will only retry if:
-/// * The status was 5XX (server error)
-/// * The status was 4XX (client error)
-///
-/// Note that success here means that the request finished without interruption, not that it was logically OK.
-fn custom_on_request_success(success: &reqwest::Response) -> Option<reqwest_retry::Retryable> {
-    let status = success.status();
-    if status.is_server_error() || status.is_client_error() {
-        Some(reqwest_retry::Retryable::Transient)
-    } else if status.is_success() {
-        None
-    } else {
-        Some(reqwest_retry::Retryable::Fatal)
-    }
-}
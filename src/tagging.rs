@@ -0,0 +1,903 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::structs::{TagOptions, VideoDetails, VideoError};
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const MKV_SEGMENT: u32 = 0x1853_8067;
+const MKV_TAGS: u32 = 0x1254_C367;
+const MKV_CHAPTERS: u32 = 0x1043_A770;
+const MKV_ATTACHMENTS: u32 = 0x1941_A469;
+
+/// Details of a video written into a downloaded file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Tags {
+    pub title: String,
+    pub artist: Option<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub url: Option<String>,
+    /// Image data and its MIME type
+    pub cover: Option<(Vec<u8>, &'static str)>,
+    /// Title, start and end in milliseconds
+    pub chapters: Vec<(String, u64, u64)>,
+}
+
+impl Tags {
+    /// Tags of a video, with the largest JPEG or PNG thumbnail as cover
+    pub async fn from_details(
+        client: &reqwest_middleware::ClientWithMiddleware,
+        details: &VideoDetails,
+        options: &TagOptions,
+    ) -> Result<Self, VideoError> {
+        let not_empty = |x: &str| Some(x.to_string()).filter(|x| !x.is_empty());
+
+        let mut tags = Tags {
+            title: details.title.clone(),
+            artist: details.author.as_ref().and_then(|x| not_empty(&x.name)),
+            date: not_empty(&details.publish_date),
+            description: not_empty(&details.description),
+            url: not_empty(&details.video_url),
+            ..Default::default()
+        };
+
+        if options.chapters {
+            let length = details.length_seconds.parse::<u64>().unwrap_or(0) * 1000;
+            let starts: Vec<u64> = details
+                .chapters
+                .iter()
+                .map(|x| x.start_time.max(0) as u64)
+                .collect();
+
+            // Chapters end where the next one starts
+            for (index, chapter) in details.chapters.iter().enumerate() {
+                let end = starts.get(index + 1).copied().unwrap_or(length);
+                tags.chapters
+                    .push((chapter.title.clone(), starts[index], end.max(starts[index])));
+            }
+        }
+
+        if options.cover_art {
+            let mut thumbnails: Vec<_> = details.thumbnails.iter().collect();
+            thumbnails.sort_by_key(|x| std::cmp::Reverse(x.width * x.height));
+
+            // Cover art is best effort, unavailable and WebP thumbnails are skipped
+            for thumbnail in thumbnails.into_iter().take(3) {
+                let Some(data) = fetch_thumbnail(client, &thumbnail.url).await else {
+                    continue;
+                };
+
+                if let Some(mime) = image_mime(&data) {
+                    tags.cover = Some((data, mime));
+                    break;
+                }
+            }
+        }
+
+        Ok(tags)
+    }
+}
+
+async fn fetch_thumbnail(
+    client: &reqwest_middleware::ClientWithMiddleware,
+    url: &str,
+) -> Option<Vec<u8>> {
+    let response = client.get(url).send().await.ok()?.error_for_status().ok()?;
+
+    Some(response.bytes().await.ok()?.to_vec())
+}
+
+/// Write `tags` into the file at `path`: ID3v2 for MP3, `ilst` for MP4 and Tags for Matroska/WebM.
+/// Other files are left untouched
+pub(crate) fn write_tags(path: &Path, tags: &Tags) -> Result<(), VideoError> {
+    let mut file = File::open(path).map_err(tagging_error)?;
+
+    let mut magic = [0u8; 8];
+    let read = file.read(&mut magic).map_err(tagging_error)?;
+    let magic = &magic[..read];
+
+    if magic.starts_with(b"ID3")
+        || (magic.len() >= 2 && magic[0] == 0xFF && magic[1] & 0xE0 == 0xE0)
+    {
+        tag_mp3(path, &mut file, tags)
+    } else if magic.get(4..8) == Some(b"ftyp") {
+        tag_mp4(path, &mut file, tags)
+    } else if magic.starts_with(&EBML_HEADER.to_be_bytes()) {
+        tag_matroska(path, &mut file, tags)
+    } else {
+        Ok(())
+    }
+}
+
+fn tagging_error(err: io::Error) -> VideoError {
+    VideoError::TaggingError(err.to_string())
+}
+
+fn image_mime(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else {
+        None
+    }
+}
+
+/// Part of a rewritten file
+enum Part {
+    Data(Vec<u8>),
+    /// Range of the original file
+    Copy(u64, u64),
+}
+
+/// Write the file again from `parts` through a temporary file
+fn rewrite(path: &Path, file: &mut File, parts: Vec<Part>) -> Result<(), VideoError> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tagging");
+
+    let mut temp = File::create(&temp_path).map_err(tagging_error)?;
+    for part in parts {
+        match part {
+            Part::Data(data) => temp.write_all(&data).map_err(tagging_error)?,
+            Part::Copy(start, length) => {
+                file.seek(SeekFrom::Start(start)).map_err(tagging_error)?;
+                io::copy(&mut (&mut *file).take(length), &mut temp).map_err(tagging_error)?;
+            }
+        }
+    }
+    drop(temp);
+
+    std::fs::rename(&temp_path, path).map_err(tagging_error)
+}
+
+fn tag_mp3(path: &Path, file: &mut File, tags: &Tags) -> Result<(), VideoError> {
+    let length = file.metadata().map_err(tagging_error)?.len();
+
+    // Replace an existing tag
+    let mut header = [0u8; 10];
+    file.seek(SeekFrom::Start(0)).map_err(tagging_error)?;
+    let mut audio = 0;
+    if file.read_exact(&mut header).is_ok() && header.starts_with(b"ID3") {
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        audio = (10 + synchsafe(&header[6..10]) + footer).min(length);
+    }
+
+    rewrite(
+        path,
+        file,
+        vec![Part::Data(id3v2(tags)), Part::Copy(audio, length - audio)],
+    )
+}
+
+fn synchsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, x| (value << 7) | (x & 0x7F) as u64)
+}
+
+fn synchsafe_bytes(value: usize) -> [u8; 4] {
+    [21, 14, 7, 0].map(|shift| ((value >> shift) & 0x7F) as u8)
+}
+
+fn id3_frame(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut frame = id.to_vec();
+    frame.extend(synchsafe_bytes(body.len()));
+    frame.extend([0, 0]);
+    frame.extend(body);
+    frame
+}
+
+/// Text frame in UTF-8
+fn id3_text(id: &[u8; 4], text: &str) -> Vec<u8> {
+    id3_frame(id, &[&[3], text.as_bytes()].concat())
+}
+
+/// ID3v2.4 tag, chapters as `CHAP` frames in a `CTOC`
+fn id3v2(tags: &Tags) -> Vec<u8> {
+    let mut frames = id3_text(b"TIT2", &tags.title);
+
+    if let Some(artist) = &tags.artist {
+        frames.extend(id3_text(b"TPE1", artist));
+    }
+    if let Some(date) = &tags.date {
+        frames.extend(id3_text(b"TDRC", date));
+    }
+    if let Some(description) = &tags.description {
+        frames.extend(id3_frame(
+            b"COMM",
+            &[&[3], &b"eng\0"[..], description.as_bytes()].concat(),
+        ));
+    }
+    if let Some(url) = &tags.url {
+        frames.extend(id3_frame(b"WXXX", &[&[3, 0], url.as_bytes()].concat()));
+    }
+    if let Some((data, mime)) = &tags.cover {
+        // Front cover without description
+        frames.extend(id3_frame(
+            b"APIC",
+            &[&[3], mime.as_bytes(), &[0, 3, 0], data].concat(),
+        ));
+    }
+
+    let chapters = &tags.chapters[..tags.chapters.len().min(255)];
+    if !chapters.is_empty() {
+        let mut toc = b"toc\0".to_vec();
+        // Top level and ordered
+        toc.extend([0x03, chapters.len() as u8]);
+
+        for (index, (title, start, end)) in chapters.iter().enumerate() {
+            let id = format!("chp{index}\0");
+            toc.extend(id.as_bytes());
+
+            let mut chapter = id.into_bytes();
+            chapter.extend((*start as u32).to_be_bytes());
+            chapter.extend((*end as u32).to_be_bytes());
+            // No byte offsets
+            chapter.extend([0xFF; 8]);
+            chapter.extend(id3_text(b"TIT2", title));
+            frames.extend(id3_frame(b"CHAP", &chapter));
+        }
+
+        frames.extend(id3_frame(b"CTOC", &toc));
+    }
+
+    let mut tag = b"ID3\x04\x00\x00".to_vec();
+    tag.extend(synchsafe_bytes(frames.len()));
+    tag.extend(frames);
+    tag
+}
+
+/// Box kind, offset and header length of the boxes in `data`
+fn mp4_children(data: &[u8]) -> Vec<([u8; 4], usize, usize, usize)> {
+    let mut children = vec![];
+    let mut position = 0;
+
+    while position + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+        let kind: [u8; 4] = data[position + 4..position + 8].try_into().unwrap();
+
+        let (size, header) = match size {
+            0 => (data.len() - position, 8),
+            1 if position + 16 <= data.len() => (
+                u64::from_be_bytes(data[position + 8..position + 16].try_into().unwrap()) as usize,
+                16,
+            ),
+            _ => (size, 8),
+        };
+        if size < header || position + size > data.len() {
+            break;
+        }
+
+        children.push((kind, position, header, size));
+        position += size;
+    }
+
+    children
+}
+
+fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    data.extend(kind);
+    data.extend(body);
+    data
+}
+
+fn mp4_item(kind: &[u8; 4], data_type: u32, value: &[u8]) -> Vec<u8> {
+    let data = [&data_type.to_be_bytes()[..], &[0; 4], value].concat();
+    mp4_box(kind, &mp4_box(b"data", &data))
+}
+
+/// `meta` box with the iTunes `ilst`
+fn mp4_meta(tags: &Tags) -> Vec<u8> {
+    let hdlr = mp4_box(b"hdlr", &[&[0; 8][..], b"mdirappl", &[0; 9]].concat());
+
+    let mut ilst = mp4_item(b"\xA9nam", 1, tags.title.as_bytes());
+    if let Some(artist) = &tags.artist {
+        ilst.extend(mp4_item(b"\xA9ART", 1, artist.as_bytes()));
+    }
+    if let Some(date) = &tags.date {
+        ilst.extend(mp4_item(b"\xA9day", 1, date.as_bytes()));
+    }
+    if let Some(description) = &tags.description {
+        ilst.extend(mp4_item(b"desc", 1, description.as_bytes()));
+    }
+    if let Some(url) = &tags.url {
+        ilst.extend(mp4_item(b"\xA9cmt", 1, url.as_bytes()));
+    }
+    if let Some((data, mime)) = &tags.cover {
+        let data_type = if *mime == "image/png" { 14 } else { 13 };
+        ilst.extend(mp4_item(b"covr", data_type, data));
+    }
+
+    mp4_box(
+        b"meta",
+        &[&[0; 4][..], &hdlr, &mp4_box(b"ilst", &ilst)].concat(),
+    )
+}
+
+/// Nero chapters (`chpl`), start times in 100 nanoseconds
+fn mp4_chapters(tags: &Tags) -> Vec<u8> {
+    let chapters = &tags.chapters[..tags.chapters.len().min(255)];
+
+    let mut body = vec![1, 0, 0, 0, 0, 0, 0, 0, chapters.len() as u8];
+    for (title, start, _) in chapters {
+        let mut end = title.len().min(255);
+        while !title.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        body.extend((start * 10_000).to_be_bytes());
+        body.push(end as u8);
+        body.extend(&title.as_bytes()[..end]);
+    }
+
+    mp4_box(b"chpl", &body)
+}
+
+/// Move an absolute offset of 4 or 8 bytes from `threshold` on by `delta`
+fn patch_offset(entry: &mut [u8], threshold: u64, delta: i64) -> Result<(), VideoError> {
+    let offset = match entry.len() {
+        4 => u32::from_be_bytes(entry.try_into().unwrap()) as u64,
+        _ => u64::from_be_bytes(entry.try_into().unwrap()),
+    };
+    if offset < threshold {
+        return Ok(());
+    }
+
+    let offset = (offset as i64 + delta) as u64;
+    if entry.len() == 4 {
+        let offset = u32::try_from(offset)
+            .map_err(|_| VideoError::TaggingError("Chunk offset exceeds 32 bits".to_string()))?;
+        entry.copy_from_slice(&offset.to_be_bytes());
+    } else {
+        entry.copy_from_slice(&offset.to_be_bytes());
+    }
+
+    Ok(())
+}
+
+/// Move chunk offsets (`stco`, `co64`) from `threshold` on by `delta`
+fn patch_chunk_offsets(data: &mut [u8], threshold: u64, delta: i64) -> Result<(), VideoError> {
+    for (kind, position, header, size) in mp4_children(data) {
+        let body = &mut data[position + header..position + size];
+
+        match &kind {
+            b"trak" | b"mdia" | b"minf" | b"stbl" => patch_chunk_offsets(body, threshold, delta)?,
+            b"stco" | b"co64" => {
+                let width = if &kind == b"stco" { 4 } else { 8 };
+
+                for entry in body
+                    .get_mut(8..)
+                    .unwrap_or_default()
+                    .chunks_exact_mut(width)
+                {
+                    patch_offset(entry, threshold, delta)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Move the absolute offsets of fragments from `threshold` on by `delta`: explicit base data
+/// offsets of `tfhd` in a `moof` and fragment offsets of `tfra` in a `mfra`
+fn patch_fragment_offsets(data: &mut [u8], threshold: u64, delta: i64) -> Result<(), VideoError> {
+    for (kind, position, header, size) in mp4_children(data) {
+        let body = &mut data[position + header..position + size];
+
+        match &kind {
+            b"traf" => patch_fragment_offsets(body, threshold, delta)?,
+            // Flag 0x000001, base data offset after the track id
+            b"tfhd" if body.len() >= 16 && body[3] & 0x01 != 0 => {
+                patch_offset(&mut body[8..16], threshold, delta)?
+            }
+            b"tfra" if body.len() >= 16 => {
+                let width = if body[0] == 1 { 8 } else { 4 };
+                let lengths = body[11];
+                let entry_size = 2 * width
+                    + ((lengths >> 4) & 3) as usize
+                    + ((lengths >> 2) & 3) as usize
+                    + (lengths & 3) as usize
+                    + 3;
+
+                // Entries start with the time followed by the offset of the moof
+                for entry in body[16..].chunks_exact_mut(entry_size) {
+                    patch_offset(&mut entry[width..2 * width], threshold, delta)?;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn tag_mp4(path: &Path, file: &mut File, tags: &Tags) -> Result<(), VideoError> {
+    let length = file.metadata().map_err(tagging_error)?.len();
+
+    // Top level boxes
+    let mut boxes = vec![];
+    let mut position = 0;
+    while position + 8 <= length {
+        let mut header = [0u8; 16];
+        file.seek(SeekFrom::Start(position))
+            .map_err(tagging_error)?;
+        file.read_exact(&mut header[..8]).map_err(tagging_error)?;
+
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) as u64 {
+            0 => length - position,
+            1 => {
+                file.read_exact(&mut header[8..]).map_err(tagging_error)?;
+                u64::from_be_bytes(header[8..].try_into().unwrap())
+            }
+            size => size,
+        };
+        if size < 8 {
+            break;
+        }
+
+        let kind: [u8; 4] = header[4..8].try_into().unwrap();
+        boxes.push((kind, position, size.min(length - position)));
+        position += size;
+    }
+
+    let Some(moov_index) = boxes.iter().position(|(kind, _, _)| kind == b"moov") else {
+        return Err(VideoError::TaggingError("MP4 without moov box".to_string()));
+    };
+    let (_, moov_start, moov_size) = boxes[moov_index];
+
+    // Segment indexes point relative to themselves, past a moov which changes its size
+    if boxes[..moov_index]
+        .iter()
+        .any(|(kind, _, _)| kind == b"sidx")
+    {
+        return Err(VideoError::TaggingError(
+            "MP4 with a segment index before the moov box".to_string(),
+        ));
+    }
+
+    let read_box = |file: &mut File, start: u64, size: u64| -> Result<Vec<u8>, VideoError> {
+        let mut data = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(start)).map_err(tagging_error)?;
+        file.read_exact(&mut data).map_err(tagging_error)?;
+        Ok(data)
+    };
+
+    let moov = read_box(file, moov_start, moov_size)?;
+
+    let Some(&(_, _, header, _)) = mp4_children(&moov).first() else {
+        return Err(VideoError::TaggingError("Invalid moov box".to_string()));
+    };
+
+    // Replace the metadata and chapters of the user data, keep the rest
+    let mut body = vec![];
+    let mut udta = vec![];
+    for (kind, position, child_header, size) in mp4_children(&moov[header..]) {
+        let child = &moov[header + position..header + position + size];
+
+        if &kind == b"udta" {
+            for (kind, position, _, size) in mp4_children(&child[child_header..]) {
+                if &kind != b"meta" && &kind != b"chpl" {
+                    udta.extend(&child[child_header + position..child_header + position + size]);
+                }
+            }
+        } else {
+            body.extend(child);
+        }
+    }
+
+    udta.extend(mp4_meta(tags));
+    if !tags.chapters.is_empty() {
+        udta.extend(mp4_chapters(tags));
+    }
+    body.extend(mp4_box(b"udta", &udta));
+
+    // Media data after the movie box moves with its new size
+    let moov_end = moov_start + moov_size;
+    let delta = (body.len() + 8) as i64 - moov_size as i64;
+    patch_chunk_offsets(&mut body, moov_end, delta)?;
+
+    let mut parts = vec![
+        Part::Copy(0, moov_start),
+        Part::Data(mp4_box(b"moov", &body)),
+    ];
+
+    // Fragments of fragmented MP4 may refer to absolute offsets as well
+    for &(kind, start, size) in &boxes[moov_index + 1..] {
+        if delta != 0 && (&kind == b"moof" || &kind == b"mfra") {
+            let mut data = read_box(file, start, size)?;
+            let header = if data[..4] == [0, 0, 0, 1] { 16 } else { 8 };
+            patch_fragment_offsets(&mut data[header..], moov_end, delta)?;
+            parts.push(Part::Data(data));
+        } else {
+            parts.push(Part::Copy(start, size));
+        }
+    }
+    // Trailing bytes which are no complete box
+    let boxes_end = boxes.last().map_or(0, |(_, start, size)| start + size);
+    parts.push(Part::Copy(boxes_end, length - boxes_end));
+
+    rewrite(path, file, parts)
+}
+
+/// Read an EBML element header at `position`: id, size (`None` if unknown) and header length
+fn ebml_header(file: &mut File, position: u64) -> Result<(u32, Option<u64>, u64), VideoError> {
+    let mut bytes = [0u8; 12];
+    file.seek(SeekFrom::Start(position))
+        .map_err(tagging_error)?;
+    let read = file.read(&mut bytes).map_err(tagging_error)?;
+    let bytes = &bytes[..read];
+
+    let invalid = || VideoError::TaggingError("Invalid EBML element".to_string());
+
+    let id_length = bytes.first().ok_or_else(invalid)?.leading_zeros() as usize + 1;
+    if id_length > 4 || bytes.len() < id_length + 1 {
+        return Err(invalid());
+    }
+    let id = bytes[..id_length]
+        .iter()
+        .fold(0u32, |id, x| (id << 8) | *x as u32);
+
+    let size_length = bytes[id_length].leading_zeros() as usize + 1;
+    if size_length > 8 || bytes.len() < id_length + size_length {
+        return Err(invalid());
+    }
+    let size = bytes[id_length..id_length + size_length]
+        .iter()
+        .enumerate()
+        .fold(0u64, |size, (index, x)| {
+            // The first byte holds the length marker
+            let x = if index == 0 {
+                *x as u64 & (0xFF >> size_length)
+            } else {
+                *x as u64
+            };
+            (size << 8) | x
+        });
+    let unknown = (1u64 << (7 * size_length)) - 1;
+
+    Ok((
+        id,
+        Some(size).filter(|x| *x != unknown),
+        (id_length + size_length) as u64,
+    ))
+}
+
+/// EBML variable size integer of `width` bytes
+fn ebml_size(value: u64, width: usize) -> Vec<u8> {
+    let value = value | (1 << (7 * width));
+    value.to_be_bytes()[8 - width..].to_vec()
+}
+
+fn ebml(id: u32, data: &[u8]) -> Vec<u8> {
+    let id = id.to_be_bytes();
+    let mut element = id[id.iter().position(|x| *x != 0).unwrap_or(3)..].to_vec();
+    element.extend(ebml_size(data.len() as u64, 8));
+    element.extend(data);
+    element
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    ebml(id, &value.to_be_bytes())
+}
+
+fn ebml_string(id: u32, value: &str) -> Vec<u8> {
+    ebml(id, value.as_bytes())
+}
+
+/// Tags, Chapters and Attachments elements
+fn matroska_elements(tags: &Tags) -> Vec<u8> {
+    let simple_tag = |name: &str, value: &str| {
+        ebml(
+            0x67C8,
+            &[ebml_string(0x45A3, name), ebml_string(0x4487, value)].concat(),
+        )
+    };
+
+    // Album/movie level target
+    let mut tag = ebml(0x63C0, &ebml_uint(0x68CA, 50));
+    tag.extend(simple_tag("TITLE", &tags.title));
+    for (name, value) in [
+        ("ARTIST", &tags.artist),
+        ("DATE_RELEASED", &tags.date),
+        ("DESCRIPTION", &tags.description),
+        ("URL", &tags.url),
+    ] {
+        if let Some(value) = value {
+            tag.extend(simple_tag(name, value));
+        }
+    }
+    let mut elements = ebml(MKV_TAGS, &ebml(0x7373, &tag));
+
+    if !tags.chapters.is_empty() {
+        let mut edition = vec![];
+        for (index, (title, start, end)) in tags.chapters.iter().enumerate() {
+            let display = [ebml_string(0x85, title), ebml_string(0x437C, "eng")].concat();
+            let atom = [
+                ebml_uint(0x73C4, index as u64 + 1),
+                ebml_uint(0x91, start * 1_000_000),
+                ebml_uint(0x92, end * 1_000_000),
+                ebml(0x80, &display),
+            ]
+            .concat();
+            edition.extend(ebml(0xB6, &atom));
+        }
+        elements.extend(ebml(MKV_CHAPTERS, &ebml(0x45B9, &edition)));
+    }
+
+    if let Some((data, mime)) = &tags.cover {
+        let name = if *mime == "image/png" {
+            "cover.png"
+        } else {
+            "cover.jpg"
+        };
+        let file = [
+            ebml_string(0x466E, name),
+            ebml_string(0x4660, mime),
+            ebml(0x465C, data),
+            ebml_uint(0x46AE, 1),
+        ]
+        .concat();
+        elements.extend(ebml(MKV_ATTACHMENTS, &ebml(0x61A7, &file)));
+    }
+
+    elements
+}
+
+/// Void the existing tags, chapters and attachments and append new ones to the segment
+fn tag_matroska(path: &Path, file: &mut File, tags: &Tags) -> Result<(), VideoError> {
+    let length = file.metadata().map_err(tagging_error)?.len();
+
+    let (_, header_size, header_length) = ebml_header(file, 0)?;
+    let segment_start = header_length + header_size.unwrap_or(0);
+
+    let (id, segment_size, segment_header) = ebml_header(file, segment_start)?;
+    if id != MKV_SEGMENT {
+        return Err(VideoError::TaggingError(
+            "Matroska without segment".to_string(),
+        ));
+    }
+    let data_start = segment_start + segment_header;
+    let segment_end = segment_size.map_or(length, |x| data_start + x);
+    if segment_end != length {
+        return Err(VideoError::TaggingError(
+            "Data after the Matroska segment".to_string(),
+        ));
+    }
+
+    let elements = matroska_elements(tags);
+
+    // The size keeps its width, positions relative to the segment stay valid
+    let mut parts = vec![];
+    if let Some(size) = segment_size {
+        let id_length = 4;
+        let width = (segment_header - id_length) as usize;
+        let size = size + elements.len() as u64;
+        if size >= (1 << (7 * width)) - 1 {
+            return Err(VideoError::TaggingError(
+                "Matroska segment size does not fit".to_string(),
+            ));
+        }
+
+        parts.push(Part::Copy(0, segment_start + id_length));
+        parts.push(Part::Data(ebml_size(size, width)));
+    } else {
+        parts.push(Part::Copy(0, data_start));
+    }
+
+    let mut copied = data_start;
+    let mut position = data_start;
+    while position < segment_end {
+        let (id, size, header) = ebml_header(file, position)?;
+        // Elements of unknown size can not be skipped
+        let Some(size) = size else {
+            break;
+        };
+
+        if matches!(id, MKV_TAGS | MKV_CHAPTERS | MKV_ATTACHMENTS) {
+            let total = header + size;
+            let void = if total >= 9 {
+                [&[0xEC][..], &ebml_size(total - 9, 8)].concat()
+            } else {
+                vec![0xEC, 0x80 | (total - 2) as u8]
+            };
+
+            parts.push(Part::Copy(copied, position - copied));
+            copied = position + void.len() as u64;
+            parts.push(Part::Data(void));
+        }
+
+        position += header + size;
+    }
+
+    parts.push(Part::Copy(copied, length - copied));
+    parts.push(Part::Data(elements));
+
+    rewrite(path, file, parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags() -> Tags {
+        Tags {
+            title: "Title".to_string(),
+            artist: Some("Artist".to_string()),
+            cover: Some((vec![0xFF, 0xD8, 0xFF, 0xE0], "image/jpeg")),
+            chapters: vec![("Intro".to_string(), 0, 1000)],
+            ..Default::default()
+        }
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rusty_ytdl_tagging_{}_{name}", std::process::id()));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn contains(data: &[u8], part: &[u8]) -> bool {
+        data.windows(part.len()).any(|x| x == part)
+    }
+
+    #[test]
+    fn tag_mp3_replacing_existing_tag() {
+        let audio = [0xFF, 0xFB, 0x90, 0x00, 1, 2, 3];
+        let path = temp_file("audio.mp3", &audio);
+
+        write_tags(&path, &tags()).unwrap();
+        write_tags(&path, &tags()).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.starts_with(b"ID3\x04"));
+        assert_eq!(
+            10 + synchsafe(&data[6..10]) as usize,
+            data.len() - audio.len()
+        );
+        assert!(data.ends_with(&audio));
+        assert!(contains(&data, b"TIT2"));
+        assert!(contains(&data, b"CHAP"));
+        assert_eq!(data.windows(4).filter(|x| x == b"ID3\x04").count(), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tag_mp4_moving_chunk_offsets() {
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\0\0");
+        let mdat_payload = b"media";
+
+        // Chunk offset of the payload, after ftyp, moov and the mdat header
+        let moov_for = |offset: u32| {
+            let stco = mp4_box(
+                b"stco",
+                &[&[0; 4][..], &1u32.to_be_bytes(), &offset.to_be_bytes()].concat(),
+            );
+            let stbl = mp4_box(b"stbl", &stco);
+            let minf = mp4_box(b"minf", &stbl);
+            let mdia = mp4_box(b"mdia", &minf);
+            mp4_box(b"moov", &mp4_box(b"trak", &mdia))
+        };
+        let moov_length = moov_for(0).len();
+        let offset = (ftyp.len() + moov_length + 8) as u32;
+
+        let file = [
+            ftyp.clone(),
+            moov_for(offset),
+            mp4_box(b"mdat", mdat_payload),
+        ]
+        .concat();
+        let path = temp_file("video.mp4", &file);
+
+        write_tags(&path, &tags()).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert!(contains(&data, b"\xA9nam"));
+        assert!(contains(&data, b"covr"));
+        assert!(contains(&data, b"chpl"));
+
+        let stco = data.windows(4).position(|x| x == b"stco").unwrap();
+        let offset = u32::from_be_bytes(data[stco + 12..stco + 16].try_into().unwrap()) as usize;
+        assert_eq!(&data[offset..offset + mdat_payload.len()], mdat_payload);
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tag_fragmented_mp4_moving_fragment_offsets() {
+        let ftyp = mp4_box(b"ftyp", b"iso5\0\0\0\0");
+        let moov = mp4_box(b"moov", &mp4_box(b"trak", &[]));
+        let mdat_payload = b"media";
+
+        // Explicit base data offset pointing at the payload
+        let moof_for = |base: u64| {
+            let tfhd = [&[0, 0, 0, 1][..], &1u32.to_be_bytes(), &base.to_be_bytes()].concat();
+            let traf = mp4_box(b"traf", &mp4_box(b"tfhd", &tfhd));
+            mp4_box(b"moof", &[mp4_box(b"mfhd", &[0; 8]), traf].concat())
+        };
+        let moof_start = (ftyp.len() + moov.len()) as u64;
+        let base = moof_start + moof_for(0).len() as u64 + 8;
+
+        let tfra = [
+            &[1, 0, 0, 0][..],
+            &1u32.to_be_bytes(),
+            &[0; 4],
+            &1u32.to_be_bytes(),
+            &0u64.to_be_bytes(),
+            &moof_start.to_be_bytes(),
+            &[1, 1, 1],
+        ]
+        .concat();
+        let mfra = mp4_box(
+            b"mfra",
+            &[mp4_box(b"tfra", &tfra), mp4_box(b"mfro", &[0; 8])].concat(),
+        );
+
+        let file = [
+            ftyp,
+            moov,
+            moof_for(base),
+            mp4_box(b"mdat", mdat_payload),
+            mfra,
+        ]
+        .concat();
+        let path = temp_file("fragmented.mp4", &file);
+
+        write_tags(&path, &tags()).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let tfhd = data.windows(4).position(|x| x == b"tfhd").unwrap();
+        let base = u64::from_be_bytes(data[tfhd + 12..tfhd + 20].try_into().unwrap()) as usize;
+        assert_eq!(&data[base..base + mdat_payload.len()], mdat_payload);
+
+        let tfra = data.windows(4).position(|x| x == b"tfra").unwrap();
+        let moof = u64::from_be_bytes(data[tfra + 28..tfra + 36].try_into().unwrap()) as usize;
+        assert_eq!(&data[moof + 4..moof + 8], b"moof");
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn tag_matroska_appending_to_segment() {
+        let header = ebml(EBML_HEADER, &ebml_string(0x4282, "webm"));
+        let info = ebml(0x1549_A966, &ebml_uint(0x2AD7B1, 1_000_000));
+        let old_tags = ebml(MKV_TAGS, &ebml(0x7373, &[]));
+        let segment = ebml(MKV_SEGMENT, &[info.clone(), old_tags].concat());
+
+        let path = temp_file("audio.webm", &[header.clone(), segment].concat());
+
+        write_tags(&path, &tags()).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let mut file = File::open(&path).unwrap();
+        let (id, size, segment_header) = ebml_header(&mut file, header.len() as u64).unwrap();
+        assert_eq!(id, MKV_SEGMENT);
+        assert_eq!(
+            header.len() as u64 + segment_header + size.unwrap(),
+            data.len() as u64
+        );
+
+        // The old tags are void, the new ones follow them
+        let (id, _, _) = ebml_header(&mut file, (header.len() + 12 + info.len()) as u64).unwrap();
+        assert_eq!(id, 0xEC);
+        assert!(contains(&data, b"TITLE"));
+        assert!(contains(&data, b"cover.jpg"));
+        assert!(contains(&data, b"Intro"));
+
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn leave_other_files_untouched() {
+        let path = temp_file("stream.ts", &[0x47, 0x40, 0x00, 0x10]);
+
+        write_tags(&path, &tags()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), [0x47, 0x40, 0x00, 0x10]);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
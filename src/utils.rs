@@ -1,7 +1,11 @@
-use boa_engine::{Context, Source};
 use once_cell::sync::Lazy;
 use rand::Rng;
 use regex::Regex;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, COOKIE},
+    Client, ClientBuilder,
+};
+use reqwest_middleware::{ClientBuilder as MiddlewareClientBuilder, ClientWithMiddleware};
 use scraper::{Html, Selector};
 use serde::{Deserialize, Serialize};
 use std::{
@@ -9,19 +13,24 @@ use std::{
     cmp::{min, Ordering},
     collections::HashMap,
     net::IpAddr,
+    sync::Arc,
 };
-use tokio::sync::RwLock;
+use tokio::sync::{OnceCell, RwLock};
 use urlencoding::decode;
 
 use crate::{
+    auth::AuthMiddleware,
     constants::{
-        AGE_RESTRICTED_URLS, AUDIO_ENCODING_RANKS, BASE_URL, FORMATS, IPV6_REGEX, PARSE_INT_REGEX,
-        VALID_QUERY_DOMAINS, VIDEO_ENCODING_RANKS,
+        AGE_RESTRICTED_URLS, AUDIO_ENCODING_RANKS, BASE_URL, DEFAULT_MAX_RETRIES, FORMATS,
+        IPV6_REGEX, PARSE_INT_REGEX, VALID_QUERY_DOMAINS, VIDEO_ENCODING_RANKS,
     },
     info_extras::{get_author, get_chapters, get_dislikes, get_likes, get_storyboards},
+    pool::EgressMiddleware,
+    retry::RetryMiddleware,
     structs::{
-        Embed, PlayerResponse, StreamingDataFormat, StringUtils, VideoDetails, VideoError,
-        VideoFormat, VideoOptions, VideoQuality, VideoSearchOptions, YTConfig,
+        CipherOptions, CipherReport, CipherStrategy, Embed, PlayerResponse, RemovalKind,
+        RequestOptions, StreamingDataFormat, StringUtils, VideoDetails, VideoError, VideoFormat,
+        VideoOptions, VideoQuality, VideoSearchOptions, YTConfig,
     },
 };
 
@@ -38,31 +47,30 @@ pub fn get_html5player(body: &str) -> Option<String> {
 }
 
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn parse_video_formats(
+pub async fn parse_video_formats(
     info: &PlayerResponse,
-    format_functions: Vec<(String, String)>,
+    player_functions: &PlayerFunctions,
+    cipher_options: &CipherOptions,
+    cipher_report: &mut CipherReport,
 ) -> Option<Vec<VideoFormat>> {
     if let Some(streaming_data) = info.streaming_data.as_ref() {
         let formats = streaming_data.formats.clone().unwrap_or_default();
         let adaptive_formats = streaming_data.adaptive_formats.clone().unwrap_or_default();
 
-        let mut formats: Vec<StreamingDataFormat> =
-            formats.into_iter().chain(adaptive_formats).collect();
+        let mut formats: Vec<StreamingDataFormat> = formats
+            .into_iter()
+            .chain(adaptive_formats)
+            .filter(|format| format.mime_type.is_some())
+            .collect();
 
-        let mut n_transform_cache: HashMap<String, String> = HashMap::new();
-        let mut cipher_cache: Option<(String, Context)> = None;
+        let solutions =
+            solve_cipher(&formats, player_functions, cipher_options, cipher_report).await;
 
         let well_formated_formats: Vec<VideoFormat> = formats
             .iter_mut()
-            .filter(|format| format.mime_type.is_some())
             .map(|format| {
                 let mut video_format = VideoFormat::from(format.clone());
-                video_format.url = set_download_url(
-                    format,
-                    format_functions.clone(),
-                    &mut n_transform_cache,
-                    &mut cipher_cache,
-                );
+                video_format.url = set_download_url(format, &solutions, cipher_report);
                 add_format_meta(&mut video_format);
                 video_format
             })
@@ -351,33 +359,118 @@ pub fn sort_formats(a: &VideoFormat, b: &VideoFormat) -> Ordering {
     )
 }
 
+/// Results of the player functions keyed by their input
+#[derive(Clone, Debug, Default)]
+pub struct CipherSolutions {
+    pub signatures: HashMap<String, String>,
+    pub n_transforms: HashMap<String, String>,
+}
+
+/// Run the player functions once for every distinct signature and n-parameter of the formats
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn set_download_url(
-    format: &mut StreamingDataFormat,
-    functions: Vec<(String, String)>,
-    n_transform_cache: &mut HashMap<String, String>,
-    cipher_cache: &mut Option<(String, Context)>,
-) -> String {
-    #[derive(Debug, Deserialize, PartialEq, Serialize)]
-    struct Query {
-        n: String,
-        url: String,
-        s: String,
-        sp: String,
+pub async fn solve_cipher(
+    formats: &[StreamingDataFormat],
+    functions: &PlayerFunctions,
+    cipher_options: &CipherOptions,
+    cipher_report: &mut CipherReport,
+) -> CipherSolutions {
+    let mut signatures: Vec<String> = vec![];
+    let mut n_values: Vec<String> = vec![];
+
+    for format in formats {
+        let url = match format.url.as_ref() {
+            Some(url) => url.clone(),
+            None => {
+                let args = get_cipher_args(
+                    &format
+                        .signature_cipher
+                        .clone()
+                        .unwrap_or(format.cipher.clone().unwrap_or_default()),
+                );
+
+                if let Some(signature) = args.get("s").and_then(serde_json::Value::as_str) {
+                    if !signatures.iter().any(|x| x == signature) {
+                        signatures.push(signature.to_string());
+                    }
+                }
+
+                match args.get("url").and_then(serde_json::Value::as_str) {
+                    Some(url) => url.to_string(),
+                    None => continue,
+                }
+            }
+        };
+
+        if let Some(n_value) = get_n_value(&url) {
+            if !n_values.contains(&n_value) {
+                n_values.push(n_value);
+            }
+        }
     }
 
-    let empty_script: (&str, &str) = ("", "");
-    let decipher_script_string = functions
-        .first()
-        .map(|(a, b)| (a.as_str(), b.as_str()))
-        .unwrap_or(empty_script);
-    let n_transform_script_string = functions
-        .get(1)
-        .map(|(a, b)| (a.as_str(), b.as_str()))
-        .unwrap_or(empty_script);
+    CipherSolutions {
+        signatures: run_player_function(
+            functions.decipher.as_ref(),
+            signatures,
+            cipher_options,
+            cipher_report,
+        )
+        .await,
+        n_transforms: run_player_function(
+            functions.n_transform.as_ref(),
+            n_values,
+            cipher_options,
+            cipher_report,
+        )
+        .await,
+    }
+}
+
+async fn run_player_function(
+    function: Option<&(String, String)>,
+    inputs: Vec<String>,
+    cipher_options: &CipherOptions,
+    cipher_report: &mut CipherReport,
+) -> HashMap<String, String> {
+    let (function_name, script) = match function {
+        Some(function) if !inputs.is_empty() => function,
+        _ => return HashMap::new(),
+    };
+
+    let runtime = &cipher_options.js_runtime;
+    cipher_report.js_runtime = Some(runtime.name().to_string());
+
+    match runtime
+        .call(script, function_name, &inputs, &cipher_options.js_limits)
+        .await
+    {
+        Ok(results) => inputs
+            .into_iter()
+            .zip(results)
+            .filter_map(|(input, result)| match result {
+                Ok(result) => Some((input, result)),
+                // Only this input stays unsolved
+                Err(err) => {
+                    cipher_report.js_error.get_or_insert(err.to_string());
+                    None
+                }
+            })
+            .collect(),
+        Err(err) => {
+            cipher_report.js_error.get_or_insert(err.to_string());
+            HashMap::new()
+        }
+    }
+}
 
+#[cfg_attr(feature = "performance_analysis", flamer::flame)]
+pub fn set_download_url(
+    format: &mut StreamingDataFormat,
+    solutions: &CipherSolutions,
+    cipher_report: &mut CipherReport,
+) -> String {
     if let Some(url) = format.url.as_ref() {
-        format.url = Some(ncode(url, n_transform_script_string, n_transform_cache));
+        format.url = Some(ncode(url, &solutions.n_transforms, cipher_report));
     } else {
         let url = format
             .signature_cipher
@@ -385,26 +478,38 @@ pub fn set_download_url(
             .unwrap_or(format.cipher.clone().unwrap_or_default());
 
         format.url = Some(ncode(
-            decipher(&url, decipher_script_string, cipher_cache).as_str(),
-            n_transform_script_string,
-            n_transform_cache,
+            decipher(&url, &solutions.signatures, cipher_report).as_str(),
+            &solutions.n_transforms,
+            cipher_report,
         ));
     }
 
     format.url.clone().unwrap_or("".to_string())
 }
 
+fn get_cipher_args(url: &str) -> serde_json::value::Map<String, serde_json::Value> {
+    #[cfg(feature = "performance_analysis")]
+    let _guard = flame::start_guard("serde_qs::from_str");
+    serde_qs::from_str(url).unwrap_or_default()
+}
+
+fn get_n_value(url: &str) -> Option<String> {
+    let components: serde_json::value::Map<String, serde_json::Value> =
+        serde_qs::from_str(&decode(url).unwrap_or(Cow::Borrowed(url))).unwrap_or_default();
+
+    components
+        .get("n")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string)
+}
+
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
 fn decipher(
     url: &str,
-    decipher_script_string: (&str, &str),
-    cipher_cache: &mut Option<(String, Context)>,
+    signatures: &HashMap<String, String>,
+    cipher_report: &mut CipherReport,
 ) -> String {
-    let args: serde_json::value::Map<String, serde_json::Value> = {
-        #[cfg(feature = "performance_analysis")]
-        let _guard = flame::start_guard("serde_qs::from_str");
-        serde_qs::from_str(url).unwrap()
-    };
+    let args = get_cipher_args(url);
 
     let get_url_string = || {
         args.get("url")
@@ -413,46 +518,23 @@ fn decipher(
             .to_string()
     };
 
-    if args.get("s").is_none() || decipher_script_string.1.is_empty() {
+    if args.get("s").is_none() {
         return get_url_string();
     }
 
-    let context = match cipher_cache {
-        Some((cache_key, context)) if cache_key == decipher_script_string.1 => context,
-        _ => {
-            #[cfg(feature = "performance_analysis")]
-            let _guard = flame::start_guard("build engine");
-            let mut context = Context::default();
-            if context
-                .eval(Source::from_bytes(decipher_script_string.1))
-                .is_err()
-            {
-                return get_url_string();
-            }
-            *cipher_cache = Some((decipher_script_string.1.to_string(), context));
-            &mut cipher_cache.as_mut().unwrap().1
-        }
-    };
-
-    let result = {
-        #[cfg(feature = "performance_analysis")]
-        let _guard = flame::start_guard("execute engine");
-        context.eval(Source::from_bytes(&format!(
-            r#"{func_name}("{args}")"#,
-            func_name = decipher_script_string.0,
-            args = args
-                .get("s")
-                .and_then(serde_json::Value::as_str)
-                .unwrap_or("")
-        )))
+    // From here on the signature is required, every fallback leaves the format unplayable
+    let mut failed = || {
+        cipher_report.decipher_failures += 1;
+        get_url_string()
     };
 
-    let result = match result {
-        Ok(res) => res
-            .as_string()
-            .and_then(|s| s.to_std_string().ok())
-            .unwrap_or_else(get_url_string),
-        Err(_) => return get_url_string(),
+    let result = match args
+        .get("s")
+        .and_then(serde_json::Value::as_str)
+        .and_then(|signature| signatures.get(signature))
+    {
+        Some(res) => res.clone(),
+        None => return failed(),
     };
 
     let mut return_url = match args
@@ -461,7 +543,7 @@ fn decipher(
         .map_or_else(|| Err(url::ParseError::EmptyHost), url::Url::parse)
     {
         Ok(url) => url,
-        Err(_) => return get_url_string(),
+        Err(_) => return failed(),
     };
     let query_name = args
         .get("sp")
@@ -491,87 +573,49 @@ fn decipher(
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
 fn ncode(
     url: &str,
-    n_transform_script_string: (&str, &str),
-    n_transfrom_cache: &mut HashMap<String, String>,
+    n_transforms: &HashMap<String, String>,
+    cipher_report: &mut CipherReport,
 ) -> String {
-    let components: serde_json::value::Map<String, serde_json::Value> =
-        serde_qs::from_str(&decode(url).unwrap_or(Cow::Borrowed(url))).unwrap_or_default();
-
-    let n_transform_value = match components.get("n").and_then(serde_json::Value::as_str) {
-        Some(val) if !n_transform_script_string.1.is_empty() => val,
-        _ => return url.to_string(),
+    let n_transform_value = match get_n_value(url) {
+        Some(val) => val,
+        None => return url.to_string(),
     };
 
-    if let Some(result) = n_transfrom_cache.get(n_transform_value) {
-        return update_url_with_n(url, result);
-    }
-
-    #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-    fn create_transform_script(script: &str) -> Option<Context> {
-        let mut context = Context::default();
-        context.eval(Source::from_bytes(script)).ok()?;
-        Some(context)
+    match n_transforms.get(&n_transform_value) {
+        // The player returns the sentinel instead of throwing when the transform fails
+        Some(res) if res.starts_with("enhanced_except_") => {
+            cipher_report.n_transform_sentinel = true;
+            cipher_report.n_transform_failures += 1;
+            url.to_string()
+        }
+        Some(res) => update_url_with_n(url, res),
+        None => {
+            cipher_report.n_transform_failures += 1;
+            url.to_string()
+        }
     }
+}
 
-    #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-    fn execute_transform_script(
-        context: &mut Context,
-        func_name: &str,
-        n_transform_value: &str,
-    ) -> Option<String> {
-        context
-            .eval(Source::from_bytes(&format!(
-                r#"{func_name}("{n_transform_value}")"#,
-                func_name = func_name,
-                n_transform_value = n_transform_value
-            )))
-            .ok()
-            .and_then(|result| {
-                result
-                    .as_string()
-                    .map(|js_str| js_str.to_std_string().unwrap_or_default())
+fn update_url_with_n(url: &str, n_value: &str) -> String {
+    let return_url = url::Url::parse(url);
+    if let Ok(mut return_url) = return_url {
+        let query: Vec<(String, String)> = return_url
+            .query_pairs()
+            .map(|(name, value)| {
+                if name == "n" {
+                    (name.into_owned(), n_value.to_string())
+                } else {
+                    (name.into_owned(), value.into_owned())
+                }
             })
-    }
-
-    let mut context = match create_transform_script(n_transform_script_string.1) {
-        Some(res) => res,
-        None => return url.to_string(),
-    };
-
-    let result = match execute_transform_script(
-        &mut context,
-        n_transform_script_string.0,
-        n_transform_value,
-    ) {
-        Some(res) => res,
-        None => return url.to_string(),
-    };
-
-    n_transfrom_cache.insert(n_transform_value.to_owned(), result.clone());
-
-    fn update_url_with_n(url: &str, n_value: &str) -> String {
-        let return_url = url::Url::parse(url);
-        if let Ok(mut return_url) = return_url {
-            let query: Vec<(String, String)> = return_url
-                .query_pairs()
-                .map(|(name, value)| {
-                    if name == "n" {
-                        (name.into_owned(), n_value.to_string())
-                    } else {
-                        (name.into_owned(), value.into_owned())
-                    }
-                })
-                .collect();
+            .collect();
 
-            return_url.query_pairs_mut().clear().extend_pairs(query);
+        return_url.query_pairs_mut().clear().extend_pairs(query);
 
-            return_url.to_string()
-        } else {
-            url.to_string()
-        }
+        return_url.to_string()
+    } else {
+        url.to_string()
     }
-
-    update_url_with_n(url, &result)
 }
 
 /// Excavate video id from URLs or id with Regex
@@ -815,6 +859,8 @@ pub fn clean_video_details(
             .and_then(|x| x.is_unplugged_corpus)
             .unwrap_or(false),
         is_live_content: is_live(player_response),
+        is_upcoming: is_upcoming(player_response),
+        scheduled_start_time: get_scheduled_start_time(player_response, initial_response),
         thumbnails: [
             video_details
                 .as_ref()
@@ -854,7 +900,7 @@ pub fn is_age_restricted(media: &serde_json::Value) -> bool {
         AGE_RESTRICTED_URLS.iter().any(|url| {
             media_object
                 .values()
-                .any(|value| value.as_str().map_or(false, |v| v.contains(url)))
+                .any(|value| value.as_str().is_some_and(|v| v.contains(url)))
         })
     } else {
         false
@@ -909,80 +955,164 @@ pub fn is_age_restricted_from_html(player_response: &PlayerResponse, html: &str)
     is_family_friendly == "false" || og_restrictions_age == "18+"
 }
 
+/// Unix timestamp an upcoming live stream or premiere is scheduled to start at
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn is_rental(player_response: &PlayerResponse) -> bool {
-    if player_response.playability_status.is_none() {
-        return false;
+pub fn get_scheduled_start_time(
+    player_response: &PlayerResponse,
+    initial_response: &serde_json::Value,
+) -> Option<u64> {
+    fn find_key<'a>(value: &'a serde_json::Value, key: &str) -> Option<&'a serde_json::Value> {
+        match value {
+            serde_json::Value::Object(object) => object
+                .get(key)
+                .or_else(|| object.values().find_map(|value| find_key(value, key))),
+            serde_json::Value::Array(array) => array.iter().find_map(|value| find_key(value, key)),
+            _ => None,
+        }
     }
 
-    player_response
+    let offline_slate = player_response
         .playability_status
         .as_ref()
-        .and_then(|x| x.status.clone())
-        .map(|x| x == "UNPLAYABLE")
-        .unwrap_or(false)
-        && player_response
-            .playability_status
-            .as_ref()
-            .and_then(|x| x.error_screen.clone())
-            .and_then(|x| x.player_legacy_desktop_ypc_offer_renderer)
-            .is_some()
+        .and_then(|x| x.live_streamability.as_ref())
+        .and_then(|x| {
+            x["liveStreamabilityRenderer"]["offlineSlate"]["liveStreamOfflineSlateRenderer"]
+                ["scheduledStartTime"]
+                .as_str()
+        });
+
+    offline_slate
+        .or_else(|| find_key(initial_response, "upcomingEventData")?["startTime"].as_str())
+        .and_then(|x| x.parse::<u64>().ok())
 }
 
+/// Whether the video is a live stream or premiere which has not started yet
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn is_not_yet_broadcasted(player_response: &PlayerResponse) -> bool {
-    if player_response.playability_status.is_none() {
-        return false;
-    }
-
+pub fn is_upcoming(player_response: &PlayerResponse) -> bool {
     player_response
-        .playability_status
+        .video_details
         .as_ref()
-        .and_then(|x| x.status.clone())
-        .map(|x| x == "LIVE_STREAM_OFFLINE")
+        .and_then(|x| x.is_upcoming)
         .unwrap_or(false)
+        || matches!(
+            get_playability_error(player_response),
+            Some(VideoError::PremiereScheduled { .. })
+        )
 }
 
+/// Typed error of a player response which can't be played, `None` if it is playable
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn is_play_error(player_response: &PlayerResponse, statuses: Vec<&str>) -> bool {
-    let playability_status = player_response
-        .playability_status
+pub fn get_playability_error(player_response: &PlayerResponse) -> Option<VideoError> {
+    let playability_status = player_response.playability_status.as_ref()?;
+    let status = playability_status.status.as_deref()?;
+    let reason = playability_status.reason.clone();
+
+    // Reasons are often generic ("Video unavailable"), details live in the subreason and messages
+    let error_message = playability_status
+        .error_screen
         .as_ref()
-        .and_then(|x| x.status.clone());
-
-    if let Some(playability_some) = playability_status {
-        return statuses.contains(&playability_some.as_str());
-    }
-
-    false
-}
-
-#[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn is_player_response_error(
-    player_response: &PlayerResponse,
-    reasons: &[&str],
-) -> Option<String> {
-    if let Some(reason) = player_response
-        .playability_status
+        .and_then(|x| x.player_error_message_renderer.as_ref());
+    let subreason = error_message.map(|x| {
+        let subreason = &x["subreason"];
+        subreason["simpleText"].as_str().map_or_else(
+            || {
+                subreason["runs"]
+                    .as_array()
+                    .map(|runs| runs.iter().filter_map(|run| run["text"].as_str()).collect())
+                    .unwrap_or_default()
+            },
+            str::to_string,
+        )
+    });
+    let text = [reason.clone(), subreason]
+        .into_iter()
+        .flatten()
+        .chain(playability_status.messages.iter().flatten().cloned())
+        .collect::<Vec<String>>()
+        .join(" ")
+        .to_lowercase();
+    let mentions = |needles: &[&str]| needles.iter().any(|needle| text.contains(needle));
+
+    let available_countries = || {
+        player_response
+            .micro_format
+            .as_ref()
+            .and_then(|x| x.player_micro_format_renderer.as_ref())
+            .and_then(|x| x.available_countries.clone())
+            .unwrap_or_default()
+    };
+    let scheduled_start_time =
+        || get_scheduled_start_time(player_response, &serde_json::Value::Null);
+    let is_paid = playability_status
+        .error_screen
         .as_ref()
-        .and_then(|status| status.reason.as_deref())
-    {
-        if reasons.contains(&reason) {
-            return Some(reason.to_string());
+        .is_some_and(|x| x.player_legacy_desktop_ypc_offer_renderer.is_some());
+    let is_age_gated = playability_status.desktop_legacy_age_gate_reason.is_some();
+
+    // Status and structured fields first, the text only decides what they leave open
+    let error = match status {
+        "OK" => return None,
+        _ if is_paid => VideoError::PaymentRequired { reason },
+        "AGE_CHECK_REQUIRED" | "AGE_VERIFICATION_REQUIRED" => VideoError::AgeRestricted { reason },
+        _ if is_age_gated => VideoError::AgeRestricted { reason },
+        // Streams which ended without a recording are offline as well
+        "LIVE_STREAM_OFFLINE" if mentions(&["recording is not available"]) => {
+            VideoError::LiveEndedWithoutArchive { reason }
         }
-    }
-
-    None
-}
+        "LIVE_STREAM_OFFLINE" => VideoError::PremiereScheduled {
+            scheduled_start_time: scheduled_start_time(),
+            reason,
+        },
+        _ if scheduled_start_time().is_some() => VideoError::PremiereScheduled {
+            scheduled_start_time: scheduled_start_time(),
+            reason,
+        },
+        _ if mentions(&["not a bot"]) => VideoError::LoginRequired { reason },
+        _ if mentions(&["copyright"]) => VideoError::VideoRemoved {
+            kind: RemovalKind::Copyright,
+            reason,
+        },
+        _ if mentions(&["terms of service", "community guidelines"]) => VideoError::VideoRemoved {
+            kind: RemovalKind::TermsOfService,
+            reason,
+        },
+        _ if mentions(&[
+            "removed by the uploader",
+            "account associated",
+            "terminated",
+        ]) =>
+        {
+            VideoError::VideoRemoved {
+                kind: RemovalKind::Other,
+                reason,
+            }
+        }
+        _ if mentions(&["your country"]) => VideoError::GeoBlocked {
+            available_countries: available_countries(),
+            reason,
+        },
+        _ if mentions(&["members-only", "join this channel"]) => VideoError::MembersOnly { reason },
+        _ if mentions(&["requires payment", "rent this video"]) => {
+            VideoError::PaymentRequired { reason }
+        }
+        _ if mentions(&["recording is not available"]) => {
+            VideoError::LiveEndedWithoutArchive { reason }
+        }
+        _ if mentions(&["confirm your age", "inappropriate for some users"]) => {
+            VideoError::AgeRestricted { reason }
+        }
+        _ if mentions(&["premieres in", "premiere will begin"]) => VideoError::PremiereScheduled {
+            scheduled_start_time: None,
+            reason,
+        },
+        _ if mentions(&["video is private", "private video"]) => VideoError::VideoIsPrivate,
+        "LOGIN_REQUIRED" => VideoError::LoginRequired { reason },
+        "ERROR" => VideoError::VideoNotFound,
+        // Unknown statuses may still carry formats
+        _ => return None,
+    };
 
-#[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn is_private_video(player_response: &PlayerResponse) -> bool {
-    player_response
-        .playability_status
-        .as_ref()
-        .and_then(|x| x.status.clone())
-        .map(|x| x == "LOGIN_REQUIRED")
-        .unwrap_or(false)
+    Some(error)
 }
 
 pub fn get_ytconfig(html: &str) -> Result<YTConfig, VideoError> {
@@ -1005,14 +1135,25 @@ pub fn get_visitor_data(html: &str) -> Result<String, VideoError> {
     }
 }
 
-type CacheFunctions = Lazy<RwLock<Option<(String, Vec<(String, String)>)>>>;
+/// Signature and n-transform functions extracted from a player script
+#[derive(Clone, Debug, Default)]
+pub struct PlayerFunctions {
+    /// (function name, function script)
+    pub decipher: Option<(String, String)>,
+    /// (function name, function script)
+    pub n_transform: Option<(String, String)>,
+    pub report: CipherReport,
+}
+
+// Concurrent requests for the same player wait on the cell instead of fetching it again
+type CacheFunctions = Lazy<RwLock<Option<(String, Arc<OnceCell<PlayerFunctions>>)>>>;
 static FUNCTIONS: CacheFunctions = Lazy::new(|| RwLock::new(None));
 
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
 pub async fn get_functions(
     html5player: impl Into<String>,
     client: &reqwest_middleware::ClientWithMiddleware,
-) -> Result<Vec<(String, String)>, VideoError> {
+) -> Result<PlayerFunctions, VideoError> {
     let mut url = url::Url::parse(BASE_URL).expect("IMPOSSIBLE");
     url.set_path(&html5player.into());
     url.query_pairs_mut().clear();
@@ -1021,31 +1162,49 @@ pub async fn get_functions(
 
     // println!("html5player url: {}", url);
 
-    {
-        // Check if an URL is already cached
-        if let Some((cached_url, cached_functions)) = FUNCTIONS.read().await.as_ref() {
+    let cached_functions = {
+        let mut cache = FUNCTIONS.write().await;
+        match cache.as_ref() {
             // Check if the cache is the same as the URL
-            if cached_url == url {
-                return Ok(cached_functions.clone());
+            Some((cached_url, cached_functions)) if cached_url == url => cached_functions.clone(),
+            // Update the cache
+            _ => {
+                let cached_functions = Arc::new(OnceCell::new());
+                *cache = Some((url.to_string(), cached_functions.clone()));
+                cached_functions
             }
         }
-    }
+    };
 
-    let response = get_html(client, url, None).await?;
+    let functions = cached_functions
+        .get_or_try_init(|| async {
+            let response = get_html(client, url, None).await?;
 
-    let functions = extract_functions(response);
+            let mut functions = extract_functions(response);
+            functions.report.player_url = url.to_string();
+            functions.report.player_version = get_player_version(url);
 
-    // Update the cache
-    {
-        *FUNCTIONS.write().await = Some((url.to_string(), functions.clone()));
-    }
+            Ok::<_, VideoError>(functions)
+        })
+        .await?;
 
-    Ok(functions)
+    Ok(functions.clone())
+}
+
+/// Get player version from player script URL (eg: `/s/player/9c6dfc4a/player_ias.vflset/en_US/base.js`)
+pub fn get_player_version(player_url: &str) -> Option<String> {
+    static PLAYER_VERSION_REGEX: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"/s/player/([a-zA-Z0-9_-]+)/").unwrap());
+
+    PLAYER_VERSION_REGEX
+        .captures(player_url)
+        .and_then(|caps| caps.get(1))
+        .map(|version| version.as_str().to_string())
 }
 
 #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-pub fn extract_functions(body: String) -> Vec<(String, String)> {
-    let mut functions: Vec<(String, String)> = vec![];
+pub fn extract_functions(body: String) -> PlayerFunctions {
+    let mut functions = PlayerFunctions::default();
 
     #[cfg_attr(feature = "performance_analysis", flamer::flame)]
     fn extract_manipulations(body: String, caller: &str) -> String {
@@ -1070,7 +1229,7 @@ pub fn extract_functions(body: String) -> Vec<(String, String)> {
     }
 
     #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-    fn extract_decipher(body: String, functions: &mut Vec<(String, String)>) {
+    fn extract_decipher(body: String, functions: &mut PlayerFunctions) {
         let function_name = between(body.as_str(), r#"a.set("alr","yes");c&&(c="#, "(decodeURIC");
         // println!("decipher function name: {}", function_name);
         if !function_name.is_empty() {
@@ -1093,14 +1252,17 @@ pub fn extract_functions(body: String) -> Vec<(String, String)> {
 
                 // println!("decipher function: {}", function_body);
 
-                functions.push((function_name.to_string(), function_body));
+                functions.report.decipher_function = Some(function_name.to_string());
+                functions.report.decipher_strategy = Some(CipherStrategy::CallSite);
+                functions.decipher = Some((function_name.to_string(), function_body));
             }
         }
     }
 
     #[cfg_attr(feature = "performance_analysis", flamer::flame)]
-    fn extract_ncode(body: String, functions: &mut Vec<(String, String)>) {
+    fn extract_ncode(body: String, functions: &mut PlayerFunctions) {
         let mut function_name = between(body.as_str(), r#"c=a.get(b))&&(c="#, "(c)");
+        let mut strategy = CipherStrategy::CallSite;
 
         let left_name = format!(
             "var {splitted_function_name}=[",
@@ -1113,6 +1275,7 @@ pub fn extract_functions(body: String) -> Vec<(String, String)> {
 
         if function_name.contains('[') {
             function_name = between(body.as_str(), left_name.as_str(), "]");
+            strategy = CipherStrategy::ArrayLookup;
         }
 
         if function_name.is_empty() {
@@ -1132,6 +1295,7 @@ pub fn extract_functions(body: String) -> Vec<(String, String)> {
 
                     if function_body.contains("enhanced_except_") {
                         function_name = name;
+                        strategy = CipherStrategy::SentinelScan;
                     }
                 }
             }
@@ -1154,7 +1318,9 @@ pub fn extract_functions(body: String) -> Vec<(String, String)> {
 
                 // println!("ncode function: {}", function_body);
 
-                functions.push((function_name.to_string(), function_body));
+                functions.report.n_transform_function = Some(function_name.to_string());
+                functions.report.n_transform_strategy = Some(strategy);
+                functions.n_transform = Some((function_name.to_string(), function_body));
             }
         }
     }
@@ -1190,6 +1356,74 @@ pub async fn get_html(
     Ok(response_first)
 }
 
+/// Build the retrying client described by the request options
+pub fn build_client(request_options: &RequestOptions) -> Result<ClientWithMiddleware, VideoError> {
+    let client = match request_options.client.clone() {
+        Some(client) => client,
+        None => client_builder(request_options)?
+            .build()
+            .map_err(VideoError::Reqwest)?,
+    };
+
+    Ok(retry_client(client, request_options))
+}
+
+/// Client builder with the proxy, IPv6 block and cookies of the request options
+pub fn client_builder(request_options: &RequestOptions) -> Result<ClientBuilder, VideoError> {
+    let mut client_builder = Client::builder();
+
+    // Pools take precedence over the single proxy and IPv6 block, their egress is set per request
+    if let (Some(proxy), None) = (&request_options.proxy, &request_options.proxy_pool) {
+        client_builder = client_builder.proxy(proxy.clone());
+    }
+
+    if let (Some(ipv6_block), None) = (&request_options.ipv6_block, &request_options.address_pool) {
+        let ipv6 = get_random_v6_ip(ipv6_block)?;
+        client_builder = client_builder.local_address(ipv6);
+    }
+
+    if let Some(cookie_jar) = &request_options.cookie_jar {
+        client_builder = client_builder.cookie_provider(Arc::new(cookie_jar.clone()));
+    } else if let Some(cookie) = &request_options.cookies {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            COOKIE,
+            HeaderValue::from_str(cookie).map_err(|_x| VideoError::CookieError)?,
+        );
+
+        client_builder = client_builder.default_headers(headers)
+    }
+
+    Ok(client_builder)
+}
+
+/// Wrap the client with the OAuth login, retry policy and the proxy and address pools of the request options
+pub fn retry_client(client: Client, request_options: &RequestOptions) -> ClientWithMiddleware {
+    let max_retries = request_options.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+
+    let mut client = MiddlewareClientBuilder::new(client);
+    if let Some(auth) = &request_options.auth {
+        // Authorization is added once, retries send the same token
+        client = client.with(AuthMiddleware::new(auth.clone()));
+    }
+
+    let client = client.with(RetryMiddleware::new(
+        request_options.retry_policy.clone(),
+        max_retries,
+    ));
+
+    // Pools are ignored like the rest of the options when a client is provided
+    let use_pools = request_options.client.is_none()
+        && (request_options.proxy_pool.is_some() || request_options.address_pool.is_some());
+
+    if use_pools {
+        // Runs after the retry middleware, every attempt gets its own egress
+        client.with(EgressMiddleware::new(request_options)).build()
+    } else {
+        client.build()
+    }
+}
+
 /// Try to generate IPv6 with custom valid block
 /// # Example
 /// ```ignore
@@ -1411,6 +1645,9 @@ pub fn cut_after_js(mixed_json: &str) -> Option<&str> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::structs::JsLimits;
+    use crate::JsRuntime;
+    use serde_json::json;
 
     #[test]
     fn test_cut_after_js() {
@@ -1525,4 +1762,381 @@ mod tests {
         assert!(cut_after_js(r#"{"a": 1,{ "b": 1}"#).is_none());
         println!("[PASSED] test_returns_error_when_missing_closing_bracket");
     }
+
+    const TEST_PLAYER: &str = r#"var Zq={ab:function(a,b){a.splice(0,b)},cd:function(a){a.reverse()}};
+Xy=function(a){a=a.split("");Zq.ab(a,1);Zq.cd(a,0);return a.join("")};
+Mn=function(a){var b=a.split("");try{b.reverse()}catch(d){return"enhanced_except_"+a}return b.join("")};
+g.k=function(a,b,c){a.set("alr","yes");c&&(c=Xy(decodeURIComponent(c)),a.set(b,c))};
+g.l=function(a,b,c){(b=a.get("n"))&&(b=a.get(b))&&(c=Mn(c),a.set("n",c))};"#;
+
+    fn test_format(signature_cipher: &str) -> StreamingDataFormat {
+        StreamingDataFormat {
+            signature_cipher: Some(signature_cipher.to_string()),
+            ..Default::default()
+        }
+    }
+
+    async fn test_download_url(
+        mut format: StreamingDataFormat,
+        functions: &PlayerFunctions,
+        cipher_options: &CipherOptions,
+        report: &mut CipherReport,
+    ) -> String {
+        let solutions = solve_cipher(
+            std::slice::from_ref(&format),
+            functions,
+            cipher_options,
+            report,
+        )
+        .await;
+        set_download_url(&mut format, &solutions, report)
+    }
+
+    #[tokio::test]
+    async fn test_extract_functions_report() {
+        let functions = extract_functions(TEST_PLAYER.to_string());
+
+        assert_eq!(functions.report.decipher_function.as_deref(), Some("Xy"));
+        assert_eq!(
+            functions.report.decipher_strategy,
+            Some(CipherStrategy::CallSite)
+        );
+        assert_eq!(functions.report.n_transform_function.as_deref(), Some("Mn"));
+        assert_eq!(
+            get_player_version("/s/player/9c6dfc4a/player_ias.vflset/en_US/base.js").as_deref(),
+            Some("9c6dfc4a")
+        );
+
+        let mut report = functions.report.clone();
+        let url = test_download_url(
+            test_format(
+                "s=abcdef&sp=sig&url=https%3A%2F%2Fexample.com%2Fvideoplayback%3Fid%3D1%26n%3Dxyz",
+            ),
+            &functions,
+            &CipherOptions::default(),
+            &mut report,
+        )
+        .await;
+
+        assert_eq!(
+            url,
+            "https://example.com/videoplayback?id=1&n=zyx&sig=fedcb"
+        );
+        assert_eq!(report.js_runtime.as_deref(), Some("boa"));
+        assert!(report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cipher_failures_are_reported() {
+        let functions = PlayerFunctions {
+            n_transform: Some((
+                "Mn".to_string(),
+                r#"var Mn=function(a){return"enhanced_except_"+a};"#.to_string(),
+            )),
+            ..Default::default()
+        };
+
+        let mut report = CipherReport::default();
+        let url = test_download_url(
+            test_format(
+                "s=abcdef&url=https%3A%2F%2Fexample.com%2Fvideoplayback%3Fid%3D1%26n%3Dxyz",
+            ),
+            &functions,
+            &CipherOptions::default(),
+            &mut report,
+        )
+        .await;
+
+        assert_eq!(url, "https://example.com/videoplayback?id=1&n=xyz");
+        assert_eq!(report.decipher_failures, 1);
+        assert_eq!(report.n_transform_failures, 1);
+        assert!(report.n_transform_sentinel);
+        assert!(!report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_js_budget_is_enforced() {
+        let functions = PlayerFunctions {
+            n_transform: Some((
+                "Mn".to_string(),
+                r#"var Mn=function(a){for(;;){try{}catch(e){}}return a};"#.to_string(),
+            )),
+            ..Default::default()
+        };
+
+        let cipher_options = CipherOptions {
+            js_limits: JsLimits {
+                loop_iteration_limit: 10_000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut report = CipherReport::default();
+        let url = test_download_url(
+            StreamingDataFormat {
+                url: Some("https://example.com/videoplayback?id=1&n=xyz".to_string()),
+                ..Default::default()
+            },
+            &functions,
+            &cipher_options,
+            &mut report,
+        )
+        .await;
+
+        assert_eq!(url, "https://example.com/videoplayback?id=1&n=xyz");
+        assert_eq!(report.n_transform_failures, 1);
+        assert_eq!(
+            report.js_error,
+            Some(VideoError::JsBudgetExceeded.to_string())
+        );
+    }
+
+    #[derive(Debug)]
+    struct UppercaseRuntime;
+
+    #[async_trait::async_trait]
+    impl JsRuntime for UppercaseRuntime {
+        fn name(&self) -> &str {
+            "uppercase"
+        }
+
+        async fn call(
+            &self,
+            _script: &str,
+            _function_name: &str,
+            inputs: &[String],
+            _limits: &JsLimits,
+        ) -> Result<Vec<Result<String, VideoError>>, VideoError> {
+            Ok(inputs
+                .iter()
+                .map(|input| Ok(input.to_uppercase()))
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_js_runtime_is_pluggable() {
+        let functions = extract_functions(TEST_PLAYER.to_string());
+        let cipher_options = CipherOptions {
+            js_runtime: std::sync::Arc::new(UppercaseRuntime),
+            ..Default::default()
+        };
+
+        let mut report = CipherReport::default();
+        let url = test_download_url(
+            test_format(
+                "s=abcdef&sp=sig&url=https%3A%2F%2Fexample.com%2Fvideoplayback%3Fid%3D1%26n%3Dxyz",
+            ),
+            &functions,
+            &cipher_options,
+            &mut report,
+        )
+        .await;
+
+        assert_eq!(
+            url,
+            "https://example.com/videoplayback?id=1&n=XYZ&sig=ABCDEF"
+        );
+        assert_eq!(report.js_runtime.as_deref(), Some("uppercase"));
+        assert!(report.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_js_runtime_fails_per_input() {
+        let results = crate::BoaRuntime
+            .call(
+                "function f(x){ if (x === 'bad') throw new Error('bad'); return x + '!' }",
+                "f",
+                &["a".to_string(), "bad".to_string(), "b".to_string()],
+                &JsLimits::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(results[0].as_deref().ok(), Some("a!"));
+        assert!(matches!(results[1], Err(VideoError::JsExecutionError(_))));
+        assert_eq!(results[2].as_deref().ok(), Some("b!"));
+    }
+
+    fn playability_error(player_response: serde_json::Value) -> Option<VideoError> {
+        get_playability_error(&serde_json::from_value(player_response).unwrap())
+    }
+
+    #[test]
+    fn test_playability_errors_are_typed() {
+        assert!(playability_error(json!({"playabilityStatus": {"status": "OK"}})).is_none());
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "LOGIN_REQUIRED",
+                    "reason": "Sign in to confirm you’re not a bot"
+                }
+            })),
+            Some(VideoError::LoginRequired { .. })
+        ));
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "UNPLAYABLE",
+                    "reason": "Video unavailable",
+                    "errorScreen": {
+                        "playerErrorMessageRenderer": {
+                            "subreason": {"runs": [{"text": "The uploader has not made this video available in your country"}]}
+                        }
+                    }
+                },
+                "microformat": {"playerMicroformatRenderer": {"availableCountries": ["DE", "FR"]}}
+            })),
+            Some(VideoError::GeoBlocked { available_countries, .. }) if available_countries == ["DE", "FR"]
+        ));
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "LIVE_STREAM_OFFLINE",
+                    "reason": "Premieres in 2 hours",
+                    "liveStreamability": {
+                        "liveStreamabilityRenderer": {
+                            "offlineSlate": {
+                                "liveStreamOfflineSlateRenderer": {"scheduledStartTime": "1700000000"}
+                            }
+                        }
+                    }
+                }
+            })),
+            Some(VideoError::PremiereScheduled {
+                scheduled_start_time: Some(1700000000),
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "ERROR",
+                    "reason": "This video is no longer available due to a copyright claim by Someone"
+                }
+            })),
+            Some(VideoError::VideoRemoved {
+                kind: RemovalKind::Copyright,
+                ..
+            })
+        ));
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "UNPLAYABLE",
+                    "reason": "This video requires payment to watch",
+                    "errorScreen": {"playerLegacyDesktopYpcOfferRenderer": {"itemTitle": "Movie"}}
+                }
+            })),
+            Some(VideoError::PaymentRequired { .. })
+        ));
+
+        assert!(matches!(
+            playability_error(
+                json!({"playabilityStatus": {"status": "ERROR", "reason": "Video unavailable"}})
+            ),
+            Some(VideoError::VideoNotFound)
+        ));
+
+        assert!(playability_error(json!({
+            "playabilityStatus": {"status": "UNPLAYABLE", "reason": "Video unavailable"}
+        }))
+        .is_none());
+    }
+
+    #[test]
+    fn test_playability_errors_prefer_structured_fields() {
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "LOGIN_REQUIRED",
+                    "reason": "Sign in to confirm your age",
+                    "desktopLegacyAgeGateReason": 1
+                }
+            })),
+            Some(VideoError::AgeRestricted { .. })
+        ));
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "UNPLAYABLE",
+                    "reason": "Join this channel to get access to members-only content like this video"
+                }
+            })),
+            Some(VideoError::MembersOnly { .. })
+        ));
+
+        // Words which only happen to appear in the reason don't decide the error
+        assert!(playability_error(json!({
+            "playabilityStatus": {
+                "status": "UNPLAYABLE",
+                "reason": "The premiere of this video is available to YouTube Premium members"
+            }
+        }))
+        .is_none());
+
+        assert!(matches!(
+            playability_error(json!({
+                "playabilityStatus": {
+                    "status": "LIVE_STREAM_OFFLINE",
+                    "reason": "This live stream recording is not available."
+                }
+            })),
+            Some(VideoError::LiveEndedWithoutArchive { .. })
+        ));
+    }
+
+    #[test]
+    fn test_scheduled_start_time() {
+        let upcoming: PlayerResponse = serde_json::from_value(json!({
+            "playabilityStatus": {"status": "LIVE_STREAM_OFFLINE", "reason": "This live event will begin in 3 hours."},
+            "videoDetails": {"isUpcoming": true, "isLiveContent": true}
+        }))
+        .unwrap();
+        let initial_response = json!({
+            "contents": [{"videoPrimaryInfoRenderer": {"upcomingEventData": {"startTime": "1700000000"}}}]
+        });
+
+        assert!(is_upcoming(&upcoming));
+        assert_eq!(
+            get_scheduled_start_time(&upcoming, &initial_response),
+            Some(1700000000)
+        );
+        assert_eq!(
+            get_scheduled_start_time(&upcoming, &serde_json::Value::Null),
+            None
+        );
+        assert!(!is_upcoming(&PlayerResponse::default()));
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        assert!(VideoError::LoginRequired {
+            reason: Some("Sign in to confirm you’re not a bot".to_string())
+        }
+        .is_retryable());
+        assert!(!VideoError::VideoRemoved {
+            kind: RemovalKind::TermsOfService,
+            reason: None
+        }
+        .is_retryable());
+        assert!(!VideoError::VideoNotFound.is_retryable());
+        assert!(!VideoError::LoginRequired { reason: None }.is_retryable());
+        assert!(VideoError::PremiereScheduled {
+            scheduled_start_time: Some(1700000000),
+            reason: None
+        }
+        .is_retryable());
+        assert!(!VideoError::PremiereScheduled {
+            scheduled_start_time: Some(u64::MAX),
+            reason: None
+        }
+        .is_retryable());
+    }
 }
@@ -0,0 +1,22 @@
+#[tokio::test]
+async fn batch_info() {
+    use futures::StreamExt;
+    use rusty_ytdl::{VideoBatch, VideoError};
+
+    let ids = ["FZ8BxMU3BYc", "not a video", "Rbgw_rduQpM"];
+
+    let batch = VideoBatch::new(ids).unwrap().concurrency(2);
+
+    let mut infos: Vec<_> = batch.basic_info().collect().await;
+    infos.sort_by_key(|(id, _)| ids.iter().position(|x| x == id));
+
+    assert_eq!(infos.len(), ids.len());
+    assert!(matches!(infos[1].1, Err(VideoError::VideoNotFound)));
+
+    for (id, info) in infos {
+        match info {
+            Ok(info) => println!("{id}: {}", info.video_details.title),
+            Err(err) => println!("{id}: {err}"),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! Local HTTP server for tests, shared by the integration tests and the unit tests of the crate
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Upper bound of a request head, larger requests are cut off
+const MAX_HEAD: usize = 64 * 1024;
+
+/// Request received by [`serve`], the head is everything up to the blank line after the headers
+pub struct Request {
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Path of the request line, with its query
+    pub fn path(&self) -> &str {
+        self.head.split_whitespace().nth(1).unwrap_or_default()
+    }
+
+    /// Value of header `name`, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then_some(value.trim())
+        })
+    }
+}
+
+/// Response written by [`serve`] with a `Content-Length` and `Connection: close`
+pub struct Response {
+    /// Status line after the version, followed by any extra header lines, e.g. `"200 OK"` or
+    /// `"429 Too Many Requests\r\nRetry-After: 1"`
+    pub head: String,
+    pub body: Vec<u8>,
+    /// Wait before answering, other connections are served meanwhile
+    pub delay: Duration,
+}
+
+impl Response {
+    pub fn new(head: impl Into<String>, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            head: head.into(),
+            body: body.into(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+/// Answer every connection with `respond` until the test ends, returns the base URL
+pub async fn serve<F>(mut respond: F) -> String
+where
+    F: FnMut(Request) -> Response + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let Some(request) = read_request(&mut socket).await else {
+                continue;
+            };
+            let response = respond(request);
+
+            tokio::spawn(async move {
+                if !response.delay.is_zero() {
+                    tokio::time::sleep(response.delay).await;
+                }
+
+                let mut bytes = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.head,
+                    response.body.len()
+                )
+                .into_bytes();
+                bytes.extend(response.body);
+                let _ = socket.write_all(&bytes).await;
+            });
+        }
+    });
+
+    url
+}
+
+/// Read a request head up to `\r\n\r\n` and its `Content-Length` body, `None` if the connection
+/// closed before the head ended
+pub async fn read_request(socket: &mut TcpStream) -> Option<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+
+    let head_end = loop {
+        if let Some(index) = buf.windows(4).position(|x| x == b"\r\n\r\n") {
+            break index + 4;
+        }
+        if buf.len() > MAX_HEAD {
+            break buf.len();
+        }
+
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(read) => buf.extend_from_slice(&chunk[..read]),
+        }
+    };
+
+    let mut body = buf.split_off(head_end);
+    let request = Request {
+        head: String::from_utf8_lossy(&buf).into_owned(),
+        body: vec![],
+    };
+
+    let length = request
+        .header("content-length")
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
+    while body.len() < length {
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => body.extend_from_slice(&chunk[..read]),
+        }
+    }
+
+    Some(Request { body, ..request })
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "ffmpeg")]
+
+use std::time::Duration;
+
+use rusty_ytdl::{FFmpegArgs, FFmpegPreset, VideoError};
+
+#[test]
+fn build_preset_with_trim_and_metadata() {
+    let args = FFmpegArgs::preset(FFmpegPreset::Mp3_320k)
+        .start(Duration::from_millis(1500))
+        .end(Duration::from_secs(10))
+        .metadata("title", "Chorus");
+
+    assert!(args.validate().is_ok());
+    assert_eq!(
+        args.build()[9..],
+        [
+            "-ss",
+            "1.500",
+            "-to",
+            "10.000",
+            "-c:a",
+            "libmp3lame",
+            "-b:a",
+            "320k",
+            "-metadata",
+            "title=Chorus",
+            "-f",
+            "mp3",
+            "-vn",
+            "pipe:1"
+        ]
+    );
+}
+
+#[test]
+fn empty_args_leave_stream_untouched() {
+    assert!(FFmpegArgs::default().validate().is_ok());
+    assert!(FFmpegArgs::default().build().is_empty());
+}
+
+#[test]
+fn reject_args_which_cannot_pipe() {
+    let invalid =
+        |args: FFmpegArgs| matches!(args.validate(), Err(VideoError::InvalidFFmpegArgs(_)));
+
+    assert!(invalid(FFmpegArgs::default().audio_codec("aac")));
+    assert!(invalid(FFmpegArgs::default().format("hls")));
+    assert!(invalid(FFmpegArgs::default().format("mp4")));
+    assert!(invalid(
+        FFmpegArgs::preset(FFmpegPreset::OpusVoice).video_codec("libx264")
+    ));
+    assert!(invalid(
+        FFmpegArgs::preset(FFmpegPreset::Mp3_320k)
+            .start(Duration::from_secs(5))
+            .end(Duration::from_secs(5))
+    ));
+
+    assert!(FFmpegArgs::preset(FFmpegPreset::H264_720p)
+        .validate()
+        .is_ok());
+    assert!(FFmpegArgs::preset(FFmpegPreset::OpusVoice)
+        .validate()
+        .is_ok());
+}
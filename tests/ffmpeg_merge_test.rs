@@ -0,0 +1,25 @@
+#[ignore]
+#[tokio::test]
+async fn ffmpeg_merge_test() {
+    #[cfg(feature = "ffmpeg")]
+    {
+        use rusty_ytdl::{FFmpegMergeOptions, Video};
+
+        let url = "FZ8BxMU3BYc";
+
+        let video = Video::new(url).unwrap();
+
+        video
+            .download_merged_with_ffmpeg(
+                r"./merged.mkv",
+                FFmpegMergeOptions {
+                    metadata: true,
+                    chapters: true,
+                    thumbnail: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+#![cfg(feature = "live")]
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use aes::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+use rusty_ytdl::{stream::VariantSelection, HlsDownloader, HlsOptions};
+
+mod common;
+
+use common::Response;
+
+const KEY: [u8; 16] = [3; 16];
+const IV: [u8; 16] = [5; 16];
+
+const MASTER: &str = "#EXTM3U
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1920x1080
+1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=640x360
+360p.m3u8
+";
+
+const MEDIA: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:1
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x05050505050505050505050505050505
+#EXTINF:1.0,
+segment-0.ts
+#EXTINF:1.0,
+segment-1.ts
+#EXT-X-ENDLIST
+";
+
+/// The key applies to the initialization segment as well
+const MEDIA_FMP4: &str = "#EXTM3U
+#EXT-X-VERSION:6
+#EXT-X-TARGETDURATION:1
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-KEY:METHOD=AES-128,URI=\"key.bin\",IV=0x05050505050505050505050505050505
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:1.0,
+segment-0.m4s
+#EXTINF:1.0,
+segment-1.m4s
+#EXT-X-ENDLIST
+";
+
+fn encrypt(data: &[u8]) -> Vec<u8> {
+    cbc::Encryptor::<aes::Aes128>::new(&KEY.into(), &IV.into())
+        .encrypt_padded_vec_mut::<Pkcs7>(data)
+}
+
+/// Serve a VOD master playlist with AES-128 encrypted segments, counting the key requests
+async fn serve(key_requests: Arc<AtomicUsize>) -> String {
+    common::serve(move |request| {
+        let body = match request.path() {
+            "/master.m3u8" => MASTER.as_bytes().to_vec(),
+            "/360p.m3u8" => MEDIA.as_bytes().to_vec(),
+            "/1080p.m3u8" => MEDIA_FMP4.as_bytes().to_vec(),
+            "/key.bin" => {
+                key_requests.fetch_add(1, Ordering::SeqCst);
+                KEY.to_vec()
+            }
+            path => encrypt(
+                path.trim_start_matches('/')
+                    .trim_end_matches(".ts")
+                    .trim_end_matches(".m4s")
+                    .trim_end_matches(".mp4")
+                    .as_bytes(),
+            ),
+        };
+
+        Response::new("200 OK", body)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn download_hls_playlist() {
+    let key_requests = Arc::new(AtomicUsize::new(0));
+    let url = serve(key_requests.clone()).await;
+
+    let downloader = HlsDownloader::new_with_options(
+        format!("{url}/master.m3u8"),
+        HlsOptions {
+            variant: VariantSelection::MaxHeight(480),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let path = std::env::temp_dir().join(format!("rusty_ytdl_hls_{}.ts", std::process::id()));
+    downloader.download(&path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"segment-0segment-1");
+    // The key is shared by both segments
+    assert_eq!(key_requests.load(Ordering::SeqCst), 1);
+
+    // Muxed audio, no separate rendition
+    assert!(!downloader.download_audio(&path).await.unwrap());
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn download_encrypted_initialization_segment() {
+    let url = serve(Arc::new(AtomicUsize::new(0))).await;
+
+    let downloader = HlsDownloader::new_with_options(
+        format!("{url}/master.m3u8"),
+        HlsOptions {
+            variant: VariantSelection::MaxHeight(1080),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let path = std::env::temp_dir().join(format!("rusty_ytdl_hls_init_{}.mp4", std::process::id()));
+    downloader.download(&path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"initsegment-0segment-1");
+
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,90 @@
+#![cfg(feature = "live")]
+
+use std::time::Duration;
+
+use rusty_ytdl::stream::{DashLiveStream, DashLiveStreamOptions, Stream};
+
+mod common;
+
+use common::Response;
+
+const HEAD_SEQUENCE: u64 = 3;
+
+/// Serve segment `sq` as `segment-{sq}` up to `HEAD_SEQUENCE`, segment `1` is answered with `status`
+async fn serve(status: &'static str) -> String {
+    common::serve(move |request| {
+        let sequence: u64 = request
+            .path()
+            .split_once("sq=")
+            .and_then(|(_, rest)| rest.split(|c: char| !c.is_ascii_digit()).next())
+            .and_then(|sq| sq.parse().ok())
+            .unwrap();
+
+        let (status, body) = if sequence == 1 {
+            (status, String::new())
+        } else if sequence <= HEAD_SEQUENCE {
+            ("200 OK", format!("segment-{sequence}"))
+        } else {
+            ("404 Not Found", String::new())
+        };
+
+        Response::new(format!("{status}\r\nX-Head-Seqnum: {HEAD_SEQUENCE}"), body)
+    })
+    .await
+}
+
+fn live_stream(url: &str) -> DashLiveStream {
+    DashLiveStream::new(DashLiveStreamOptions {
+        client: Some(reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build()),
+        url: format!("{url}/videoplayback?itag=137&sq=99"),
+        start_sequence: 0,
+        target_duration: Some(Duration::from_millis(10)),
+        end_timeout: Some(Duration::from_millis(200)),
+        refresher: None,
+    })
+    .unwrap()
+}
+
+#[tokio::test]
+async fn record_live_from_start() {
+    let url = serve("404 Not Found").await;
+
+    let stream = live_stream(&url);
+
+    let mut segments = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        segments.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+
+    // Missing segments behind the live edge are skipped, the stream ends once the edge stops moving
+    assert_eq!(segments, ["segment-0", "segment-2", "segment-3"]);
+    assert_eq!(stream.head_sequence().await, Some(HEAD_SEQUENCE));
+    assert_eq!(stream.next_sequence().await, HEAD_SEQUENCE + 1);
+}
+
+#[tokio::test]
+async fn forbidden_segment_fails() {
+    let url = serve("403 Forbidden").await;
+
+    let stream = live_stream(&url);
+
+    assert!(stream.chunk().await.unwrap().is_some());
+    // Without a refresher a forbidden link is an error, not a missing segment
+    assert!(stream.chunk().await.is_err());
+    assert_eq!(stream.next_sequence().await, 1);
+}
+
+#[tokio::test]
+async fn paused_stream_still_fetches() {
+    let url = serve("404 Not Found").await;
+
+    let stream = live_stream(&url);
+
+    // A consumer reading another stream first must not find this one ended
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    assert_eq!(
+        stream.chunk().await.unwrap().as_deref(),
+        Some(&b"segment-0"[..])
+    );
+}
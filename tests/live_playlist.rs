@@ -0,0 +1,369 @@
+#![cfg(feature = "live")]
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use rusty_ytdl::stream::{LiveGap, LiveStream, LiveStreamOptions, Stream, VariantSelection};
+use rusty_ytdl::VideoError;
+
+mod common;
+
+use common::Response;
+
+const MASTER: &str = "#EXTM3U
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"English\",LANGUAGE=\"en\",DEFAULT=YES,URI=\"audio/en.m3u8\"
+#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Deutsch\",LANGUAGE=\"de\",URI=\"audio/de.m3u8\"
+#EXT-X-STREAM-INF:BANDWIDTH=3000000,RESOLUTION=1920x1080,AUDIO=\"aac\"
+broken/1080p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=1000000,RESOLUTION=640x360,AUDIO=\"aac\"
+360p.m3u8
+#EXT-X-STREAM-INF:BANDWIDTH=2000000,RESOLUTION=1280x720,AUDIO=\"aac\"
+720p.m3u8
+";
+
+const MEDIA: &str = "aaaabbbbcccc";
+
+/// Byte ranges of `MEDIA` behind an initialization segment, the last one after a discontinuity
+const RANGES: &str = "#EXTM3U
+#EXT-X-VERSION:4
+#EXT-X-TARGETDURATION:1
+#EXT-X-MEDIA-SEQUENCE:0
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXTINF:1.0,
+#EXT-X-BYTERANGE:4@0
+media.bin
+#EXTINF:1.0,
+#EXT-X-BYTERANGE:4
+media.bin
+#EXT-X-DISCONTINUITY
+#EXTINF:1.0,
+#EXT-X-BYTERANGE:4@8
+media.bin
+#EXT-X-ENDLIST
+";
+
+fn playlist(segments: std::ops::Range<u64>, end_list: bool) -> String {
+    playlist_in("", segments, end_list)
+}
+
+/// Playlist of segments in `directory`
+fn playlist_in(directory: &str, segments: std::ops::Range<u64>, end_list: bool) -> String {
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{}\n",
+        segments.start
+    );
+    for sequence in segments {
+        playlist.push_str(&format!("#EXTINF:1.0,\n{directory}segment-{sequence}.ts\n"));
+    }
+    if end_list {
+        playlist.push_str("#EXT-X-ENDLIST\n");
+    }
+    playlist
+}
+
+/// Serve each playlist path with its playlists one after another on each reload, the last one stays.
+/// Segments are served by name, segments under `/broken/` fail, under `/flaky/` they fail once and
+/// under `/slow/` they take 300ms
+async fn serve(routes: Vec<(&'static str, Vec<String>)>) -> String {
+    let mut reloads = HashMap::new();
+    let mut failed = HashSet::new();
+
+    common::serve(move |request| {
+        let path = request.path().to_string();
+
+        let (status, body) =
+            if let Some((_, playlists)) = routes.iter().find(|(route, _)| *route == path) {
+                let reload: &mut usize = reloads.entry(path.clone()).or_default();
+                *reload += 1;
+                (
+                    "200 OK",
+                    playlists[(*reload - 1).min(playlists.len() - 1)].clone(),
+                )
+            } else if path == "/media.bin" {
+                // Byte ranges of the media file
+                let range = request
+                    .header("range")
+                    .and_then(|x| x.strip_prefix("bytes="))
+                    .unwrap();
+                let (start, end) = range.split_once('-').unwrap();
+                let (start, end): (usize, usize) = (start.parse().unwrap(), end.parse().unwrap());
+                ("206 Partial Content", MEDIA[start..=end].to_string())
+            } else if path.starts_with("/broken/")
+                || (path.starts_with("/flaky/") && failed.insert(path.clone()))
+            {
+                ("500 Internal Server Error", String::new())
+            } else {
+                let name = path.rsplit('/').next().unwrap();
+                ("200 OK", name.trim_end_matches(".ts").to_string())
+            };
+
+        let response = Response::new(status, body);
+        if path.starts_with("/slow/") {
+            response.delay(Duration::from_millis(300))
+        } else {
+            response
+        }
+    })
+    .await
+}
+
+#[tokio::test]
+async fn reload_after_target_duration() {
+    let url = serve(vec![(
+        "/live.m3u8",
+        vec![playlist(0..1, false), playlist(0..2, true)],
+    )])
+    .await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        stream_url: format!("{url}/live.m3u8"),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let started = Instant::now();
+    let mut segments = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        // Waiting for new segments must not surface as empty chunks
+        assert!(!chunk.is_empty());
+        segments.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+
+    assert_eq!(segments, ["segment-0", "segment-1"]);
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn unchanged_playlist_stalls() {
+    let url = serve(vec![("/live.m3u8", vec![playlist(0..1, false)])]).await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        stream_url: format!("{url}/live.m3u8"),
+        stall_timeout: Some(Duration::from_millis(1500)),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert!(stream.chunk().await.unwrap().is_some());
+    assert!(matches!(
+        stream.chunk().await,
+        Err(VideoError::LiveStreamStalled)
+    ));
+}
+
+#[tokio::test]
+async fn select_master_playlist_variant() {
+    let url = serve(vec![
+        ("/master.m3u8", vec![MASTER.to_string()]),
+        ("/720p.m3u8", vec![playlist(0..1, true)]),
+    ])
+    .await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        stream_url: format!("{url}/master.m3u8"),
+        variant: VariantSelection::MaxHeight(720),
+        audio_language: Some("de".to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(
+        stream.media_url().await.unwrap(),
+        format!("{url}/720p.m3u8")
+    );
+
+    let audio = stream.audio_rendition().await.unwrap().unwrap();
+    assert_eq!(
+        audio.media_url().await.unwrap(),
+        format!("{url}/audio/de.m3u8")
+    );
+
+    assert_eq!(stream.chunk().await.unwrap().unwrap(), "segment-0");
+    assert!(stream.chunk().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn adaptive_falls_back_to_lower_variant() {
+    let url = serve(vec![
+        ("/master.m3u8", vec![MASTER.to_string()]),
+        ("/broken/1080p.m3u8", vec![playlist(0..2, true)]),
+        ("/720p.m3u8", vec![playlist(0..2, true)]),
+    ])
+    .await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        // Without retries, the failing segments fall back right away
+        client: Some(reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build()),
+        stream_url: format!("{url}/master.m3u8"),
+        adaptive: true,
+        segment_retries: Some(0),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(
+        stream.media_url().await.unwrap(),
+        format!("{url}/broken/1080p.m3u8")
+    );
+
+    // Segments of the highest variant fail, the same segments come from the next lower one
+    assert_eq!(stream.chunk().await.unwrap().unwrap(), "segment-0");
+    assert_eq!(
+        stream.media_url().await.unwrap(),
+        format!("{url}/720p.m3u8")
+    );
+    assert_eq!(stream.chunk().await.unwrap().unwrap(), "segment-1");
+    assert!(stream.chunk().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn init_segments_and_byte_ranges() {
+    let url = serve(vec![("/live.m3u8", vec![RANGES.to_string()])]).await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        stream_url: format!("{url}/live.m3u8"),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let mut chunks = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        chunks.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+
+    assert_eq!(chunks, ["init.mp4aaaa", "bbbb", "init.mp4cccc"]);
+}
+
+#[tokio::test]
+async fn prefetch_segments_in_order() {
+    let url = serve(vec![("/live.m3u8", vec![playlist_in("slow/", 0..6, true)])]).await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        stream_url: format!("{url}/live.m3u8"),
+        prefetch: Some(6),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let started = Instant::now();
+    let mut segments = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        segments.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+
+    assert_eq!(
+        segments,
+        (0..6).map(|x| format!("segment-{x}")).collect::<Vec<_>>()
+    );
+    // One after another the segments would take 1.8 seconds
+    assert!(started.elapsed() < Duration::from_millis(1200));
+}
+
+#[tokio::test]
+async fn retry_failed_segments() {
+    let url = serve(vec![(
+        "/live.m3u8",
+        vec![playlist_in("flaky/", 0..2, true)],
+    )])
+    .await;
+
+    let stream = LiveStream::new(LiveStreamOptions {
+        client: Some(reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build()),
+        stream_url: format!("{url}/live.m3u8"),
+        segment_retries: Some(1),
+        ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(stream.chunk().await.unwrap().unwrap(), "segment-0");
+    assert_eq!(stream.chunk().await.unwrap().unwrap(), "segment-1");
+    assert!(stream.chunk().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn record_segments_to_directory() {
+    let url = serve(vec![("/live.m3u8", vec![RANGES.to_string()])]).await;
+    let dir = std::env::temp_dir().join(format!("rusty_ytdl_segments_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let record = || async {
+        LiveStream::new(LiveStreamOptions {
+            stream_url: format!("{url}/live.m3u8"),
+            ..Default::default()
+        })
+        .unwrap()
+        .download_segments(&dir)
+        .await
+        .unwrap()
+    };
+
+    record().await;
+
+    let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("init-d0000000000s0000000000.ts"), "init.mp4");
+    assert_eq!(read("d0000000000s0000000000.ts"), "aaaa");
+    assert_eq!(read("d0000000000s0000000001.ts"), "bbbb");
+    assert_eq!(read("d0000000001s0000000002.ts"), "cccc");
+
+    let playlist = read("index.m3u8");
+    assert!(playlist.contains(
+        "#EXT-X-DISCONTINUITY\n#EXT-X-MAP:URI=\"init-d0000000000s0000000000.ts\"\n#EXTINF:1.000,\nd0000000001s0000000002.ts\n"
+    ));
+    assert!(playlist.ends_with("#EXT-X-ENDLIST\n"));
+
+    // Resume, segments on disk are not downloaded again
+    std::fs::write(dir.join("d0000000000s0000000000.ts"), "kept").unwrap();
+    std::fs::remove_file(dir.join("d0000000000s0000000001.ts")).unwrap();
+    record().await;
+
+    assert_eq!(read("d0000000000s0000000000.ts"), "kept");
+    assert_eq!(read("d0000000000s0000000001.ts"), "bbbb");
+    assert_eq!(read("index.m3u8"), playlist);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn report_and_recover_gaps() {
+    for recover_gaps in [false, true] {
+        let url = serve(vec![(
+            "/live.m3u8",
+            vec![playlist(0..2, false), playlist(5..7, true)],
+        )])
+        .await;
+
+        let stream = LiveStream::new(LiveStreamOptions {
+            stream_url: format!("{url}/live.m3u8"),
+            recover_gaps,
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut segments = vec![];
+        while let Some(chunk) = stream.chunk().await.unwrap() {
+            segments.push(String::from_utf8(chunk.to_vec()).unwrap());
+        }
+
+        let expected: Vec<u64> = if recover_gaps {
+            (0..7).collect()
+        } else {
+            vec![0, 1, 5, 6]
+        };
+        assert_eq!(
+            segments,
+            expected
+                .iter()
+                .map(|x| format!("segment-{x}"))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            stream.gaps().await,
+            [LiveGap {
+                start: (0, 2),
+                end: (0, 4),
+                recovered: recover_gaps,
+            }]
+        );
+    }
+}
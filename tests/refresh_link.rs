@@ -0,0 +1,94 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use rusty_ytdl::stream::{LinkRefresher, NonLiveStream, NonLiveStreamOptions, Stream};
+use rusty_ytdl::VideoError;
+
+mod common;
+
+use common::Response;
+
+const BODY: &[u8] = b"0123456789abcdef";
+
+struct FreshLink(String);
+
+#[async_trait::async_trait]
+impl LinkRefresher for FreshLink {
+    async fn refresh(&self) -> Result<String, VideoError> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Serve `BODY` ranges on `/fresh`, everything else is `403 Forbidden`
+async fn serve() -> String {
+    common::serve(|request| {
+        if !request.path().starts_with("/fresh") {
+            return Response::new("403 Forbidden", "");
+        }
+
+        let range = request
+            .header("range")
+            .and_then(|x| x.strip_prefix("bytes="))
+            .unwrap_or("0-");
+        let (start, end) = range.split_once('-').unwrap();
+        let start: usize = start.parse().unwrap();
+        let end: usize = end
+            .parse::<usize>()
+            .map_or(BODY.len() - 1, |end| end.min(BODY.len() - 1));
+
+        Response::new("206 Partial Content", &BODY[start..=end])
+    })
+    .await
+}
+
+#[tokio::test]
+async fn refresh_link_on_forbidden() {
+    let url = serve().await;
+
+    let stream = NonLiveStream::new(NonLiveStreamOptions {
+        client: Some(reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build()),
+        link: format!("{url}/expired"),
+        content_length: BODY.len() as u64,
+        dl_chunk_size: 5,
+        start: 0,
+        end: 5,
+        refresher: Some(Arc::new(FreshLink(format!("{url}/fresh")))),
+        #[cfg(feature = "ffmpeg")]
+        ffmpeg_args: None,
+    })
+    .unwrap();
+
+    let mut downloaded = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        downloaded.extend_from_slice(&chunk);
+    }
+
+    assert_eq!(downloaded, BODY);
+}
+
+#[tokio::test]
+async fn refresh_link_with_default_client() {
+    let url = serve().await;
+
+    // The default client retries with backoff, forbidden links must reach the refresher right away
+    let stream = NonLiveStream::new(NonLiveStreamOptions {
+        link: format!("{url}/expired"),
+        content_length: BODY.len() as u64,
+        dl_chunk_size: 5,
+        end: 5,
+        refresher: Some(Arc::new(FreshLink(format!("{url}/fresh")))),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let instant = Instant::now();
+    let mut downloaded = vec![];
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        downloaded.extend_from_slice(&chunk);
+    }
+
+    assert_eq!(downloaded, BODY);
+    assert!(instant.elapsed() < Duration::from_secs(1));
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::bind_instead_of_map)]
+
 #[tokio::test]
 async fn search_playlist() {
     use rusty_ytdl::search::{Playlist, PlaylistSearchOptions};